@@ -5,6 +5,7 @@ use shared::{
     casting::DespawnTime,
     event::{client::Chat, server::SendChat, NetEntId, ERFE},
     netlib::{
+        rpc::{send_request, PendingRequests, RequestTimedOut},
         send_event_to_server, EventToClient, EventToServer, MainServerEndpoint, ServerResources,
     },
     AnyUnit,
@@ -37,7 +38,11 @@ impl Plugin for ChatPlugin {
             )
             .add_systems(
                 Update,
-                (receive_network_chats, on_local_chat_send)
+                (
+                    receive_network_chats,
+                    on_local_chat_send,
+                    on_chat_command_timeout,
+                )
                     .run_if(in_state(GameState::ClientConnected)),
             );
     }
@@ -200,10 +205,29 @@ fn on_local_chat_send(
     //our_id: Query<&NetEntId, With<Player>>,
     sr: Res<ServerResources<EventToClient>>,
     mse: Res<MainServerEndpoint>,
+    mut pending: ResMut<PendingRequests>,
+    time: Res<Time<Real>>,
 ) {
     for e in er.read() {
-        let event = EventToServer::SendChat(SendChat { text: e.0.clone() });
-        send_event_to_server(&sr.handler, mse.0, &event);
+        let chat = SendChat { text: e.0.clone() };
+        // commands get an answer, so we can tell if the server never saw them
+        if chat.text.starts_with('/') {
+            send_request(&sr.handler, mse.0, &mut pending, &time, chat);
+        } else {
+            send_event_to_server(&sr.handler, mse.0, &EventToServer::SendChat(chat));
+        }
+    }
+}
+
+fn on_chat_command_timeout(
+    mut timeouts: EventReader<RequestTimedOut<SendChat>>,
+    mut ew: EventWriter<Chat>,
+) {
+    for _ in timeouts.read() {
+        ew.send(Chat {
+            source: None,
+            text: "The server never answered that command".into(),
+        });
     }
 }
 
@@ -7,6 +7,10 @@ use bevy::{
 use shared::{
     animations::AnimationTimer,
     event::{server::Cast, NetEntId},
+    faction::{Faction, FactionRules, Relation},
+    movement::{apply_input, facing, speed_multiplier, PendingInputs},
+    skills::Skills,
+    status_effects::StatusEffects,
     unit::MovementIntention,
     AnyUnit, Config, GameAction,
 };
@@ -125,7 +129,6 @@ impl Default for LastMovement {
     }
 }
 
-pub const PLAYER_SPEED: f32 = 25.;
 pub(crate) fn player_movement(
     _commands: Commands,
     mut player_query: Query<(
@@ -134,17 +137,27 @@ pub(crate) fn player_movement(
         &mut Jumper,
         &mut Player,
         &mut MovementIntention,
+        &mut PendingInputs,
         // are we casting anything?
         Option<(&AnimationTimer, &Cast)>,
     )>,
+    effects: Query<&StatusEffects>,
     camera_query: Query<&CameraFollow>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     config: Res<Config>,
     mut last_movement: Local<LastMovement>,
     time: Res<Time>,
+    skills: Res<Skills>,
 ) {
-    for (mut transform, _player_ent, mut jumper, _player, mut movement, casting) in
-        player_query.iter_mut()
+    for (
+        mut transform,
+        player_ent,
+        mut jumper,
+        _player,
+        mut movement,
+        mut pending_inputs,
+        casting,
+    ) in player_query.iter_mut()
     {
         let mut move_vector = Vec2::ZERO;
         if config.pressed(&keyboard_input, GameAction::MoveForward) {
@@ -171,23 +184,7 @@ pub(crate) fn player_movement(
             let camera = camera_query.single();
             let rotation = Vec2::from_angle(-camera.yaw_radians);
             // final intended movement
-            let mut movem = move_vector.normalize().rotate(rotation);
-
-            // If you are casting something, you will move slower
-            if let Some((anim_timer, cast)) = casting {
-                let anim_timer = &anim_timer.0;
-                let anim = cast.get_current_animation(anim_timer.elapsed());
-                match anim {
-                    shared::animations::AnimationState::FrontSwing => movem *= 0.75,
-                    shared::animations::AnimationState::WindUp => movem *= 0.25,
-                    shared::animations::AnimationState::WindDown => movem *= 0.5,
-                    shared::animations::AnimationState::Backswing => movem *= 0.75,
-                    shared::animations::AnimationState::Done => {}
-                }
-            }
-
-            transform.translation +=
-                Vec3::new(movem.x, 0.0, movem.y) * PLAYER_SPEED * time.delta_seconds();
+            let movem = move_vector.normalize().rotate(rotation);
 
             last_movement.0 = movem;
 
@@ -196,14 +193,22 @@ pub(crate) fn player_movement(
             move_vector
         };
 
+        // Predict where the server will put us. We also need to tell it when we stop moving.
+        if final_move != Vec2::ZERO || movement.0 != final_move {
+            // If you are casting something or slowed, you will move slower
+            let speed_multiplier = speed_multiplier(&skills, casting, effects.get(player_ent).ok());
+            let input = pending_inputs.push(final_move, time.delta_seconds(), speed_multiplier);
+            transform.translation = apply_input(transform.translation, &input, speed_multiplier);
+        }
+
         // point in the direction you are moving, offset by (animation sections * 1 turn per second)
-        let movem = last_movement.0;
-        transform.rotation = Quat::from_rotation_y(movem.x.atan2(movem.y));
+        transform.rotation = facing(last_movement.0);
 
         // If we are casting, animate our model
         if let Some((anim_timer, cast)) = casting {
             let anim_timer = &anim_timer.0;
-            let anim = cast.get_current_animation(anim_timer.elapsed());
+            let si = skills.get(cast);
+            let anim = si.get_current_animation(anim_timer.elapsed());
             let time_offset = anim_timer.elapsed_secs() * PI * 2.0;
 
             match anim {
@@ -225,7 +230,6 @@ pub(crate) fn player_movement(
                 }
                 shared::animations::AnimationState::Backswing => {
                     // slowly turn back up
-                    let si = cast.get_skill_info();
                     let pct_recovered = (anim_timer.elapsed() - si.get_free_point()).as_secs_f32()
                         / (si.get_total_duration() - si.get_free_point()).as_secs_f32();
                     transform.rotation *= Quat::from_rotation_x(PI);
@@ -270,28 +274,33 @@ pub(crate) fn spawn_targeting(
 pub(crate) fn update_targeting(
     _commands: Commands,
     mut player_query: Query<
-        (&Transform, &mut CurrentTargetingCursor),
+        (&Transform, &Faction, &mut CurrentTargetingCursor),
         (With<Player>, Without<TargetingReticle>),
     >,
     mut targeting_reticle: Query<&mut Transform, With<TargetingReticle>>,
     _camera_query: Query<&CameraFollow>,
     units: Query<
-        (&Transform, &NetEntId),
+        (&Transform, &NetEntId, &Faction),
         (With<AnyUnit>, Without<TargetingReticle>, Without<Player>),
     >,
+    rules: Res<FactionRules>,
     //keyboard_input: Res<Input<KeyCode>>,
     //config: Res<Config>,
     //mut last_movement: Local<LastMovement>,
     //time: Res<Time>,
 ) {
-    let Ok((player_tfm, mut maybe_cusor)) = player_query.get_single_mut() else {
+    let Ok((player_tfm, player_faction, mut maybe_cusor)) = player_query.get_single_mut() else {
         return;
     };
 
     let mut dist = f32::MAX;
     let mut close = None;
     let mut close_tfm = None;
-    for (unit_tfm, unit_net_id) in &units {
+    for (unit_tfm, unit_net_id, unit_faction) in &units {
+        // Nothing we could do to allies would hurt them
+        if rules.relation(*player_faction, *unit_faction) == Relation::Allied {
+            continue;
+        }
         // get nearest
         let cur_dist = unit_tfm
             .translation
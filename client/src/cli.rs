@@ -1,6 +1,7 @@
 use bevy::ecs::system::Resource;
 use clap::ValueEnum;
 use serde::Deserialize;
+use shared::netlib::conditioner::LinkConditions;
 
 #[derive(Deserialize, PartialEq, Eq, Debug, Clone, ValueEnum)]
 pub enum Optimizations {
@@ -10,7 +11,8 @@ pub enum Optimizations {
 
 #[derive(clap::Parser, Resource, Debug)]
 pub struct CliArgs {
-    /// Automatically connect to this p and port (no name resolution, must be an ip.)
+    /// Automatically connect to this ip and port (no name resolution, must be an ip.) Prefix it
+    /// with tcp:// or ws:// to use something other than udp.
     #[arg(short, long, name = "IP")]
     pub autoconnect: Option<String>,
 
@@ -29,6 +31,9 @@ pub struct CliArgs {
     /// Print default config and exit
     #[arg(long)]
     pub print_config: bool,
+
+    #[command(flatten)]
+    pub link_conditions: LinkConditions,
 }
 
 impl CliArgs {
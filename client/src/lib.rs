@@ -0,0 +1,17 @@
+pub mod cameras;
+pub mod cli;
+pub mod menu;
+pub mod network;
+pub mod physics;
+pub mod player;
+pub mod skills;
+pub mod states;
+pub mod worldgen;
+
+use bevy::prelude::*;
+
+pub fn despawn_all_component<T: Component>(items: Query<Entity, With<T>>, mut commands: Commands) {
+    for item in &items {
+        commands.entity(item).despawn_recursive();
+    }
+}
@@ -1,20 +1,10 @@
-pub mod cameras;
-pub mod menu;
-pub mod network;
-pub mod physics;
-pub mod player;
-pub mod skills;
-pub mod states;
-pub mod worldgen;
-
-mod cli;
-
 use bevy::{
     diagnostic::FrameTimeDiagnosticsPlugin,
     prelude::*,
     window::{Cursor, CursorGrabMode},
 };
 use clap::Parser;
+use client::{cameras, cli, menu, network, skills, states, worldgen};
 use shared::Config;
 
 pub const HEIGHT: f32 = 720.0;
@@ -70,6 +60,7 @@ fn main() {
             cameras::CameraPlugin,
             cameras::notifications::NotificationPlugin,
             shared::ConfigPlugin,
+            shared::skills::SkillsPlugin,
             states::StatePlugin,
             menu::MenuPlugin,
             // physics::PhysPlugin,
@@ -77,6 +68,12 @@ fn main() {
             network::NetworkingPlugin,
             worldgen::WorldGenPlugin,
         ))
+        .add_systems(
+            Startup,
+            |args: Res<cli::CliArgs>, mut config: ResMut<Config>| {
+                config.override_link_conditions(&args.link_conditions)
+            },
+        )
         .add_systems(Update, bevy::window::close_on_esc); // Close the window when you press escape
 
     add_inspector(&mut app);
@@ -91,9 +88,3 @@ fn add_inspector(app: &mut App) {
 
 #[cfg(not(feature = "inspector"))]
 fn add_inspector(_: &mut App) {}
-
-pub fn despawn_all_component<T: Component>(items: Query<Entity, With<T>>, mut commands: Commands) {
-    for item in &items {
-        commands.entity(item).despawn_recursive();
-    }
-}
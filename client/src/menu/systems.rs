@@ -1,7 +1,10 @@
 use std::net::SocketAddr;
 
 use bevy::{ecs::query::QuerySingleError, prelude::*};
-use shared::{netlib::NetworkConnectionTarget, Config};
+use shared::{
+    netlib::{NetTransport, NetworkConnectionTarget},
+    Config,
+};
 
 use crate::{cli::CliArgs, player::Player, states::GameState};
 
@@ -19,12 +22,21 @@ pub fn check_autoconnect_cli(
         Some("main") => NetworkConnectionTarget {
             ip: "john2143.com".into(),
             port: 25565,
+            transport: NetTransport::Udp,
         },
-        Some("local") => NetworkConnectionTarget {
-            ip: config.ip.clone(),
-            port: config.port,
-        },
+        Some("local") => config.connection_target(),
         Some(other) => {
+            // Optionally starts with the transport, like tcp://127.0.0.1:25565
+            let (transport, other) = match other.split_once("://") {
+                Some((scheme, rest)) => (
+                    scheme
+                        .parse()
+                        .expect("--autoconnect was given an invalid transport"),
+                    rest,
+                ),
+                None => (NetTransport::Udp, other),
+            };
+
             // Split this into ip and port and then connect
             let addr: SocketAddr = other
                 .parse()
@@ -33,6 +45,7 @@ pub fn check_autoconnect_cli(
             NetworkConnectionTarget {
                 ip: addr.ip().to_string(),
                 port: addr.port(),
+                transport,
             }
         }
     };
@@ -77,15 +90,13 @@ pub fn menu_select(
             commands.insert_resource(NetworkConnectionTarget {
                 ip: "john2143.com".into(),
                 port: 25565,
+                transport: NetTransport::Udp,
             });
-            game_state.set(GameState::ClientSendRequestPacket);
+            game_state.set(GameState::ClientConnecting);
         }
         MenuButton::ConnectLocal => {
-            commands.insert_resource(NetworkConnectionTarget {
-                ip: config.ip.clone(),
-                port: config.port,
-            });
-            game_state.set(GameState::ClientSendRequestPacket);
+            commands.insert_resource(config.connection_target());
+            game_state.set(GameState::ClientConnecting);
         }
         MenuButton::Quit => {
             game_state.set(GameState::Quit);
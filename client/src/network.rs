@@ -1,34 +1,62 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use crate::{
-    cameras::{notifications::Notification, thirdperson::PLAYER_SPEED},
+    cameras::notifications::Notification,
     cli::CliArgs,
-    network::stats::HPIndicator,
-    player::{Player, PlayerName},
+    despawn_all_component,
+    network::{interpolation::SnapshotBuffer, stats::HPIndicator},
+    player::{spawn_player_sprite, Player, PlayerName, PrimaryUnitControl},
     states::GameState,
 };
-use bevy::{prelude::*, time::common_conditions::on_timer};
+use bevy::{app::AppExit, prelude::*, time::common_conditions::on_timer};
 use shared::{
     event::{
-        client::{PlayerDisconnected, SomeoneMoved, SpawnUnit, WorldData},
-        server::{ChangeMovement, ConnectRequest, Heartbeat},
-        NetEntId, ERFE,
-    }, netlib::{
-        send_event_to_server, send_event_to_server_batch, setup_client, EventToClient,
-        EventToServer, MainServerEndpoint, ServerResources,
-    }, unit::AttackIntention, AnyUnit, Config
+        client::{
+            ConnectRejected, HeartbeatResponse, PlayerDisconnected, SomeoneMoved, SpawnUnit,
+            WorldData, WorldSnapshot, YourMovementResult,
+        },
+        server::{ChangeMovement, ConnectRequest, Disconnect, Heartbeat, SnapshotAck},
+        NetEntId, ERFE, PROTOCOL_VERSION,
+    }, faction::FactionRules, movement::PendingInputs, skills::Skills, netlib::{
+        send_event_to_server, setup_client, EventToClient,
+        EventToServer, MainServerEndpoint, NetworkConnectionTarget, ServerResources,
+    }, replication::ReplicaHistory, tick::{tick_secs, ClockSync}, unit::AttackIntention, AnyUnit, Config
 };
 
 use shared::unit::MovementIntention;
 
 pub mod casting;
 mod interactable;
+pub mod interpolation;
+mod netstats;
 pub mod npc;
 pub mod stats;
 
 #[derive(Component)]
 pub struct OtherPlayer;
 
+/// If the server doesn't answer a heartbeat for this many seconds, assume the connection is gone
+const SERVER_TIMEOUT: f64 = 3.0;
+
+/// How long we keep trying to reconnect. The server gives up on us after about this long too.
+const RECONNECT_TIMEOUT: f64 = 30.0;
+
+/// From the [WorldData] we were sent, used to get our unit back if we lose connection
+#[derive(Resource, Debug)]
+struct Session(u64);
+
+/// When we last got a [HeartbeatResponse], in real seconds
+#[derive(Resource, Debug, Default)]
+struct LastHeardFromServer(f64);
+
+/// Where we connected to last, so we can do it again
+#[derive(Resource, Debug)]
+struct LastConnectionTarget(NetworkConnectionTarget);
+
+/// When we started trying to reconnect, in real seconds
+#[derive(Resource, Debug, Default)]
+struct ReconnectingSince(f64);
+
 pub struct NetworkingPlugin;
 impl Plugin for NetworkingPlugin {
     fn build(&self, app: &mut App) {
@@ -38,32 +66,79 @@ impl Plugin for NetworkingPlugin {
                 stats::StatsNetworkPlugin,
                 npc::NPCPlugin,
                 interactable::InteractablePlugin,
+                interpolation::InterpolationPlugin,
+                netstats::NetStatsPlugin,
+                shared::netlib::rpc::RpcPlugin,
             ))
             .add_event::<SpawnUnit>()
+            .init_resource::<ClockSync>()
+            .init_resource::<ReplicaHistory>()
+            .init_resource::<LastHeardFromServer>()
+            .init_resource::<ReconnectingSince>()
+            .init_resource::<FactionRules>()
             .add_systems(
                 OnEnter(GameState::ClientConnecting),
                 (
                     // Setup the client and immediatly advance the state
+                    |target: Res<NetworkConnectionTarget>, mut commands: Commands| {
+                        commands.insert_resource(LastConnectionTarget(target.clone()));
+                    },
                     setup_client::<EventToClient>,
+                    reset_connection_state,
                     |mut state: ResMut<NextState<GameState>>| {
                         state.set(GameState::ClientSendRequestPacket)
                     },
-                ),
+                )
+                    .chain(),
+            )
+            // Start over with a new socket, keeping our own unit around until the server gives it
+            // back to us
+            .add_systems(
+                OnEnter(GameState::ClientReconnecting),
+                (
+                    shutdown_network,
+                    despawn_remote_units,
+                    reconnect_to_last_server,
+                    setup_client::<EventToClient>,
+                    reset_connection_state,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                OnEnter(GameState::ClientConnected),
+                |mut last_heard: ResMut<LastHeardFromServer>, time: Res<Time<Real>>| {
+                    last_heard.0 = time.elapsed_seconds_f64();
+                },
             )
             // After sending the first packet, resend it every so often to see if the server comes
             // alive
             .add_systems(
                 Update,
-                (shared::event::client::drain_events, receive_world_data).run_if(
-                    in_state(GameState::ClientSendRequestPacket)
-                        .or_else(in_state(GameState::ClientConnected)),
-                ),
+                (
+                    shared::event::client::drain_events,
+                    receive_world_data,
+                    check_skills.after(receive_world_data),
+                    on_connect_rejected,
+                    on_server_disconnect,
+                )
+                    .run_if(
+                        in_state(GameState::ClientSendRequestPacket)
+                            .or_else(in_state(GameState::ClientConnected))
+                            .or_else(in_state(GameState::ClientReconnecting)),
+                    ),
             )
             .add_systems(
                 Update,
                 (send_connect_packet)
                     .run_if(on_timer(Duration::from_millis(1000)))
-                    .run_if(in_state(GameState::ClientSendRequestPacket)),
+                    .run_if(
+                        in_state(GameState::ClientSendRequestPacket)
+                            .or_else(in_state(GameState::ClientReconnecting)),
+                    ),
+            )
+            .add_systems(
+                Update,
+                give_up_reconnecting.run_if(in_state(GameState::ClientReconnecting)),
             )
             // Once we are connected, advance normally
             .add_systems(
@@ -73,13 +148,16 @@ impl Plugin for NetworkingPlugin {
                     on_connect,
                     on_disconnect,
                     on_someone_move,
-                    go_movement_intents,
+                    on_world_snapshot,
+                    on_movement_result,
+                    on_heartbeat_response,
+                    check_server_timeout,
                 )
                     .run_if(in_state(GameState::ClientConnected)),
             )
             .add_systems(
                 Update,
-                (send_movement, send_interp)
+                send_inputs
                     .run_if(on_timer(Duration::from_millis(25)))
                     .run_if(in_state(GameState::ClientConnected)),
             )
@@ -88,8 +166,102 @@ impl Plugin for NetworkingPlugin {
                 send_heartbeat
                     .run_if(on_timer(Duration::from_millis(200)))
                     .run_if(in_state(GameState::ClientConnected)),
-            );
+            )
+            // Back at the menu, forget about the server and everything it told us
+            .add_systems(
+                OnEnter(GameState::MainMenu),
+                (
+                    shutdown_network,
+                    despawn_all_component::<AnyUnit>,
+                    despawn_all_component::<PrimaryUnitControl>,
+                    despawn_all_component::<HPIndicator>,
+                    |mut commands: Commands| commands.remove_resource::<Session>(),
+                )
+                    .before(spawn_player_sprite),
+            )
+            .add_systems(Last, on_app_exit);
+    }
+}
+
+fn shutdown_network(sr: Option<Res<ServerResources<EventToClient>>>) {
+    if let Some(sr) = sr {
+        sr.handler.shutdown();
+    }
+}
+
+/// A new server means a new clock and nothing to build deltas on
+fn reset_connection_state(mut commands: Commands) {
+    commands.insert_resource(ClockSync::default());
+    commands.insert_resource(ReplicaHistory::default());
+}
+
+/// The server sends us everything around us again once we are back
+fn despawn_remote_units(
+    units: Query<Entity, (With<AnyUnit>, Without<Player>)>,
+    mut commands: Commands,
+) {
+    for unit in &units {
+        commands.entity(unit).despawn_recursive();
+    }
+}
+
+fn reconnect_to_last_server(
+    last: Res<LastConnectionTarget>,
+    time: Res<Time<Real>>,
+    mut since: ResMut<ReconnectingSince>,
+    mut commands: Commands,
+) {
+    since.0 = time.elapsed_seconds_f64();
+    commands.insert_resource(last.0.clone());
+}
+
+fn check_server_timeout(
+    last_heard: Res<LastHeardFromServer>,
+    time: Res<Time<Real>>,
+    mut notif: EventWriter<Notification>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if time.elapsed_seconds_f64() - last_heard.0 > SERVER_TIMEOUT {
+        warn!("Lost connection to the server");
+        notif.send(Notification("Lost connection, reconnecting...".into()));
+        game_state.set(GameState::ClientReconnecting);
+    }
+}
+
+fn give_up_reconnecting(
+    since: Res<ReconnectingSince>,
+    time: Res<Time<Real>>,
+    mut notif: EventWriter<Notification>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if time.elapsed_seconds_f64() - since.0 > RECONNECT_TIMEOUT {
+        error!("Could not reconnect to the server");
+        notif.send(Notification("Could not reconnect to the server".into()));
+        game_state.set(GameState::MainMenu);
+    }
+}
+
+/// Tell the server we are leaving, so our unit doesn't stand around until our heartbeats time out
+fn on_app_exit(
+    mut exit: EventReader<AppExit>,
+    state: Res<State<GameState>>,
+    sr: Option<Res<ServerResources<EventToClient>>>,
+    mse: Option<Res<MainServerEndpoint>>,
+) {
+    if exit.read().next().is_none() {
+        return;
+    }
+    let (Some(sr), Some(mse)) = (sr, mse) else {
+        return;
+    };
+
+    if *state.get() == GameState::ClientConnected {
+        let event = EventToServer::Disconnect(Disconnect {
+            reason: "Quit".into(),
+        });
+        send_event_to_server(&sr.handler, mse.0, &event);
     }
+    sr.handler.shutdown();
 }
 
 fn send_connect_packet(
@@ -97,23 +269,85 @@ fn send_connect_packet(
     args: Res<CliArgs>,
     mse: Res<MainServerEndpoint>,
     config: Res<Config>,
+    session: Option<Res<Session>>,
     mut notif: EventWriter<Notification>,
     local_player: Query<&Transform, With<Player>>,
 ) {
     let my_location = *local_player.single();
     let name = args.name_override.clone().or(config.name.clone());
     let event = EventToServer::ConnectRequest(ConnectRequest {
+        protocol_version: PROTOCOL_VERSION,
         name: name.clone(),
         my_location,
+        resume: session.map(|x| x.0),
+        password: config.password.clone(),
     });
     notif.send(Notification(format!(
-        "Connecting server={} name={name:?}",
+        "Connecting server={} name={name:?} protocol={PROTOCOL_VERSION:016x}",
         mse.0.addr(),
     )));
     send_event_to_server(&sr.handler, mse.0, &event);
     info!("Sent connection packet to {}", mse.0);
 }
 
+fn on_connect_rejected(
+    mut rejections: ERFE<ConnectRejected>,
+    mut notif: EventWriter<Notification>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for rejection in rejections.read() {
+        error!(?rejection.event, "Server rejected our connection");
+        notif.send(Notification(format!(
+            "Server rejected connection: {}",
+            rejection.event.reason
+        )));
+        game_state.set(GameState::MainMenu);
+    }
+}
+
+/// Casts would go off at different times for us and the server, so don't even try
+fn check_skills(
+    mut world_data: ERFE<WorldData>,
+    skills: Res<Skills>,
+    sr: Res<ServerResources<EventToClient>>,
+    mut notif: EventWriter<Notification>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for data in world_data.read() {
+        if data.event.skills_checksum == skills.checksum() {
+            continue;
+        }
+        error!(
+            server = data.event.skills_checksum,
+            ours = skills.checksum(),
+            "Our skills don't match the server's"
+        );
+        let event = EventToServer::Disconnect(Disconnect {
+            reason: "Has different skills".into(),
+        });
+        send_event_to_server(&sr.handler, data.endpoint, &event);
+        notif.send(Notification(
+            "Your skills.yaml is different from the server's".into(),
+        ));
+        game_state.set(GameState::MainMenu);
+    }
+}
+
+fn on_server_disconnect(
+    mut disconnects: ERFE<shared::event::client::Disconnect>,
+    mut notif: EventWriter<Notification>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for disconnect in disconnects.read() {
+        warn!(?disconnect.event, "Server disconnected us");
+        notif.send(Notification(format!(
+            "Disconnected: {}",
+            disconnect.event.reason
+        )));
+        game_state.set(GameState::MainMenu);
+    }
+}
+
 fn build_healthbar(
     s: &mut ChildBuilder,
     meshes: &mut ResMut<Assets<Mesh>>,
@@ -141,17 +375,32 @@ fn receive_world_data(
     mut world_data: ERFE<WorldData>,
     mut commands: Commands,
     mut notif: EventWriter<Notification>,
-    mut local_player: Query<(Entity, &mut Transform), With<Player>>,
+    mut local_player: Query<(Entity, &mut Transform, &mut PendingInputs), With<Player>>,
     mut spawn_units: EventWriter<SpawnUnit>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    state: Res<State<GameState>>,
     mut game_state: ResMut<NextState<GameState>>,
+    session: Option<Res<Session>>,
     asset_server: ResMut<AssetServer>,
 ) {
+    // We keep asking until the first one gets here, so the server might answer more than once
+    let mut connected = *state.get() == GameState::ClientConnected;
     for event in world_data.read() {
+        if connected {
+            continue;
+        }
+        connected = true;
         game_state.set(GameState::ClientConnected);
         info!(?event, "Server has returned world data!");
 
+        // If we are reconnecting, we already have a healthbar and everything else from the first
+        // time, even if the server gave us a new unit
+        let reconnecting = session.is_some();
+        let resumed = session.as_ref().is_some_and(|x| x.0 == event.event.session);
+        commands.insert_resource(Session(event.event.session));
+        commands.insert_resource(event.event.faction_rules);
+
         let my_id = event.event.your_unit_id;
         for unit in &event.event.unit_data {
             match &unit.unit {
@@ -159,12 +408,18 @@ fn receive_world_data(
                     // If it's a player, check to see if it is us
                     if unit.ent_id == my_id {
                         // If so, start aligning the client to it
-                        let (p_ent, mut p_tfm) = local_player.single_mut();
+                        let (p_ent, mut p_tfm, mut p_pending) = local_player.single_mut();
                         p_tfm.translation = unit.transform.translation;
+                        // Anything we did in the menu happened before the server knew about us
+                        p_pending.clear();
 
-                        notif.send(Notification(format!(
-                            "Connected to server as {name} {my_id:?}"
-                        )));
+                        if resumed {
+                            notif.send(Notification("Reconnected".into()));
+                        } else {
+                            notif.send(Notification(format!(
+                                "Connected to server as {name} {my_id:?}"
+                            )));
+                        }
 
                         // Add our netentid + name
                         commands
@@ -172,9 +427,13 @@ fn receive_world_data(
                             .insert(my_id)
                             .insert(PlayerName(name.clone()))
                             .insert(unit.health)
-                            .with_children(|s| {
+                            .insert(unit.faction)
+                            .insert(unit.effects.clone());
+                        if !reconnecting {
+                            commands.entity(p_ent).with_children(|s| {
                                 build_healthbar(s, &mut meshes, &mut materials, Vec3::ZERO)
                             });
+                        }
 
                         // if this is us, skip the spawn units call cause we updated a local unit
                         // instead. TODO eventually fix this so when we fully despawn the menu
@@ -192,6 +451,10 @@ fn receive_world_data(
             spawn_units.send(SpawnUnit { data: unit.clone() });
         }
 
+        if reconnecting {
+            continue;
+        }
+
         commands.spawn((
             HPIndicator::HP,
             TextBundle::from_section(
@@ -231,44 +494,68 @@ fn receive_world_data(
     }
 }
 
-fn send_heartbeat(sr: Res<ServerResources<EventToClient>>, mse: Res<MainServerEndpoint>) {
-    let event = EventToServer::Heartbeat(Heartbeat {});
+fn send_heartbeat(
+    sr: Res<ServerResources<EventToClient>>,
+    mse: Res<MainServerEndpoint>,
+    time: Res<Time<Real>>,
+) {
+    let event = EventToServer::Heartbeat(Heartbeat {
+        client_time: time.elapsed_seconds_f64(),
+    });
     send_event_to_server(&sr.handler, mse.0, &event);
 }
 
-fn send_interp(
+fn on_heartbeat_response(
+    mut responses: ERFE<HeartbeatResponse>,
+    mut clock: ResMut<ClockSync>,
+    mut last_heard: ResMut<LastHeardFromServer>,
+    time: Res<Time<Real>>,
+) {
+    for response in responses.read() {
+        last_heard.0 = time.elapsed_seconds_f64();
+        clock.on_heartbeat_response(
+            response.event.client_time,
+            time.elapsed_seconds_f64(),
+            response.event.tick,
+        );
+    }
+}
+
+fn send_inputs(
     sr: Res<ServerResources<EventToClient>>,
     mse: Res<MainServerEndpoint>,
-    our_transform: Query<&MovementIntention, (With<Player>, Changed<MovementIntention>)>,
+    our_inputs: Query<&PendingInputs, With<Player>>,
 ) {
-    if let Ok(intent) = our_transform.get_single() {
+    if let Ok(pending) = our_inputs.get_single() {
+        if pending.is_empty() {
+            return;
+        }
+
         // TODO add interp for `AttackIntent` here
-        let event = EventToServer::ChangeMovement(ChangeMovement::Move2d(intent.0));
+        let event = EventToServer::ChangeMovement(ChangeMovement::Inputs(
+            pending.unacked().copied().collect(),
+        ));
         send_event_to_server(&sr.handler, mse.0, &event);
     }
 }
 
-fn send_movement(
-    sr: Res<ServerResources<EventToClient>>,
-    mse: Res<MainServerEndpoint>,
-    our_transform: Query<
-        (&Transform, Option<&MovementIntention>),
-        (With<Player>, Changed<Transform>),
-    >,
+/// The server has told us where we really are. Start from there and redo everything it hasn't
+/// seen yet.
+fn on_movement_result(
+    mut results: ERFE<YourMovementResult>,
+    mut local_player: Query<(&mut Transform, &mut PendingInputs), With<Player>>,
 ) {
-    if let Ok((transform, some_intent)) = our_transform.get_single() {
-        let mut events = vec![];
-        events.push(EventToServer::ChangeMovement(ChangeMovement::SetTransform(
-            *transform,
-        )));
-
-        if let Some(intent) = some_intent {
-            events.push(EventToServer::ChangeMovement(ChangeMovement::Move2d(
-                intent.0,
-            )));
-        };
+    let Ok((mut tfm, mut pending)) = local_player.get_single_mut() else {
+        return;
+    };
 
-        send_event_to_server_batch(&sr.handler, mse.0, &events);
+    for result in results.read() {
+        let result = &result.event;
+        if let Some(pos) = pending.reconcile(result.transform.translation, result.last_input) {
+            // Height is only used for jumping, which the server does not care about
+            tfm.translation.x = pos.x;
+            tfm.translation.z = pos.z;
+        }
     }
 }
 
@@ -276,7 +563,6 @@ fn on_disconnect(
     mut dc_info: ERFE<PlayerDisconnected>,
     mut notif: EventWriter<Notification>,
     mut commands: Commands,
-    // TODO what if the server is disconnecting us?
     other_players: Query<(Entity, &NetEntId, &PlayerName), With<OtherPlayer>>,
 ) {
     for event in dc_info.read() {
@@ -293,14 +579,25 @@ fn on_disconnect(
 
 fn on_someone_move(
     mut someone_moved: ERFE<SomeoneMoved>,
-    mut other_players: Query<(&NetEntId, &mut Transform, &mut MovementIntention, &mut AttackIntention), With<AnyUnit>>,
+    mut other_players: Query<(&NetEntId, &mut Transform, &mut MovementIntention, &mut AttackIntention, Option<&mut SnapshotBuffer>), With<AnyUnit>>,
     //mut other_players: Query<(&NetEntId, &mut Transform, &mut MovementIntention), (With<AnyUnit>, Without<Player>)>,
+    clock: Res<ClockSync>,
+    time: Res<Time<Real>>,
 ) {
     for movement in someone_moved.read() {
-        for (ply_net, mut ply_tfm, mut ply_intent, mut ply_attack_intent,) in &mut other_players {
+        for (ply_net, mut ply_tfm, mut ply_intent, mut ply_attack_intent, snapshots) in &mut other_players {
             if &movement.event.id == ply_net {
                 match &movement.event.movement {
-                    ChangeMovement::SetTransform(t) => *ply_tfm = *t,
+                    ChangeMovement::SetTransform(t) => match snapshots {
+                        Some(mut snapshots) => {
+                            // Not everything comes in a batch, so guess when it was sent
+                            let sent_at = movement.tick.map(tick_secs).unwrap_or_else(|| {
+                                clock.server_time(time.elapsed_seconds_f64())
+                            });
+                            snapshots.push(*t, sent_at);
+                        }
+                        None => *ply_tfm = *t,
+                    },
                     ChangeMovement::StandStill => {}
                     ChangeMovement::AttackIntent(intent) => {
                         *ply_attack_intent = intent.clone();
@@ -308,22 +605,54 @@ fn on_someone_move(
                     ChangeMovement::Move2d(intent) => {
                         *ply_intent = MovementIntention(*intent);
                     }
+                    // Only ever sent by clients
+                    ChangeMovement::Inputs(_) => {}
                 }
             }
         }
     }
 }
 
-fn go_movement_intents(
-    mut other_players: Query<
-        (&mut Transform, &MovementIntention),
-        (With<AnyUnit>, Without<Player>),
-    >,
-    time: Res<Time>,
+fn on_world_snapshot(
+    mut snapshots: ERFE<WorldSnapshot>,
+    mut history: ResMut<ReplicaHistory>,
+    mut units: Query<(
+        &NetEntId,
+        &mut SnapshotBuffer,
+        &mut MovementIntention,
+        &mut AttackIntention,
+    )>,
+    sr: Res<ServerResources<EventToClient>>,
+    mse: Res<MainServerEndpoint>,
 ) {
-    for (mut ply_tfm, ply_intent) in &mut other_players {
-        ply_tfm.translation +=
-            Vec3::new(ply_intent.0.x, 0.0, ply_intent.0.y) * PLAYER_SPEED * time.delta_seconds();
+    for snapshot in snapshots.read() {
+        let snapshot = &snapshot.event;
+        let Some(changed) = history.receive(snapshot) else {
+            // The server will notice we never acked this and send everything again
+            debug!(?snapshot.baseline, "Missing the baseline for a snapshot");
+            continue;
+        };
+
+        let event = EventToServer::SnapshotAck(SnapshotAck { id: snapshot.id });
+        send_event_to_server(&sr.handler, mse.0, &event);
+
+        let changed: HashMap<_, _> = changed.into_iter().collect();
+        for (net_ent_id, mut buffer, mut intent, mut attack) in &mut units {
+            let Some(state) = changed.get(net_ent_id) else {
+                continue;
+            };
+
+            buffer.push(state.transform(), tick_secs(snapshot.tick));
+            intent.0 = state.intention();
+            match (state.attacking, &*attack) {
+                (Some(true), AttackIntention::None) => {
+                    // We only care that they are attacking, the server keeps the real timer
+                    *attack = AttackIntention::AutoAttack(Timer::default())
+                }
+                (Some(false), AttackIntention::AutoAttack(_)) => *attack = AttackIntention::None,
+                _ => {}
+            }
+        }
     }
 }
 
@@ -1,17 +1,19 @@
-use std::time::Duration;
-
 use bevy::prelude::*;
 use shared::{
     animations::{AnimationTimer, CastNetId, CastPointTimer, DoCast},
-    casting::{CasterNetId, DespawnTime, SharedCastingPlugin, TargetedBullet},
+    casting::{CasterNetId, DespawnTime, SharedCastingPlugin},
     event::{
-        client::{BulletHit, SomeoneCast},
+        client::{BulletHit, SomeoneCast, SomeoneTeleported},
         NetEntId, ERFE,
     },
+    projectile::Projectile,
+    skills::{SkillKind, Skills},
+    tick::{tick_secs, ClockSync},
     AnyUnit, Config,
 };
 
 use crate::{
+    network::interpolation::SnapshotBuffer,
     player::{Player, PlayerName},
     states::GameState,
 };
@@ -25,15 +27,60 @@ impl Plugin for CastingNetworkPlugin {
             .add_event::<DoCast>()
             .add_systems(
                 Update,
-                (on_someone_cast, on_someone_hit, on_us_tp, do_cast_finish)
+                (
+                    on_someone_cast,
+                    on_someone_hit,
+                    on_someone_teleported,
+                    on_us_tp.after(on_someone_teleported),
+                    do_cast_finish,
+                )
                     .run_if(in_state(GameState::ClientConnected)),
             );
     }
 }
 
+/// The server moved us, which is the only way we ever teleport
 #[derive(Event)]
 struct WeTeleported(Vec3);
 
+fn on_someone_teleported(
+    mut teleported: ERFE<SomeoneTeleported>,
+    mut units: Query<(&NetEntId, Option<&mut SnapshotBuffer>, Has<Player>)>,
+    mut ev_w: EventWriter<WeTeleported>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    clock: Res<ClockSync>,
+    time: Res<Time<Real>>,
+) {
+    for tp in teleported.read() {
+        let SomeoneTeleported { id, transform } = tp.event;
+        // TODO also play it where they left from, if they went far enough
+        commands.spawn((
+            TransformBundle::from_transform(Transform::from_translation(transform.translation)),
+            AudioBundle {
+                source: asset_server.load("sounds/teleport.ogg"),
+                settings: PlaybackSettings::DESPAWN.with_spatial(true),
+                ..default()
+            },
+        ));
+
+        for (net_ent_id, snapshots, is_us) in &mut units {
+            if net_ent_id != &id {
+                continue;
+            }
+            if is_us {
+                ev_w.send(WeTeleported(transform.translation));
+            } else if let Some(mut snapshots) = snapshots {
+                let sent_at = tp
+                    .tick
+                    .map(tick_secs)
+                    .unwrap_or_else(|| clock.server_time(time.elapsed_seconds_f64()));
+                snapshots.teleport(transform, sent_at);
+            }
+        }
+    }
+}
+
 fn on_us_tp(
     mut local_player: Query<&mut Transform, With<Player>>,
     mut ev_r: EventReader<WeTeleported>,
@@ -49,12 +96,10 @@ fn on_us_tp(
 fn do_cast_finish(
     mut do_cast: EventReader<DoCast>,
     mut commands: Commands,
-    //mut units: Query<(&NetEntId, &mut Transform, ), With<AnyUnit>>,
-    local_player_ent_id: Query<&NetEntId, With<Player>>,
+    units: Query<(&NetEntId, &Transform), With<AnyUnit>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
-    mut ev_w: EventWriter<WeTeleported>,
+    skills: Res<Skills>,
 ) {
     for DoCast(cast) in do_cast.read() {
         debug!(?cast, "Cast has completed");
@@ -63,28 +108,8 @@ fn do_cast_finish(
         //if
         //}
         match cast.cast {
-            shared::event::server::Cast::Teleport(target) => {
-                // Spawn a sound at both the source and dest
-                // TODO only play both if you go a far enough distance
-                for loc in &[target /* ply_tfm.translation */] {
-                    commands.spawn((
-                        TransformBundle::from_transform(Transform::from_translation(*loc)),
-                        //Transform::from_xyz(0.0, 0.0, 0.0),
-                        AudioBundle {
-                            source: asset_server.load("sounds/teleport.ogg"),
-                            settings: PlaybackSettings::DESPAWN.with_spatial(true),
-                            ..default()
-                        },
-                    ));
-                }
-
-                match local_player_ent_id.single() == &cast.caster_id {
-                    true => {
-                        ev_w.send(WeTeleported(target));
-                    }
-                    false => trace!("Someone else teleported"),
-                }
-            }
+            // Where we end up is up to the server, see on_someone_teleported
+            shared::event::server::Cast::Teleport(_) => {}
             shared::event::server::Cast::Shoot(ref dat) => {
                 let cube = PbrBundle {
                     mesh: meshes.add(Mesh::from(Cuboid {
@@ -98,10 +123,14 @@ fn do_cast_finish(
                 trace!(?cast.cast_id, "Spawning a bullet with id");
                 commands.spawn((
                     cube,
-                    dat.clone(),
+                    Projectile::new(
+                        SkillKind::Shoot,
+                        skills.get_kind(SkillKind::Shoot).get_projectile(),
+                        dat.shot_from,
+                        dat.target,
+                    ),
                     cast.cast_id,
                     CasterNetId(cast.caster_id),
-                    DespawnTime(Timer::new(Duration::from_secs(5), TimerMode::Once)),
                 ));
             }
             shared::event::server::Cast::ShootTargeted(from_loc, ref net_id) => {
@@ -114,13 +143,21 @@ fn do_cast_finish(
                     ..Default::default()
                 };
 
+                let towards = units
+                    .iter()
+                    .find(|(id, _)| *id == net_id)
+                    .map_or(from_loc, |(_, x)| x.translation);
                 commands.spawn((
                     cube,
                     cast.cast_id,
-                    TargetedBullet(from_loc, *net_id),
+                    Projectile::at_unit(
+                        SkillKind::ShootTargeted,
+                        skills.get_kind(SkillKind::ShootTargeted).get_projectile(),
+                        from_loc,
+                        *net_id,
+                        towards,
+                    ),
                     CasterNetId(cast.caster_id),
-                    // TODO hardcoded proj duration
-                    DespawnTime(Timer::new(Duration::from_secs(1), TimerMode::Once)),
                 ));
             }
             ref rest => {
@@ -141,6 +178,7 @@ fn on_someone_cast(
     //TODO dont actually spawn a cube on cast
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    skills: Res<Skills>,
     //mut ev_w: EventWriter<WeTeleported>,
     //asset_server: Res<AssetServer>,
 ) {
@@ -148,6 +186,7 @@ fn on_someone_cast(
         for (casting_ent, net_ent_id, _caster_tfm, is_us) in &other_players {
             if &cast.event.caster_id == net_ent_id {
                 let cast_data = &cast.event.cast;
+                let skill = skills.get(cast_data);
 
                 match cast.event.cast {
                     shared::event::server::Cast::Teleport(targ) => {
@@ -163,10 +202,7 @@ fn on_someone_cast(
 
                         commands.spawn((
                             cube,
-                            DespawnTime(Timer::new(
-                                cast_data.get_skill_info().get_free_point(),
-                                TimerMode::Once,
-                            )),
+                            DespawnTime(Timer::new(skill.get_free_point(), TimerMode::Once)),
                             TpCube(cast.event.cast_id),
                         ));
                     }
@@ -179,14 +215,8 @@ fn on_someone_cast(
                 }
 
                 commands.entity(casting_ent).insert((
-                    AnimationTimer(Timer::new(
-                        cast_data.get_skill_info().get_total_duration(),
-                        TimerMode::Once,
-                    )),
-                    CastPointTimer(Timer::new(
-                        cast_data.get_skill_info().get_cast_point(),
-                        TimerMode::Once,
-                    )),
+                    AnimationTimer(Timer::new(skill.get_total_duration(), TimerMode::Once)),
+                    CastPointTimer(Timer::new(skill.get_cast_point(), TimerMode::Once)),
                     CastNetId(cast.event.cast_id),
                     cast_data.clone(),
                 ));
@@ -199,22 +229,26 @@ fn on_someone_hit(
     mut someone_hit: ERFE<BulletHit>,
     all_plys: Query<(&NetEntId, &Transform, Option<&PlayerName>, Has<Player>), With<AnyUnit>>,
     //mut notifs: EventWriter<Notification>,
-    bullets: Query<(Entity, &NetEntId, &CasterNetId)>,
+    mut bullets: Query<(Entity, &NetEntId, &CasterNetId, &mut Projectile)>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     config: Res<Config>,
 ) {
     for hit in someone_hit.read() {
         let mut bullet_caster_id = None;
-        for (_bullet_ent, bullet_ent_id, attacker_net_id) in &bullets {
+        for (bullet_ent, bullet_ent_id, attacker_net_id, mut projectile) in &mut bullets {
             if bullet_ent_id == &hit.event.bullet {
-                bullet_caster_id = Some(attacker_net_id);
+                bullet_caster_id = Some(attacker_net_id.0);
+                // The server decides what it hits, so it stops here for us too
+                if projectile.hit(hit.event.player) {
+                    commands.entity(bullet_ent).despawn_recursive();
+                }
             }
         }
 
         // if we dont know about the bullet, return
         let bullet_caster_id = match bullet_caster_id {
-            Some(s) => s.0,
+            Some(s) => s,
             // This happens when the bullet hit packet arrives before the "spawn bullet" packet.
             // TODO! maybe add this to a queue of hit events that we poll every frame until we find
             // the matching bullet
@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use shared::{movement::PLAYER_SPEED, tick::ClockSync, unit::MovementIntention, Config};
+
+use crate::states::GameState;
+
+/// If the newest snapshot is older than this when another one arrives, the unit was standing still
+/// and the server stopped telling us about it. Don't spend the whole gap sliding towards the new
+/// position.
+const MAX_SNAPSHOT_GAP: f64 = 0.1;
+
+/// How far past the newest snapshot we keep guessing where a unit is going before we just leave
+/// it where it is.
+const MAX_EXTRAPOLATION: f64 = 0.25;
+
+/// Anything that moves further than this between two snapshots was teleported, so don't draw it
+/// flying across the map.
+const TELEPORT_DISTANCE_SQ: f32 = 20.0 * 20.0;
+
+/// How many snapshots we remember per unit. Only the ones around the render time matter.
+const MAX_SNAPSHOTS: usize = 32;
+
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            interpolate_remote_units.run_if(in_state(GameState::ClientConnected)),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    /// When the server sent this, in server seconds. See [ClockSync]
+    time: f64,
+    transform: Transform,
+}
+
+/// Every transform the server has sent us for a unit we don't control. The unit is drawn
+/// [Config::interp_delay] in the past so there is (almost) always a snapshot on either side of
+/// what we are drawing.
+#[derive(Component, Debug, Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn new(transform: Transform, time: f64) -> Self {
+        let mut buffer = Self::default();
+        buffer.push(transform, time);
+        buffer
+    }
+
+    /// Add a snapshot the server sent at `time`. These can arrive out of order.
+    pub fn push(&mut self, transform: Transform, time: f64) {
+        let newer = self.snapshots.partition_point(|x| x.time <= time);
+        if newer < self.snapshots.len() {
+            // Something sent after this already got here, slot it in between
+            let duplicate = newer > 0 && self.snapshots[newer - 1].time == time;
+            if !duplicate {
+                self.snapshots.insert(newer, Snapshot { time, transform });
+            }
+            return;
+        }
+
+        if let Some(&last) = self.snapshots.back() {
+            if last
+                .transform
+                .translation
+                .distance_squared(transform.translation)
+                > TELEPORT_DISTANCE_SQ
+            {
+                self.snapshots.clear();
+            } else if time - last.time > MAX_SNAPSHOT_GAP {
+                self.snapshots.push_back(Snapshot {
+                    time: time - MAX_SNAPSHOT_GAP,
+                    transform: last.transform,
+                });
+            }
+        }
+
+        if self.snapshots.len() >= MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot { time, transform });
+    }
+
+    /// The unit jumped to `transform` at `time`, so anything from before then is no use for
+    /// drawing where it was going.
+    pub fn teleport(&mut self, transform: Transform, time: f64) {
+        self.snapshots.retain(|x| x.time > time);
+        self.push(transform, time);
+    }
+
+    /// Where the unit should be drawn at `render_time`. `velocity` is used to guess where it went
+    /// once we run out of snapshots.
+    fn sample(&mut self, render_time: f64, velocity: Vec3) -> Option<Transform> {
+        // Drop everything we will never need again, keeping one snapshot before the render time
+        while self.snapshots.len() > 1 && self.snapshots[1].time <= render_time {
+            self.snapshots.pop_front();
+        }
+
+        let from = *self.snapshots.front()?;
+        match self.snapshots.get(1) {
+            Some(to) if render_time >= from.time => {
+                let t = ((render_time - from.time) / (to.time - from.time)) as f32;
+                Some(Transform {
+                    translation: from.transform.translation.lerp(to.transform.translation, t),
+                    rotation: from.transform.rotation.slerp(to.transform.rotation, t),
+                    scale: from.transform.scale,
+                })
+            }
+            // Everything we know about is newer than what we are drawing, wait for time to catch up
+            Some(_) => Some(from.transform),
+            None => {
+                let ahead = (render_time - from.time).clamp(0.0, MAX_EXTRAPOLATION) as f32;
+                let mut transform = from.transform;
+                transform.translation += velocity * ahead;
+                Some(transform)
+            }
+        }
+    }
+}
+
+fn interpolate_remote_units(
+    // Only remote units get a snapshot buffer
+    mut units: Query<(&mut Transform, &mut SnapshotBuffer, &MovementIntention)>,
+    config: Res<Config>,
+    clock: Res<ClockSync>,
+    time: Res<Time<Real>>,
+) {
+    let render_time =
+        clock.server_time(time.elapsed_seconds_f64()) - config.interp_delay().as_secs_f64();
+    for (mut tfm, mut buffer, intent) in &mut units {
+        let velocity = Vec3::new(intent.0.x, 0.0, intent.0.y) * PLAYER_SPEED;
+        if let Some(sampled) = buffer.sample(render_time, velocity) {
+            *tfm = sampled;
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use bevy::{
+    diagnostic::{DiagnosticPath, DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use shared::{
+    netlib::{
+        stats::{NetDiagnosticsPlugin, NET_LOSS, NET_RECEIVED, NET_SENT},
+        EventToClient, ServerResources,
+    },
+    tick::ClockSync,
+};
+
+/// Shows how the connection is doing in the corner of the screen
+pub struct NetStatsPlugin;
+
+impl Plugin for NetStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(NetDiagnosticsPlugin::<EventToClient>::default())
+            .add_systems(Startup, spawn_net_stats_panel)
+            .add_systems(Update, update_net_stats_panel);
+    }
+}
+
+#[derive(Component)]
+struct NetStatsText;
+
+fn spawn_net_stats_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        NetStatsText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/ttf/JetBrainsMono-Regular.ttf"),
+                font_size: 14.0,
+                color: Color::WHITE.with_a(0.7),
+            },
+        )
+        .with_text_justify(JustifyText::Right)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.0),
+            top: Val::Px(10.0),
+            ..default()
+        }),
+    ));
+}
+
+fn update_net_stats_panel(
+    mut text: Query<&mut Text, With<NetStatsText>>,
+    diagnostics: Res<DiagnosticsStore>,
+    clock: Res<ClockSync>,
+    sr: Option<Res<ServerResources<EventToClient>>>,
+) {
+    let Ok(mut text) = text.get_single_mut() else {
+        return;
+    };
+    let value = |path: &DiagnosticPath| diagnostics.get(path).and_then(|x| x.value());
+
+    let mut lines = vec![match value(&FrameTimeDiagnosticsPlugin::FPS) {
+        Some(fps) => format!("{fps:.0} fps"),
+        None => "- fps".to_string(),
+    }];
+    if sr.is_some() {
+        lines.push(match clock.rtt() {
+            Some(rtt) => format!("ping {:.0} ms", rtt * 1000.0),
+            None => "ping - ms".to_string(),
+        });
+        lines.push(format!("loss {:.1}%", value(&NET_LOSS).unwrap_or_default()));
+        lines.push(format!(
+            "up {:.1} kbps",
+            value(&NET_SENT).unwrap_or_default()
+        ));
+        lines.push(format!(
+            "down {:.1} kbps",
+            value(&NET_RECEIVED).unwrap_or_default()
+        ));
+    }
+
+    text.sections[0].value = lines.join("\n");
+}
@@ -1,15 +1,16 @@
 use bevy::prelude::*;
 use shared::{
     event::{
-        client::{SpawnUnit, UnitDie},
+        client::{LeaveInterest, SpawnUnit, UnitDie},
         NetEntId, ERFE,
     },
+    tick::ClockSync,
     unit::{AttackIntention, MovementIntention},
     AnyUnit,
 };
 
 use crate::{
-    network::{build_healthbar, OtherPlayer},
+    network::{build_healthbar, interpolation::SnapshotBuffer, OtherPlayer},
     player::{PlayerName, PrimaryUnitControl},
     states::GameState,
 };
@@ -25,7 +26,8 @@ impl Plugin for NPCPlugin {
             //.add_systems(Update, on_chat_toggle.run_if(shared::GameAction::Chat.just_pressed()))
             .add_systems(
                 Update,
-                (on_npc_spawn, on_unit_die).run_if(in_state(GameState::ClientConnected)),
+                (on_npc_spawn, on_unit_die, on_leave_interest)
+                    .run_if(in_state(GameState::ClientConnected)),
             );
     }
 }
@@ -36,11 +38,14 @@ fn on_npc_spawn(
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    clock: Res<ClockSync>,
+    time: Res<Time<Real>>,
     //parent: Query<Entity, With<ChatContainer>>,
     //players: Query<(&NetEntId, &PlayerName), With<AnyPlayer>>,
     //mut er: EventReader<Chat>,
     //time: Res<Time>,
 ) {
+    let spawned_at = clock.server_time(time.elapsed_seconds_f64());
     for event in pd.read() {
         let ud = &event.data;
         match &ud.unit {
@@ -58,19 +63,23 @@ fn on_npc_spawn(
                         PlayerName(name.clone()),
                         MovementIntention(Vec2::ZERO),
                         AttackIntention::None,
+                        SnapshotBuffer::new(ud.transform, spawned_at),
                         Name::new(format!("Player: {name}")),
                         // their NetEntId is a component
                         ud.ent_id,
+                        ud.faction,
                         ud.health,
+                        ud.effects.clone(),
                         AnyUnit,
                     ))
                     .with_children(|s| build_healthbar(s, &mut meshes, &mut materials, Vec3::ZERO));
             }
             shared::event::UnitType::NPC { npc_type } => {
                 //commands.insert_resource(Animation(asset_server.load(npc_type.animation())));
+                let cube_transform = Transform::from_translation(ud.transform.translation);
                 let cube = SceneBundle {
                     scene: asset_server.load(npc_type.model()),
-                    transform: Transform::from_translation(ud.transform.translation),
+                    transform: cube_transform,
                     ..default()
                 };
 
@@ -78,11 +87,14 @@ fn on_npc_spawn(
                     .spawn((
                         cube,
                         ud.ent_id,
+                        ud.faction,
                         ud.health,
+                        ud.effects.clone(),
                         npc_type.clone(),
                         Name::new(format!("NPC: {:?}", npc_type)),
                         MovementIntention(Vec2::ZERO),
                         AttackIntention::None,
+                        SnapshotBuffer::new(cube_transform, spawned_at),
                         AnyUnit,
                     ))
                     .with_children(|s| {
@@ -116,3 +128,18 @@ fn on_unit_die(
         }
     }
 }
+
+fn on_leave_interest(
+    mut er: ERFE<LeaveInterest>,
+    units: Query<(Entity, &NetEntId), (With<AnyUnit>, Without<PrimaryUnitControl>)>,
+    mut commands: Commands,
+) {
+    for e in er.read() {
+        for (unit_ent, &unit_ent_id) in &units {
+            if e.event.id == unit_ent_id {
+                // The server spawns it again if it comes back into range
+                commands.entity(unit_ent).despawn_recursive();
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use bevy::prelude::*;
 use shared::{
-    event::{client::SomeoneUpdateComponent, NetEntId, ERFE},
+    event::{client::SomeoneUpdateComponent, spells::UpdateSharedComponent, NetEntId, ERFE},
     stats::Health,
+    status_effects::{EffectKind, StatusEffects},
     AnyUnit,
 };
 
@@ -17,7 +18,12 @@ impl Plugin for StatsNetworkPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             Update,
-            (on_someone_update_stats, on_hp_change, update_hp_bar)
+            (
+                on_someone_update_stats,
+                on_hp_change,
+                update_hp_bar,
+                update_effect_icons,
+            )
                 .run_if(in_state(GameState::ClientConnected)),
         );
     }
@@ -25,14 +31,18 @@ impl Plugin for StatsNetworkPlugin {
 
 fn on_someone_update_stats(
     mut stat_update: ERFE<SomeoneUpdateComponent>,
-    mut players: Query<(&NetEntId, &mut Health), With<AnyUnit>>,
+    mut players: Query<(Entity, &NetEntId, &mut Health), With<AnyUnit>>,
+    mut commands: Commands,
 ) {
     for update in stat_update.read() {
-        for (ply_ent, mut ply_hp) in &mut players {
+        for (ent, ply_ent, mut ply_hp) in &mut players {
             if ply_ent == &update.event.id {
-                match update.event.update {
-                    shared::event::spells::UpdateSharedComponent::Health(hp) => {
-                        *ply_hp = hp;
+                match &update.event.update {
+                    UpdateSharedComponent::Health(hp) => {
+                        *ply_hp = *hp;
+                    }
+                    UpdateSharedComponent::StatusEffects(effects) => {
+                        commands.entity(ent).insert(effects.clone());
                     }
                 }
             }
@@ -121,3 +131,62 @@ fn update_hp_bar(
         }
     }
 }
+
+/// How big the squares above a unit for each of its effects are
+const ICON_SIZE: f32 = 0.15;
+
+/// One of the squares above a unit, one for each effect it has
+#[derive(Component)]
+pub struct EffectIcon;
+
+fn icon_color(kind: EffectKind) -> Color {
+    match kind {
+        EffectKind::DamageUp => Color::rgb(0.9, 0.1, 0.1),
+        EffectKind::SpeedUp => Color::rgb(0.1, 0.8, 0.9),
+        EffectKind::Haste => Color::rgb(0.9, 0.8, 0.1),
+        EffectKind::DamageOverTime => Color::rgb(0.5, 0.1, 0.6),
+        EffectKind::HealOverTime => Color::rgb(0.1, 0.9, 0.2),
+        EffectKind::Slow => Color::rgb(0.2, 0.3, 0.9),
+        EffectKind::Stun => Color::rgb(0.9, 0.9, 0.9),
+    }
+}
+
+fn update_effect_icons(
+    units: Query<(Entity, &StatusEffects, Option<&Children>), Changed<StatusEffects>>,
+    icons: Query<(), With<EffectIcon>>,
+    hp_bars: Query<(&Transform, &HPBar)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    for (ent, effects, children) in &units {
+        for &child in children.iter().flat_map(|x| x.iter()) {
+            if icons.contains(child) {
+                commands.entity(child).despawn_recursive();
+            }
+        }
+
+        // In a row just above their hp bar
+        let Some((hp_bar_tfm, _)) = hp_bars.iter().find(|(_, HPBar(owner))| *owner == ent) else {
+            continue;
+        };
+        let count = effects.iter().count();
+        commands.entity(ent).with_children(|s| {
+            for (i, effect) in effects.iter().enumerate() {
+                let x = (i as f32 - (count - 1) as f32 / 2.0) * ICON_SIZE * 1.5;
+                let offset = Vec3::new(x, ICON_SIZE * 1.5, 0.0);
+                s.spawn((
+                    PbrBundle {
+                        mesh: meshes.add(Mesh::from(Cuboid {
+                            half_size: Vec3::splat(ICON_SIZE / 2.0),
+                        })),
+                        material: materials.add(icon_color(effect.kind)),
+                        transform: Transform::from_translation(hp_bar_tfm.translation + offset),
+                        ..default()
+                    },
+                    EffectIcon,
+                ));
+            }
+        });
+    }
+}
@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 //use bevy_xpbd_3d::prelude::{Collider, RigidBody};
-use shared::{unit::{AttackIntention, MovementIntention}, AnyUnit};
+use shared::{faction::Faction, movement::PendingInputs, unit::{AttackIntention, MovementIntention}, AnyUnit};
 
 use crate::{skills::CurrentTargetingCursor, worldgen::ChunkPos};
 
@@ -54,6 +54,7 @@ pub fn spawn_player_sprite(
         //Collider::cuboid(1., 1., 1.),
         Name::new("Player"),
         MovementIntention(Vec2::ZERO),
+        PendingInputs::default(),
         AttackIntention::None,
         Player::default(),
         crate::cameras::FaceCamera,
@@ -61,6 +62,8 @@ pub fn spawn_player_sprite(
             timer: Timer::from_seconds(1.05, TimerMode::Once),
         },
         AnyUnit,
+        // Until the server tells us otherwise
+        Faction::Players,
         PrimaryUnitControl,
         SpatialListener::new(1.0),
         CurrentTargetingCursor(None),
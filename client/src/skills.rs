@@ -11,11 +11,13 @@ use shared::event::ERFE;
 use shared::event::client::YourCastResult;
 use shared::event::spells::ShootingData;
 use shared::event::NetEntId;
+use shared::movement::clamp_to_world;
+use shared::netlib::rpc::{send_request, PendingRequests, RequestId, RequestTimedOut};
 use shared::netlib::EventToClient;
-use shared::netlib::EventToServer;
+use shared::skills::{SkillKind, Skills};
 use shared::{
     event::server::Cast,
-    netlib::{send_event_to_server, MainServerEndpoint, ServerResources},
+    netlib::{MainServerEndpoint, ServerResources},
     Config,
 };
 
@@ -68,6 +70,10 @@ pub type GameTime = f64;
 #[derive(Event, Debug)]
 struct StartLocalAnimation(Cast);
 
+/// The request we sent for the cast we are doing, so answers about older casts are ignored
+#[derive(Component, Debug)]
+struct CastRequest(RequestId);
+
 fn cast_skill_click(
     //keyboard_input: Res<ButtonInput<KeyCode>>,
     //config: Res<Config>,
@@ -86,6 +92,7 @@ fn cast_skill_2(
     config: Res<Config>,
     player: Query<(&Transform, &CurrentTargetingCursor), With<Player>>,
     aim_dir: Query<&ClientAimDirection>,
+    skills: Res<Skills>,
     mut ev_sa: EventWriter<StartLocalAnimation>,
 ) {
     let (transform, target_ent) = player.single();
@@ -104,9 +111,10 @@ fn cast_skill_2(
                 x: aim_dir.cos(),
                 y: 0.0,
                 z: -aim_dir.sin(),
-            } * 30.0;
+            } * skills.get_kind(SkillKind::Teleport).range;
 
-        let event = Cast::Teleport(target);
+        // The server won't let us out of the world
+        let event = Cast::Teleport(clamp_to_world(target));
         ev_sa.send(StartLocalAnimation(event));
     } else {
         let event = Cast::Aoe(transform.translation);
@@ -151,18 +159,22 @@ fn start_local_skill_cast_animation(
     mut commands: Commands,
     sr: Res<ServerResources<EventToClient>>,
     mse: Res<MainServerEndpoint>,
+    mut pending: ResMut<PendingRequests>,
+    time: Res<Time<Real>>,
+    skills: Res<Skills>,
 ) {
     for StartLocalAnimation(cast) in ev_sa.read() {
         let (player_ent, existing_cast) = player.single();
         trace!(?cast, ?existing_cast, "Attempting to cast");
-        let skill_data = cast.get_skill_info();
+        let skill_data = skills.get(cast);
 
         let mut can_cast = true;
 
         // TODO check cooldown
         if let Some((anim_timer, existing_cast_data)) = existing_cast {
-            let current_anim_state =
-                existing_cast_data.get_current_animation(anim_timer.0.elapsed());
+            let current_anim_state = skills
+                .get(existing_cast_data)
+                .get_current_animation(anim_timer.0.elapsed());
 
             trace!(?current_anim_state, ?existing_cast_data);
 
@@ -185,14 +197,14 @@ fn start_local_skill_cast_animation(
             continue;
         }
 
-        let event = EventToServer::Cast(cast.clone());
-        send_event_to_server(&sr.handler, mse.0, &event);
+        let request = send_request(&sr.handler, mse.0, &mut pending, &time, cast.clone());
 
         commands
             .entity(player_ent)
             .remove::<(AnimationTimer, Cast)>()
             .insert((
                 cast.clone(),
+                CastRequest(request),
                 AnimationTimer(Timer::new(skill_data.get_total_duration(), TimerMode::Once)),
                 CastPointTimer(Timer::new(skill_data.get_cast_point(), TimerMode::Once)),
             ));
@@ -201,28 +213,62 @@ fn start_local_skill_cast_animation(
 
 fn maybe_cancel_local_skill_animation(
     mut commands: Commands,
-    player: Query<Entity, With<Player>>,
+    player: Query<(Entity, Option<&CastRequest>), With<Player>>,
+    mut timers: Query<(&mut AnimationTimer, &mut CastPointTimer), With<Player>>,
     mut skill_cast_results: ERFE<YourCastResult>,
+    mut timeouts: EventReader<RequestTimedOut<Cast>>,
     mut notifs: EventWriter<Notification>,
 ) {
+    let Ok((player_ent, cast_request)) = player.get_single() else {
+        return;
+    };
+    let cast_request = cast_request.map(|x| x.0);
+
     for skill_cast_result in skill_cast_results.read() {
         trace!(?skill_cast_result);
-        match skill_cast_result.event {
+        if skill_cast_result.request != cast_request {
+            debug!(?skill_cast_result, "Ignoring the result of an older cast");
+            continue;
+        }
+        match &skill_cast_result.event {
             YourCastResult::Ok(new_cast_id) => {
                 // server says we can keep casting, insert the new id we got
-                commands
-                    .entity(player.single())
-                    .insert(CastNetId(new_cast_id));
+                commands.entity(player_ent).insert(CastNetId(*new_cast_id));
+            }
+            YourCastResult::OffsetBy(offset, new_cast_id) => {
+                // the server started the cast later than we did, so it finishes later too
+                if let Ok((mut animation, mut cast_point)) = timers.get_single_mut() {
+                    let duration = animation.0.duration() + *offset;
+                    animation.0.set_duration(duration);
+                    let duration = cast_point.0.duration() + *offset;
+                    cast_point.0.set_duration(duration);
+                }
+                commands.entity(player_ent).insert(CastNetId(*new_cast_id));
             }
-            YourCastResult::OffsetBy(_, _) => todo!(),
             YourCastResult::No(tl) => {
                 notifs.send(Notification(format!("Skill is on cooldown! {tl:?}")));
 
                 // we got denied, stop casting, refund everything
                 commands
-                    .entity(player.single())
-                    .remove::<(AnimationTimer, Cast, CastPointTimer)>();
+                    .entity(player_ent)
+                    .remove::<(AnimationTimer, Cast, CastPointTimer, CastRequest)>();
+            }
+            YourCastResult::Denied(reason) => {
+                notifs.send(Notification(reason.clone()));
+                commands
+                    .entity(player_ent)
+                    .remove::<(AnimationTimer, Cast, CastPointTimer, CastRequest)>();
             }
         }
     }
+
+    for timeout in timeouts.read() {
+        if Some(timeout.id) != cast_request {
+            continue;
+        }
+        notifs.send(Notification("The server never answered our cast".into()));
+        commands
+            .entity(player_ent)
+            .remove::<(AnimationTimer, Cast, CastPointTimer, CastRequest)>();
+    }
 }
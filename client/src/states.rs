@@ -9,6 +9,8 @@ pub enum GameState {
     ClientConnecting,
     ClientSendRequestPacket,
     ClientConnected,
+    /// We stopped hearing from the server, and are trying to get our unit back
+    ClientReconnecting,
 
     Quit,
 }
@@ -0,0 +1,324 @@
+//! Generates the network protocol from the event types in `shared::event`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, Attribute, Ident, Item, ItemMod, LitInt, Token,
+};
+
+/// `to = Client`
+struct Args {
+    to: Ident,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key: Ident = input.parse()?;
+        if key != "to" {
+            return Err(syn::Error::new(
+                key.span(),
+                "expected `to = Client` or `to = Server`",
+            ));
+        }
+        input.parse::<Token![=]>()?;
+        Ok(Self { to: input.parse()? })
+    }
+}
+
+struct NetEvent {
+    ident: Ident,
+    id: u32,
+    /// Carries another event inside, see [net_events]
+    wrapper: bool,
+}
+
+/// Take the `#[net_event(id = N)]` off an item, returning `N` and whether it is a wrapper if it
+/// had one.
+fn take_net_event(attrs: &mut Vec<Attribute>) -> syn::Result<Option<(u32, bool)>> {
+    let Some(i) = attrs.iter().position(|x| x.path().is_ident("net_event")) else {
+        return Ok(None);
+    };
+    let attr = attrs.remove(i);
+    if let Some(other) = attrs.iter().find(|x| x.path().is_ident("net_event")) {
+        return Err(syn::Error::new_spanned(
+            other,
+            "only one `#[net_event]` per event",
+        ));
+    }
+
+    let mut id = None;
+    let mut wrapper = false;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("id") {
+            id = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            return Ok(());
+        }
+        if meta.path.is_ident("wrapper") {
+            wrapper = true;
+            return Ok(());
+        }
+        Err(meta.error("expected `id = N` or `wrapper`"))
+    })?;
+    match id {
+        Some(id) => Ok(Some((id, wrapper))),
+        None => Err(syn::Error::new_spanned(
+            attr,
+            "expected `#[net_event(id = N)]`",
+        )),
+    }
+}
+
+/// Every struct and enum in the module marked with `#[net_event(id = N)]`, in the order of their
+/// ids.
+fn find_events(module: &mut ItemMod) -> syn::Result<Vec<NetEvent>> {
+    let Some((_, items)) = &mut module.content else {
+        return Err(syn::Error::new_spanned(
+            &module.ident,
+            "`#[net_events]` needs to see every event, so the module has to be inline",
+        ));
+    };
+
+    let mut events: Vec<NetEvent> = vec![];
+    for item in items {
+        let (attrs, ident, generics) = match item {
+            Item::Struct(x) => (&mut x.attrs, &x.ident, &x.generics),
+            Item::Enum(x) => (&mut x.attrs, &x.ident, &x.generics),
+            // Anything else with a `#[net_event]` fails to compile, since nothing takes it off
+            _ => continue,
+        };
+        let Some((id, wrapper)) = take_net_event(attrs)? else {
+            continue;
+        };
+
+        if !generics.params.is_empty() {
+            return Err(syn::Error::new_spanned(generics, "events can't be generic"));
+        }
+        if let Some(other) = events.iter().find(|x| x.id == id) {
+            return Err(syn::Error::new_spanned(
+                ident,
+                format!("id {id} is already used by {}", other.ident),
+            ));
+        }
+        events.push(NetEvent {
+            ident: ident.clone(),
+            id,
+            wrapper,
+        });
+    }
+
+    events.sort_by_key(|x| x.id);
+    Ok(events)
+}
+
+fn generate(typename: &Ident, events: &[NetEvent]) -> TokenStream2 {
+    let idents: Vec<_> = events.iter().map(|x| &x.ident).collect();
+    let names: Vec<_> = events.iter().map(|x| x.ident.to_string()).collect();
+    let ids: Vec<_> = events.iter().map(|x| x.id).collect();
+    let ids_u64: Vec<_> = events.iter().map(|x| x.id as u64).collect();
+    let wrapper_ids: Vec<_> = events.iter().filter(|x| x.wrapper).map(|x| x.id).collect();
+    let typename_str = typename.to_string();
+    let expecting = format!("an {typename}");
+
+    quote!(
+        #[derive(Debug, Clone)]
+        #[non_exhaustive]
+        pub enum #typename {
+            #( #idents ( #idents ) ),*
+        }
+
+        impl #typename {
+            /// Which event this is, for network stats
+            pub fn name(&self) -> &'static str {
+                match self {
+                    #( #typename :: #idents (_) => #names ),*
+                }
+            }
+
+            /// What this is sent as, from its `#[net_event]`
+            pub fn id(&self) -> u32 {
+                match self {
+                    #( #typename :: #idents (_) => #ids ),*
+                }
+            }
+
+            /// Decodes the event inside a `#[net_event(wrapper)]`, which can't be another wrapper.
+            /// Otherwise nesting them deep enough would overflow the stack of whoever decodes it.
+            pub fn deserialize_wrapped<'de, D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Box<Self>, D::Error> {
+                Self::deserialize_event(deserializer, true).map(Box::new)
+            }
+
+            fn deserialize_event<'de, D: ::serde::Deserializer<'de>>(deserializer: D, wrapped: bool) -> Result<Self, D::Error> {
+                use ::serde::de::{Error, Unexpected};
+
+                const VARIANTS: &[&str] = &[#( #names ),*];
+
+                /// The id in binary formats, the name in text ones
+                struct Variant(u32);
+
+                struct VariantVisitor;
+
+                impl<'de> ::serde::de::Visitor<'de> for VariantVisitor {
+                    type Value = Variant;
+
+                    fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                        f.write_str("an event id or name")
+                    }
+
+                    fn visit_u64<E: Error>(self, v: u64) -> Result<Variant, E> {
+                        match v {
+                            #( #ids_u64 => Ok(Variant(#ids)), )*
+                            _ => Err(E::invalid_value(Unexpected::Unsigned(v), &self)),
+                        }
+                    }
+
+                    fn visit_str<E: Error>(self, v: &str) -> Result<Variant, E> {
+                        match v {
+                            #( #names => Ok(Variant(#ids)), )*
+                            _ => Err(E::unknown_variant(v, VARIANTS)),
+                        }
+                    }
+                }
+
+                impl<'de> ::serde::Deserialize<'de> for Variant {
+                    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                        deserializer.deserialize_identifier(VariantVisitor)
+                    }
+                }
+
+                struct EventVisitor {
+                    /// Whether this is inside a wrapper already
+                    wrapped: bool,
+                }
+
+                impl<'de> ::serde::de::Visitor<'de> for EventVisitor {
+                    type Value = #typename;
+
+                    fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                        f.write_str(#expecting)
+                    }
+
+                    fn visit_enum<A: ::serde::de::EnumAccess<'de>>(self, data: A) -> Result<#typename, A::Error> {
+                        use ::serde::de::VariantAccess;
+
+                        let (Variant(id), variant) = data.variant()?;
+                        if self.wrapped && [#( #wrapper_ids ),*].contains(&id) {
+                            return Err(A::Error::custom(format!("event id {id} can't be wrapped")));
+                        }
+                        match id {
+                            #( #ids => variant.newtype_variant().map(#typename :: #idents), )*
+                            _ => Err(A::Error::custom(format!("unknown event id {id}"))),
+                        }
+                    }
+                }
+
+                deserializer.deserialize_enum(#typename_str, VARIANTS, EventVisitor { wrapped })
+            }
+        }
+
+        #(
+            impl From< #idents > for #typename {
+                fn from(x: #idents) -> Self {
+                    #typename :: #idents (x)
+                }
+            }
+        )*
+
+        /// The id goes where serde would put the variant index, so it is the only thing on the
+        /// wire that says which event this is.
+        impl ::serde::Serialize for #typename {
+            fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                match self {
+                    #(
+                        #typename :: #idents (x) => {
+                            serializer.serialize_newtype_variant(#typename_str, #ids, #names, x)
+                        }
+                    ),*
+                }
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for #typename {
+            fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                #typename :: deserialize_event(deserializer, false)
+            }
+        }
+
+        /// Hand everything the network thread received to the rest of the game as bevy events.
+        ///
+        /// This takes the whole world instead of an `EventWriter` per event, because there are
+        /// more events than a system is allowed to have parameters.
+        pub fn drain_events(world: &mut ::bevy::prelude::World) {
+            let new_events = ::std::mem::take(
+                &mut *world
+                    .resource::<crate::netlib::ServerResources<#typename>>()
+                    .event_list
+                    .lock()
+                    .unwrap(),
+            );
+            for crate::event::EventFromEndpoint { event, endpoint, tick, request } in new_events {
+                match event {
+                    #(
+                        #typename :: #idents (data) => {
+                            world.send_event(crate::event::EventFromEndpoint {
+                                event: data,
+                                endpoint,
+                                tick,
+                                request,
+                            });
+                        }
+                    ),*
+                }
+            }
+        }
+
+        pub fn register_events(app: &mut ::bevy::prelude::App) {
+            #(
+                app.add_event::<crate::event::EventFromEndpoint< #idents >>();
+            )*
+        }
+    )
+}
+
+/// Turns every struct and enum in an inline module marked with `#[net_event(id = N)]` into a
+/// variant of `EventTo{Client,Server}`, and adds `drain_events` and `register_events` for them.
+/// Each event also gets a `From` impl into the enum.
+///
+/// The id is what is sent over the wire, so declarations can be moved around freely and helper
+/// types are never part of the protocol by accident. Ids must never be reused or changed, or
+/// clients built from a different commit will decode the wrong event.
+///
+/// Events that carry another event, like an rpc request, are marked `#[net_event(id = N, wrapper)]`
+/// and decode it with `deserialize_wrapped`, which refuses a wrapper inside a wrapper.
+///
+/// ```ignore
+/// #[net_events(to = Client)]
+/// mod events {
+///     #[net_event(id = 0)]
+///     #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+///     pub struct Chat {
+///         pub text: String,
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn net_events(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as Args);
+    let mut module = parse_macro_input!(input as ItemMod);
+
+    let events = match find_events(&mut module) {
+        Ok(events) => events,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let typename = format_ident!("EventTo{}", args.to);
+    let generated = match syn::parse2::<syn::File>(generate(&typename, &events)) {
+        Ok(file) => file.items,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    if let Some((_, items)) = &mut module.content {
+        items.extend(generated);
+    }
+
+    quote!(#module).into()
+}
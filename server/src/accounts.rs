@@ -0,0 +1,389 @@
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::HashMap,
+    env::current_dir,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use bevy::{app::AppExit, prelude::*};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use shared::{
+    event::{client::UnitDie, NetEntId},
+    stats::Health,
+    Config,
+};
+
+use crate::{on_player_disconnect, PlayerDisconnect, ServerState};
+
+/// Where accounts are kept, next to the config
+const ACCOUNTS_FILE: &str = "accounts.yaml";
+
+/// Longest name anyone can pick
+const MAX_NAME_LEN: usize = 24;
+
+/// Guests are called this and a number. Nobody can register a name like that, so there are always
+/// some left for guests.
+const GUEST_PREFIX: &str = "Player #";
+
+/// How many random guest names we try before giving up on finding a free one
+const GUEST_NAME_TRIES: usize = 100;
+
+/// How many logins can be waiting on their password at once. Clients keep asking until they get
+/// an answer, so anyone over this just gets let in a bit later.
+pub const MAX_PENDING_LOGINS: usize = 8;
+
+pub struct AccountsPlugin;
+
+impl Plugin for AccountsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Accounts::load_from_main_dir())
+            .add_systems(
+                FixedUpdate,
+                (
+                    count_deaths,
+                    save_on_disconnect.before(on_player_disconnect),
+                )
+                    .run_if(in_state(ServerState::Running)),
+            )
+            .add_systems(Last, save_on_exit);
+    }
+}
+
+/// Everything we remember about a player between sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    /// As they typed it when registering
+    pub name: String,
+    password_hash: String,
+    pub position: Option<Vec3>,
+    pub health: Option<Health>,
+    pub deaths: u32,
+}
+
+/// Which account a connected player is logged in to
+#[derive(Component, Debug)]
+pub struct LoggedIn(String);
+
+/// Who someone connecting gets to play as
+#[derive(Debug)]
+pub struct Login {
+    pub name: String,
+    /// None for guests
+    pub account: Option<Account>,
+    /// Gets saved once they are let in
+    new_account: bool,
+}
+
+impl LoggedIn {
+    /// Guests never are, since anyone can play as a guest with any name nobody registered
+    pub fn is_admin(&self, config: &Config) -> bool {
+        config.is_admin(&self.0)
+    }
+}
+
+impl Login {
+    /// Goes on their unit, so their account gets saved when they leave
+    pub fn component(&self) -> Option<LoggedIn> {
+        self.account.as_ref().map(|x| LoggedIn(key(&x.name)))
+    }
+}
+
+/// A [Login] that might still be checking its password. Argon2 is slow on purpose, so that
+/// happens on a thread of its own instead of stalling the tick. Bevy's task pools only get their
+/// own threads with its `multi-threaded` feature, which the server doesn't use.
+pub struct PendingLogin(Checking);
+
+enum Checking {
+    Done(Login),
+    Password(JoinHandle<Result<Login, String>>),
+}
+
+impl PendingLogin {
+    pub fn is_finished(&self) -> bool {
+        match &self.0 {
+            Checking::Done(_) => true,
+            Checking::Password(x) => x.is_finished(),
+        }
+    }
+
+    /// Blocks until it [PendingLogin::is_finished]. Hand the login to [Accounts::finish_login].
+    pub fn join(self) -> Result<Login, String> {
+        match self.0 {
+            Checking::Done(login) => Ok(login),
+            Checking::Password(x) => x.join().unwrap_or_else(|_| {
+                error!("Checking a password panicked");
+                Err("Could not check your password".to_string())
+            }),
+        }
+    }
+}
+
+/// Names are unique no matter how they are capitalized
+fn key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+fn is_guest_name(name: &str) -> bool {
+    key(name).starts_with(&key(GUEST_PREFIX))
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct Accounts {
+    accounts: HashMap<String, Account>,
+    path: Option<PathBuf>,
+}
+
+impl Accounts {
+    pub fn load_from_main_dir() -> Self {
+        let mut path = current_dir().unwrap();
+        path.push(ACCOUNTS_FILE);
+        Self::load(&path)
+    }
+
+    pub fn load(path: &Path) -> Self {
+        let accounts = match OpenOptions::new().read(true).open(path) {
+            Ok(file) => serde_yaml::from_reader(file)
+                .unwrap_or_else(|e| panic!("Failed to load accounts from {path:?}: {e:?}")),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => panic!("Failed to open accounts file {e:?}"),
+        };
+        info!(?path, count = accounts.len(), "Loaded accounts");
+
+        Self {
+            accounts,
+            path: Some(path.to_owned()),
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let mut options = OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+        // Password hashes are still worth guessing at, so only we get to read them
+        #[cfg(unix)]
+        options.mode(0o600);
+        let saved = options
+            .open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                serde_yaml::to_writer(file, &self.accounts).map_err(|e| e.to_string())
+            });
+        if let Err(e) = saved {
+            error!(?path, ?e, "Could not save accounts");
+        }
+    }
+
+    /// Figure out who someone is playing as. With a password, this logs them in to the account
+    /// with their name, or registers it if nobody has it yet. Without one they play as a guest,
+    /// but can't use a name that is registered. `online` is everyone currently playing.
+    ///
+    /// Nothing is saved until the [PendingLogin] is handed to [Accounts::finish_login].
+    pub fn login<'a>(
+        &self,
+        name: Option<&str>,
+        password: Option<&str>,
+        online: impl Iterator<Item = &'a str>,
+    ) -> Result<PendingLogin, String> {
+        let online: Vec<_> = online.map(key).collect();
+
+        let Some(name) = name.map(str::trim).filter(|x| !x.is_empty()) else {
+            let name = (0..GUEST_NAME_TRIES)
+                .map(|_| format!("{GUEST_PREFIX}{}", rand::thread_rng().gen_range(1..10000)))
+                .find(|x| !online.contains(&key(x)) && !self.accounts.contains_key(&key(x)))
+                .ok_or("There are too many guests playing, try again later")?;
+            return Ok(PendingLogin(Checking::Done(Login {
+                name,
+                account: None,
+                new_account: false,
+            })));
+        };
+
+        if name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("Names can be at most {MAX_NAME_LEN} characters"));
+        }
+        if is_guest_name(name) {
+            return Err(format!(
+                "Names starting with {GUEST_PREFIX:?} are for guests"
+            ));
+        }
+        if online.contains(&key(name)) {
+            return Err(format!("{name} is already playing"));
+        }
+
+        let check = match (self.accounts.get(&key(name)), password) {
+            (Some(_), None) => {
+                return Err(format!(
+                    "{name} is registered, set your password in your config to log in"
+                ))
+            }
+            (Some(account), Some(password)) => {
+                let account = account.clone();
+                let password = password.to_string();
+                std::thread::spawn(move || {
+                    if !verify(&password, &account.password_hash) {
+                        return Err(format!("Wrong password for {}", account.name));
+                    }
+                    info!(name = ?account.name, "Logged in");
+                    Ok(Login {
+                        name: account.name.clone(),
+                        account: Some(account),
+                        new_account: false,
+                    })
+                })
+            }
+            (None, Some(password)) => {
+                let name = name.to_string();
+                let password = password.to_string();
+                std::thread::spawn(move || {
+                    let account = Account {
+                        name: name.clone(),
+                        password_hash: hash(&password)?,
+                        position: None,
+                        health: None,
+                        deaths: 0,
+                    };
+                    Ok(Login {
+                        name,
+                        account: Some(account),
+                        new_account: true,
+                    })
+                })
+            }
+            (None, None) => {
+                return Ok(PendingLogin(Checking::Done(Login {
+                    name: name.to_string(),
+                    account: None,
+                    new_account: false,
+                })))
+            }
+        };
+
+        Ok(PendingLogin(Checking::Password(check)))
+    }
+
+    /// Let in someone whose [PendingLogin] went through. Others could have logged in or registered
+    /// while their password was being checked, so this checks `online` and the accounts again.
+    pub fn finish_login<'a>(
+        &mut self,
+        login: Login,
+        mut online: impl Iterator<Item = &'a str>,
+    ) -> Result<Login, String> {
+        let name = &login.name;
+        if online.any(|x| key(x) == key(name)) {
+            return Err(format!("{name} is already playing"));
+        }
+
+        let registered = self.accounts.contains_key(&key(name));
+        if login.new_account {
+            if is_guest_name(name) {
+                return Err(format!(
+                    "Names starting with {GUEST_PREFIX:?} are for guests"
+                ));
+            }
+            if registered {
+                return Err(format!("{name} was just registered by someone else"));
+            }
+            let account = login.account.clone().expect("New accounts have an account");
+            info!(?name, "Registered a new account");
+            self.accounts.insert(key(name), account);
+            self.save();
+        } else if login.account.is_none() && registered {
+            return Err(format!(
+                "{name} is registered, set your password in your config to log in"
+            ));
+        }
+
+        Ok(login)
+    }
+}
+
+fn hash(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|x| x.to_string())
+        .map_err(|e| {
+            error!(?e, "Could not hash a password");
+            "Could not create your account".to_string()
+        })
+}
+
+fn verify(password: &str, hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        error!("An account has a broken password hash");
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+fn count_deaths(
+    mut deaths: EventReader<UnitDie>,
+    players: Query<(&NetEntId, &LoggedIn)>,
+    mut accounts: ResMut<Accounts>,
+) {
+    for death in deaths.read() {
+        let Some((_, LoggedIn(key))) = players.iter().find(|(id, _)| **id == death.id) else {
+            continue;
+        };
+        if let Some(account) = accounts.accounts.get_mut(key) {
+            account.deaths += 1;
+        }
+    }
+}
+
+/// Remember where they were and how they were doing
+fn update_account(accounts: &mut Accounts, key: &str, tfm: &Transform, health: &Health) {
+    if let Some(account) = accounts.accounts.get_mut(key) {
+        account.position = Some(tfm.translation);
+        account.health = Some(*health);
+    }
+}
+
+fn save_on_disconnect(
+    mut pd: EventReader<PlayerDisconnect>,
+    players: Query<(&NetEntId, &LoggedIn, &Transform, &Health)>,
+    mut accounts: ResMut<Accounts>,
+) {
+    let mut changed = false;
+    for player in pd.read() {
+        for (id, LoggedIn(key), tfm, health) in &players {
+            if *id == player.ent {
+                update_account(&mut accounts, key, tfm, health);
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        accounts.save();
+    }
+}
+
+fn save_on_exit(
+    mut exit: EventReader<AppExit>,
+    players: Query<(&LoggedIn, &Transform, &Health)>,
+    mut accounts: ResMut<Accounts>,
+) {
+    if exit.read().last().is_none() {
+        return;
+    }
+
+    for (LoggedIn(key), tfm, health) in &players {
+        update_account(&mut accounts, key, tfm, health);
+    }
+    accounts.save();
+    info!("Saved accounts");
+}
@@ -1,7 +1,4 @@
-use std::{
-    mem::{discriminant, Discriminant},
-    time::Duration,
-};
+use std::mem::{discriminant, Discriminant};
 
 use bevy::{prelude::*, utils::HashSet};
 use shared::{
@@ -9,34 +6,40 @@ use shared::{
     casting::{CasterNetId, DespawnTime, SharedCastingPlugin},
     event::{
         client::{
-            BulletHit, SomeoneCast, SomeoneUpdateComponent, SpawnInteractable, UnitDie,
-            YourCastResult,
+            BulletHit, SomeoneCast, SomeoneTeleported, SomeoneUpdateComponent, SpawnInteractable,
+            UnitDie, YourCastResult,
         },
         server::Cast,
-        spells::ShootingData,
         NetEntId, ERFE,
     },
+    faction::{Faction, FactionRules, Relation},
     interactable::Interactable,
-    netlib::{send_event_to_server, EventToClient, EventToServer, ServerResources},
+    netlib::{rpc::respond, send_event_to_server, EventToClient, EventToServer, ServerResources},
+    projectile::Projectile,
+    skills::{SkillInfo, SkillKind, Skills},
     stats::Health,
+    status_effects::{ApplyEffect, StatusEffects},
     AnyUnit,
 };
 
-use crate::{EndpointToNetId, PlayerEndpoint, ServerState};
+use crate::{
+    interest::Interest,
+    validation::{landing_spot, validate_cast, Violation},
+    EndpointToNetId, PlayerEndpoint, ServerState,
+};
 
 pub struct CastingPlugin;
 
 impl Plugin for CastingPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(SharedCastingPlugin)
-            .add_event::<BulletHit>()
+            .add_event::<ProjectileHit>()
             .add_event::<UnitDie>()
             .add_event::<DoSpawnInteractable>()
             .add_event::<DoCast>()
             .add_event::<DoDamage>()
-            .insert_resource(HitList::default())
             .add_systems(
-                Update,
+                FixedUpdate,
                 (
                     on_player_try_cast,
                     hit,
@@ -44,9 +47,9 @@ impl Plugin for CastingPlugin {
                     on_die,
                     shared::animations::systems::tick_casts,
                     do_cast,
+                    teleport_caster,
                     spawn_interactable,
                     unit_damaged,
-                    tick_spell_proj,
                 )
                     .run_if(in_state(ServerState::Running)),
             );
@@ -56,105 +59,149 @@ impl Plugin for CastingPlugin {
 #[derive(Component, Debug)]
 pub(crate) struct PlayerCooldown(pub Discriminant<Cast>, pub NetEntId);
 
-#[derive(Component, Debug)]
-pub(crate) struct SpellProj(pub Timer, pub Cast);
-
-#[derive(Component, Debug)]
-pub(crate) struct SpellTarget(pub NetEntId);
+/// How much damage `skill` does when `caster` casts it, with whatever effects they have
+fn damage_of(
+    skill: &SkillInfo,
+    caster: &NetEntId,
+    effects: &Query<(&NetEntId, &StatusEffects)>,
+) -> f64 {
+    let multiplier = effects
+        .iter()
+        .find(|(id, _)| *id == caster)
+        .map_or(1.0, |(_, x)| x.damage_multiplier());
+    skill.damage * multiplier as f64
+}
 
-fn tick_spell_proj(
-    mut projectiles: Query<(Entity, &mut SpellProj, &SpellTarget)>,
-    mut damage_events: EventWriter<DoDamage>,
-    time: Res<Time<Virtual>>,
-    mut commands: Commands,
-) {
-    for (ent, mut sp, target_id) in &mut projectiles {
-        sp.0.tick(time.delta());
-        if sp.0.finished() {
-            damage_events.send(DoDamage(target_id.0, sp.1.get_damage()));
-            commands.entity(ent).despawn_recursive();
-        }
-    }
+/// Give `target` the effects of a skill that hit them
+fn send_effects(applied: &mut EventWriter<ApplyEffect>, skill: &SkillInfo, target: NetEntId) {
+    applied.send_batch(
+        skill
+            .effects
+            .iter()
+            .map(|&effect| ApplyEffect { target, effect }),
+    );
 }
 
 fn do_cast(
     mut do_cast: EventReader<DoCast>,
     mut commands: Commands,
-    all_unit_locations: Query<(&NetEntId, &Transform)>,
-    _time: Res<Time<Virtual>>,
+    all_unit_locations: Query<(&NetEntId, &Transform, &Faction)>,
+    effects: Query<(&NetEntId, &StatusEffects)>,
+    skills: Res<Skills>,
+    rules: Res<FactionRules>,
     mut damage_events: EventWriter<DoDamage>,
+    mut applied: EventWriter<ApplyEffect>,
 ) {
     for DoCast(cast) in do_cast.read() {
         trace!(?cast, "Cast has completed");
-
+        let skill = skills.get(&cast.cast);
+        let damage = damage_of(skill, &cast.caster_id, &effects);
+
+        let caster_faction = all_unit_locations
+            .iter()
+            .find(|(id, ..)| **id == cast.caster_id)
+            .map(|(_, _, &x)| x);
+        // How the caster gets along with a unit of `faction`
+        let relation = |faction: &Faction| match caster_faction {
+            Some(caster) => rules.relation(caster, *faction),
+            // They are gone, so nobody is on their side anymore
+            None => Relation::Hostile,
+        };
+
+        let cooldown_multiplier = effects
+            .iter()
+            .find(|(id, _)| **id == cast.caster_id)
+            .map_or(1.0, |(_, x)| x.cooldown_multiplier());
         commands.spawn((
             PlayerCooldown(discriminant(&cast.cast), cast.caster_id),
             DespawnTime(Timer::new(
-                cast.cast.get_skill_info().cooldown,
+                skill.cooldown.mul_f32(cooldown_multiplier),
                 TimerMode::Once,
             )),
         ));
 
         match cast.cast {
-            Cast::Teleport(_) => {} // TODO
+            // See teleport_caster
+            Cast::Teleport(_) => {}
             Cast::Shoot(ref shot_data) => {
                 commands.spawn((
                     Transform::from_translation(shot_data.shot_from),
-                    shot_data.clone(),
+                    Projectile::new(
+                        SkillKind::Shoot,
+                        skill.get_projectile(),
+                        shot_data.shot_from,
+                        shot_data.target,
+                    ),
                     //bullets have a net ent id + a caster.
                     cast.cast_id,
                     CasterNetId(cast.caster_id),
-                    DespawnTime(Timer::new(Duration::from_secs(5), TimerMode::Once)),
-                    // TODO Add a netentid for referencing this item later
                 ));
             }
             Cast::Aoe(loc) => {
-                for (other_unit_ent_id, other_unit_tfm) in &all_unit_locations {
-                    if other_unit_tfm.translation.distance(loc) < 25.0
+                for (other_unit_ent_id, other_unit_tfm, other_faction) in &all_unit_locations {
+                    if other_unit_tfm.translation.distance(loc) < skill.area_radius
                         && &cast.caster_id != other_unit_ent_id
+                        && relation(other_faction).can_hurt()
                     {
                         //TODO also check angle of attach
-                        damage_events.send(DoDamage(*other_unit_ent_id, cast.cast.get_damage()));
+                        damage_events.send(DoDamage(*other_unit_ent_id, damage));
+                        send_effects(&mut applied, skill, *other_unit_ent_id);
                     }
                 }
             }
             Cast::Melee => {
-                for (unit_ent_id, unit_tfm) in &all_unit_locations {
+                for (unit_ent_id, unit_tfm, _) in &all_unit_locations {
                     // find everything in an aoe around the caster
                     if unit_ent_id == &cast.caster_id {
-                        for (other_unit_ent_id, other_unit_tfm) in &all_unit_locations {
-                            if other_unit_tfm.translation.distance(unit_tfm.translation) < 5.0
+                        for (other_unit_ent_id, other_unit_tfm, other_faction) in
+                            &all_unit_locations
+                        {
+                            if other_unit_tfm.translation.distance(unit_tfm.translation)
+                                < skill.area_radius
                                 && unit_ent_id != other_unit_ent_id
+                                && relation(other_faction).can_hurt()
                             {
                                 //TODO also check angle of attach
-                                damage_events
-                                    .send(DoDamage(*other_unit_ent_id, cast.cast.get_damage()));
+                                damage_events.send(DoDamage(*other_unit_ent_id, damage));
+                                send_effects(&mut applied, skill, *other_unit_ent_id);
                             }
                         }
                     }
                 }
             }
-            Cast::ShootTargeted(_, target_net_ent_id) => {
+            Cast::ShootTargeted(from, target_net_ent_id) => {
+                let towards = all_unit_locations
+                    .iter()
+                    .find(|(id, ..)| **id == target_net_ent_id)
+                    .map_or(from, |(_, tfm, _)| tfm.translation);
                 commands.spawn((
+                    Transform::from_translation(from),
+                    Projectile::at_unit(
+                        SkillKind::ShootTargeted,
+                        skill.get_projectile(),
+                        from,
+                        target_net_ent_id,
+                        towards,
+                    ),
                     //bullets have a net ent id + a caster.
                     cast.cast_id,
                     CasterNetId(cast.caster_id),
-                    // TODO hardcoded proj duration
-                    SpellProj(
-                        Timer::new(Duration::from_secs(1), TimerMode::Once),
-                        cast.cast.clone(),
-                    ),
-                    SpellTarget(target_net_ent_id),
                 ));
             }
             Cast::Buff => {
-                for (unit_ent_id, unit_tfm) in &all_unit_locations {
-                    // find everything in an aoe around the caster
+                for (unit_ent_id, unit_tfm, _) in &all_unit_locations {
+                    // find all our allies in an aoe around the caster, including us
                     if unit_ent_id == &cast.caster_id {
-                        for (other_unit_ent_id, other_unit_tfm) in &all_unit_locations {
-                            if other_unit_tfm.translation.distance(unit_tfm.translation) < 25.0 {
-                                //TODO buff the units here
-                                warn!(?other_unit_ent_id, "Buff was cast on");
+                        for (other_unit_ent_id, other_unit_tfm, other_faction) in
+                            &all_unit_locations
+                        {
+                            if other_unit_tfm.translation.distance(unit_tfm.translation)
+                                < skill.area_radius
+                                && (unit_ent_id == other_unit_ent_id
+                                    || relation(other_faction) == Relation::Allied)
+                            {
+                                trace!(?other_unit_ent_id, "Buff was cast on");
+                                send_effects(&mut applied, skill, *other_unit_ent_id);
                             }
                         }
                     }
@@ -164,29 +211,142 @@ fn do_cast(
     }
 }
 
+/// Clients can't move themselves anymore, so we have to move them when their teleport goes off,
+/// and tell everyone where they landed.
+fn teleport_caster(
+    mut do_cast: EventReader<DoCast>,
+    mut units: Query<(&NetEntId, &mut Transform), With<AnyUnit>>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
+    sr: Res<ServerResources<EventToServer>>,
+) {
+    for DoCast(cast) in do_cast.read() {
+        // Range and world bounds are checked in on_player_try_cast
+        let Cast::Teleport(target) = cast.cast else {
+            continue;
+        };
+
+        // Whoever is standing there now is in the way
+        let others: Vec<Vec3> = units
+            .iter()
+            .filter(|(id, _)| **id != cast.caster_id)
+            .map(|(_, tfm)| tfm.translation)
+            .collect();
+        let Some((_, mut caster_tfm)) = units.iter_mut().find(|(id, _)| **id == cast.caster_id)
+        else {
+            continue;
+        };
+        caster_tfm.translation = landing_spot(caster_tfm.translation, target, &others);
+        debug!(?cast.caster_id, ?target, landed = ?caster_tfm.translation, "Teleported");
+
+        let event = EventToClient::SomeoneTeleported(SomeoneTeleported {
+            id: cast.caster_id,
+            transform: *caster_tfm,
+        });
+        for (c_net_client, interest) in &clients {
+            if interest.sees(&cast.caster_id) {
+                send_event_to_server(&sr.handler, c_net_client.0, &event);
+            }
+        }
+    }
+}
+
 fn on_player_try_cast(
     mut casts: ERFE<shared::event::server::Cast>,
     endpoint_mapping: Res<EndpointToNetId>,
-    clients: Query<(&PlayerEndpoint, &NetEntId)>,
-    casting_units: Query<(Entity, &NetEntId, Option<(&AnimationTimer, &Cast)>), With<AnyUnit>>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
+    casting_units: Query<
+        (
+            Entity,
+            &NetEntId,
+            &Transform,
+            &Faction,
+            Option<&CastPointTimer>,
+        ),
+        With<AnyUnit>,
+    >,
+    effects: Query<(&NetEntId, &StatusEffects)>,
     cooldowns: Query<(&PlayerCooldown, &DespawnTime)>,
     sr: Res<ServerResources<EventToServer>>,
+    skills: Res<Skills>,
+    rules: Res<FactionRules>,
+    mut violations: EventWriter<Violation>,
     mut commands: Commands,
 ) {
+    // The cast components only show up once commands are applied, so this catches a second cast
+    // in the same frame
+    let mut started = HashSet::new();
     'next_cast: for cast in casts.read() {
         if let Some(caster_net_id) = endpoint_mapping.map.get(&cast.endpoint) {
+            let Some((_, _, caster, &caster_faction, cast_point)) = casting_units
+                .iter()
+                .find(|(_, net_ent_id, ..)| *net_ent_id == caster_net_id)
+            else {
+                continue;
+            };
+
+            let find_unit = |id| {
+                casting_units
+                    .iter()
+                    .find(|(_, net_ent_id, ..)| **net_ent_id == id)
+                    .map(|(_, _, x, &faction, _)| {
+                        (x.translation, rules.relation(caster_faction, faction))
+                    })
+            };
+            if let Err(reason) = validate_cast(&skills, &cast.event, caster.translation, find_unit)
+            {
+                respond(&sr.handler, cast, YourCastResult::Denied(reason.clone()));
+                violations.send(Violation {
+                    endpoint: cast.endpoint,
+                    reason,
+                });
+                continue;
+            }
+
+            let stunned = effects
+                .iter()
+                .find(|(id, _)| *id == caster_net_id)
+                .and_then(|(_, x)| x.stunned());
+            if let Some(remaining) = stunned {
+                debug!(?caster_net_id, ?remaining, "denied cast while stunned");
+                let denied = YourCastResult::Denied("You are stunned".to_string());
+                respond(&sr.handler, cast, denied);
+                continue;
+            }
+
+            // The cooldown only starts at the cast point, so until then the cast they are
+            // already doing is what stops them
+            let winding_up = cast_point.filter(|x| !x.0.paused());
+            if let Some(cast_point) = winding_up {
+                debug!(?caster_net_id, "denied cast while winding up another");
+                respond(
+                    &sr.handler,
+                    cast,
+                    YourCastResult::No(cast_point.0.remaining()),
+                );
+                continue;
+            }
+
             // if it's on cd, deny it and don't tell anyone else.
             for (cd, time_left) in &cooldowns {
                 if cd.1 == *caster_net_id && discriminant(&cast.event) == cd.0 {
                     debug!(?cd, "denied cast for cooldown");
-                    let event =
-                        EventToClient::YourCastResult(YourCastResult::No(time_left.0.remaining()));
-                    send_event_to_server(&sr.handler, cast.endpoint, &event);
+                    respond(
+                        &sr.handler,
+                        cast,
+                        YourCastResult::No(time_left.0.remaining()),
+                    );
                     continue 'next_cast;
                 }
             }
 
-            // if we can cast, then send to all endpoints including us.
+            if !started.insert(*caster_net_id) {
+                debug!(?caster_net_id, "denied a second cast in the same frame");
+                let denied = YourCastResult::Denied("You are already casting".to_string());
+                respond(&sr.handler, cast, denied);
+                continue;
+            }
+
+            // if we can cast, then send to everyone who can see us, including us.
             let new_cast_id = NetEntId::random();
             let event = EventToClient::SomeoneCast(SomeoneCast {
                 caster_id: *caster_net_id,
@@ -194,26 +354,22 @@ fn on_player_try_cast(
                 cast: cast.event.clone(),
             });
 
-            for (c_net_client, _c_net_ent) in &clients {
-                send_event_to_server(&sr.handler, c_net_client.0, &event);
+            for (c_net_client, interest) in &clients {
+                if interest.sees(caster_net_id) {
+                    send_event_to_server(&sr.handler, c_net_client.0, &event);
+                }
             }
 
             // tell the client they are ok to continue their animation
-            let event = EventToClient::YourCastResult(YourCastResult::Ok(new_cast_id));
-            send_event_to_server(&sr.handler, cast.endpoint, &event);
+            respond(&sr.handler, cast, YourCastResult::Ok(new_cast_id));
 
-            for (casting_ent, net_ent_id, _current_cast) in &casting_units {
+            for (casting_ent, net_ent_id, ..) in &casting_units {
                 if net_ent_id == caster_net_id {
                     trace!(?net_ent_id, ?cast.event, "Adding the cast to the entity");
+                    let skill = skills.get(&cast.event);
                     commands.entity(casting_ent).insert((
-                        AnimationTimer(Timer::new(
-                            cast.event.get_skill_info().get_total_duration(),
-                            TimerMode::Once,
-                        )),
-                        CastPointTimer(Timer::new(
-                            cast.event.get_skill_info().get_cast_point(),
-                            TimerMode::Once,
-                        )),
+                        AnimationTimer(Timer::new(skill.get_total_duration(), TimerMode::Once)),
+                        CastPointTimer(Timer::new(skill.get_cast_point(), TimerMode::Once)),
                         CastNetId(new_cast_id),
                         cast.event.clone(),
                     ));
@@ -223,23 +379,50 @@ fn on_player_try_cast(
     }
 }
 
+/// A projectile went into a unit, see [check_collision]
+#[derive(Event, Debug)]
+struct ProjectileHit {
+    hit: BulletHit,
+    caster: NetEntId,
+    skill: SkillKind,
+}
+
+/// Clients fly projectiles too, but only we decide what they hit
 fn check_collision(
-    bullets: Query<(&NetEntId, &CasterNetId, &Transform), (With<ShootingData>, Without<AnyUnit>)>,
-    players: Query<(&NetEntId, &Transform), With<AnyUnit>>,
-    mut ev_w: EventWriter<BulletHit>,
+    mut bullets: Query<(Entity, &NetEntId, &CasterNetId, &mut Projectile)>,
+    players: Query<(&NetEntId, &Transform, &Faction), With<AnyUnit>>,
+    rules: Res<FactionRules>,
+    mut ev_w: EventWriter<ProjectileHit>,
+    mut commands: Commands,
 ) {
-    for (b_id, CasterNetId(caster), bullet) in &bullets {
-        for (p_id, player) in &players {
-            if caster == p_id {
-                //you cannot hit yourself
+    for (ent, b_id, CasterNetId(caster), mut bullet) in &mut bullets {
+        let caster_faction = players
+            .iter()
+            .find(|(id, ..)| *id == caster)
+            .map(|(_, _, &x)| x);
+        for (p_id, player, &faction) in &players {
+            if caster == p_id || bullet.has_hit(p_id) {
+                //you cannot hit yourself, or anyone twice
+                continue;
+            }
+            // Bullets go through allies
+            if caster_faction.is_some_and(|x| !rules.relation(x, faction).can_hurt()) {
                 continue;
             }
 
-            if bullet.translation.distance_squared(player.translation) < 5.0 {
-                ev_w.send(BulletHit {
-                    bullet: *b_id,
-                    player: *p_id,
+            if bullet.touches(player.translation) {
+                ev_w.send(ProjectileHit {
+                    hit: BulletHit {
+                        bullet: *b_id,
+                        player: *p_id,
+                    },
+                    caster: *caster,
+                    skill: bullet.skill,
                 });
+                if bullet.hit(*p_id) {
+                    commands.entity(ent).despawn_recursive();
+                    break;
+                }
             }
         }
     }
@@ -248,7 +431,7 @@ fn check_collision(
 fn unit_damaged(
     mut damage_events: EventReader<DoDamage>,
     mut death: EventWriter<UnitDie>,
-    clients: Query<&PlayerEndpoint>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
     mut unit: Query<(&NetEntId, &mut Health), With<AnyUnit>>,
     sr: Res<ServerResources<EventToServer>>,
 ) {
@@ -264,8 +447,10 @@ fn unit_damaged(
                     update: shared::event::spells::UpdateSharedComponent::Health(*ply_hp),
                 });
 
-                for c_net_client in &clients {
-                    send_event_to_server(&sr.handler, c_net_client.0, &hp_event)
+                for (c_net_client, interest) in &clients {
+                    if interest.sees(net_ent_id) {
+                        send_event_to_server(&sr.handler, c_net_client.0, &hp_event)
+                    }
                 }
                 if ply_hp.0 <= 0 {
                     death.send(UnitDie {
@@ -278,39 +463,34 @@ fn unit_damaged(
     }
 }
 
-#[derive(Resource, Default)]
-struct HitList(HashSet<BulletHit>);
-
 fn hit(
-    mut ev_r: EventReader<BulletHit>,
+    mut ev_r: EventReader<ProjectileHit>,
     mut damage_events: EventWriter<DoDamage>,
-    clients: Query<&PlayerEndpoint>,
-    mut unit: Query<&NetEntId, With<AnyUnit>>,
+    mut applied: EventWriter<ApplyEffect>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
+    effects: Query<(&NetEntId, &StatusEffects)>,
     sr: Res<ServerResources<EventToServer>>,
-    mut hit_list: ResMut<HitList>,
+    skills: Res<Skills>,
 ) {
-    for e in ev_r.read() {
-        if hit_list.0.contains(e) {
-            continue;
-        }
-
-        hit_list.0.insert(e.clone());
-
-        let bullet_damage = { Cast::Shoot(ShootingData::default()).get_damage() };
-
-        for ent_id in &mut unit {
-            if ent_id == &e.player {
-                damage_events.send(DoDamage(*ent_id, bullet_damage));
+    for ProjectileHit {
+        hit: e,
+        caster,
+        skill,
+    } in ev_r.read()
+    {
+        let skill = skills.get_kind(*skill);
+        damage_events.send(DoDamage(e.player, damage_of(skill, caster, &effects)));
+        send_effects(&mut applied, skill, e.player);
+
+        for (c_net_client, interest) in &clients {
+            if interest.sees(&e.player) {
+                send_event_to_server(
+                    &sr.handler,
+                    c_net_client.0,
+                    &EventToClient::BulletHit(e.clone()),
+                );
             }
         }
-
-        for c_net_client in &clients {
-            send_event_to_server(
-                &sr.handler,
-                c_net_client.0,
-                &EventToClient::BulletHit(e.clone()),
-            );
-        }
     }
 }
 
@@ -319,7 +499,7 @@ struct DoSpawnInteractable(Vec3);
 fn spawn_interactable(
     mut do_spawns: EventReader<DoSpawnInteractable>,
     sr: Res<ServerResources<EventToServer>>,
-    clients: Query<&PlayerEndpoint>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
     mut commands: Commands,
 ) {
     for spawn in do_spawns.read() {
@@ -337,8 +517,10 @@ fn spawn_interactable(
             // TODO look for interactions
         ));
 
-        for c_net_client in &clients {
-            send_event_to_server(&sr.handler, c_net_client.0, &event)
+        for (c_net_client, interest) in &clients {
+            if interest.sees_point(spawn.0) {
+                send_event_to_server(&sr.handler, c_net_client.0, &event)
+            }
         }
     }
 }
@@ -347,7 +529,7 @@ fn on_die(
     mut death: EventReader<UnitDie>,
     sr: Res<ServerResources<EventToServer>>,
     ents: Query<(Entity, &NetEntId, &Transform), With<AnyUnit>>,
-    clients: Query<&PlayerEndpoint>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
     mut commands: Commands,
     mut do_spawns: EventWriter<DoSpawnInteractable>,
 ) {
@@ -363,8 +545,10 @@ fn on_die(
             }
         }
 
-        for c_net_client in &clients {
-            send_event_to_server(&sr.handler, c_net_client.0, &event)
+        for (c_net_client, interest) in &clients {
+            if interest.sees(&death.id) {
+                send_event_to_server(&sr.handler, c_net_client.0, &event)
+            }
         }
     }
 }
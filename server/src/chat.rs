@@ -1,6 +1,6 @@
 use std::fs::OpenOptions;
 
-use bevy::{prelude::*, utils::HashMap};
+use bevy::{diagnostic::DiagnosticsStore, prelude::*, utils::HashMap};
 
 pub struct ChatPlugin;
 impl Plugin for ChatPlugin {
@@ -22,7 +22,7 @@ impl Plugin for ChatPlugin {
 }
 
 use clap::{Args, Parser};
-use message_io::{network::Endpoint, node::NodeHandler};
+use message_io::network::Endpoint;
 use serde::{Deserialize, Serialize};
 use shared::{
     event::{
@@ -31,14 +31,21 @@ use shared::{
         spells::NPC,
         EventFromEndpoint, NetEntId, UnitData, UnitType, ERFE,
     },
-    netlib::{send_event_to_server, EventToClient, EventToServer, ServerResources},
+    faction::Faction,
+    netlib::{
+        rpc::respond, send_event_to_server, stats::EndpointDiagnostics, EventToClient,
+        EventToServer, NetworkHandler, ServerResources,
+    },
     stats::Health,
-    AnyUnit,
+    status_effects::StatusEffects,
+    AnyUnit, Config,
 };
 
 use crate::{
-    game_manager::GameManagerState, ConnectedPlayerName, EndpointToNetId, PlayerEndpoint,
-    ServerState,
+    accounts::LoggedIn,
+    game_manager::GameManagerState,
+    validation::{Violation, MAX_CHAT_LENGTH},
+    ConnectedPlayerName, EndpointToNetId, PlayerDisconnect, PlayerEndpoint, ServerState,
 };
 #[derive(Parser, Debug, Event)]
 #[command(name = "chat_command")]
@@ -46,6 +53,9 @@ use crate::{
 pub enum ChatCommand {
     Spawn(CmdSpawnUnit),
     List(CmdListUnits),
+    Kick(CmdKick),
+    /// Show how everyone's connection is doing
+    Netstats,
     ///SaveState
     S,
     ///StateState Load
@@ -59,6 +69,9 @@ pub enum ChatCommand {
 #[derive(Args, Debug)]
 pub struct CmdSpawnUnit {
     pub enemy_type: NPC,
+    /// Which side it is on, if not the usual one for its type
+    #[arg(short, long)]
+    pub faction: Option<Faction>,
 }
 
 /// List all the units on the server
@@ -68,6 +81,14 @@ pub struct CmdListUnits {
     verbose: bool,
 }
 
+/// Disconnect a player
+#[derive(Args, Debug)]
+pub struct CmdKick {
+    /// Names can have spaces in them, so this takes the rest of the line
+    #[arg(required = true)]
+    pub name: Vec<String>,
+}
+
 #[derive(Event)]
 struct RunChatCommand {
     runner: NetEntId,
@@ -80,10 +101,19 @@ fn on_chat(
     clients: Query<&PlayerEndpoint, With<AnyUnit>>,
     sr: Res<ServerResources<EventToServer>>,
     mut cmd: EventWriter<EventFromEndpoint<RunChatCommand>>,
+    mut violations: EventWriter<Violation>,
 ) {
     for chat in pd.read() {
         if let Some(chatter_net_id) = endpoint_mapping.map.get(&chat.endpoint) {
             let text = &chat.event.text;
+            let length = text.chars().count();
+            if length > MAX_CHAT_LENGTH {
+                violations.send(Violation {
+                    endpoint: chat.endpoint,
+                    reason: format!("Chat message of {length} characters is too long"),
+                });
+                continue;
+            }
             info!(?chatter_net_id, text, "Chat");
             if text.starts_with('/') {
                 // if it starts with /, its a command parse it using clap
@@ -92,11 +122,11 @@ fn on_chat(
                 let cmd_parts = [""].iter().cloned().chain(cmd_parts);
                 match ChatCommand::try_parse_from(cmd_parts) {
                     Ok(x) => {
-                        let event = EventToClient::Chat(Chat {
+                        let response = Chat {
                             source: None,
                             text: format!("Running {:?}", x),
-                        });
-                        send_event_to_server(&sr.handler, chat.endpoint, &event);
+                        };
+                        respond(&sr.handler, chat, response);
 
                         // Trigger event to send the chat command
                         cmd.send(EventFromEndpoint {
@@ -105,14 +135,16 @@ fn on_chat(
                                 runner: *chatter_net_id,
                                 command: x,
                             },
+                            tick: chat.tick,
+                            request: chat.request,
                         });
                     }
                     Err(k) => {
-                        let event = EventToClient::Chat(Chat {
+                        let response = Chat {
                             source: None,
                             text: format!("Error in {}\n{}", text, k),
-                        });
-                        send_event_to_server(&sr.handler, chat.endpoint, &event);
+                        };
+                        respond(&sr.handler, chat, response);
                     }
                 };
             } else {
@@ -142,9 +174,12 @@ struct SaveStateUnit {
     hp: Health,
     unit: UnitType,
     transform: Transform,
+    /// Saves from before factions don't have one
+    #[serde(default)]
+    faction: Option<Faction>,
 }
 
-type NPCSavestateQuery<'a> = (&'a NPC, &'a Health, &'a Transform);
+type NPCSavestateQuery<'a> = (&'a NPC, &'a Health, &'a Transform, &'a Faction);
 impl From<NPCSavestateQuery<'_>> for SaveStateUnit {
     fn from(value: NPCSavestateQuery<'_>) -> Self {
         Self {
@@ -153,6 +188,7 @@ impl From<NPCSavestateQuery<'_>> for SaveStateUnit {
                 npc_type: value.0.clone(),
             },
             transform: *value.2,
+            faction: Some(*value.3),
         }
     }
 }
@@ -161,7 +197,7 @@ impl From<NPCSavestateQuery<'_>> for SaveStateUnit {
 #[derive(Clone)]
 struct NetworkableClientEndpoint {
     endpoint: Endpoint,
-    handler: NodeHandler<()>,
+    handler: NetworkHandler,
 }
 
 #[derive(Event, Clone)]
@@ -217,8 +253,12 @@ fn on_load_savestate(
         for unit in save_data.0.npcs.clone() {
             spawn_npc.send(SpawnUnit {
                 data: UnitData {
+                    faction: unit
+                        .faction
+                        .unwrap_or_else(|| unit.unit.default_faction()),
                     unit: unit.unit,
                     health: unit.hp,
+                    effects: StatusEffects::default(),
                     transform: unit.transform,
                     ent_id: NetEntId(rand::random()),
                 },
@@ -229,20 +269,31 @@ fn on_load_savestate(
 
 fn on_chat_command(
     mut cmd: EventReader<EventFromEndpoint<RunChatCommand>>,
-    players: Query<(Entity, &Transform, &NetEntId, &ConnectedPlayerName)>,
+    players: Query<(
+        Entity,
+        &Transform,
+        &NetEntId,
+        &ConnectedPlayerName,
+        Option<&LoggedIn>,
+    )>,
+    config: Res<Config>,
     list_npc_query: Query<(&NetEntId, &NPC), With<AnyUnit>>,
     list_player_query: Query<(&NetEntId, &ConnectedPlayerName), With<AnyUnit>>,
+    connected_players: Query<(&ConnectedPlayerName, &PlayerEndpoint)>,
+    diagnostics: Res<DiagnosticsStore>,
+    endpoint_diagnostics: Res<EndpointDiagnostics<EventToServer>>,
     sr: Res<ServerResources<EventToServer>>,
     mut spawn_npc: EventWriter<SpawnUnit>,
     mut load_savestate: EventWriter<LoadSaveState>,
     mut save_savestate: EventWriter<SaveSaveState>,
+    mut kick: EventWriter<PlayerDisconnect>,
     mut next_game_manager_state: ResMut<NextState<GameManagerState>>,
     cur_game_manager_state: Res<State<GameManagerState>>,
 ) {
     for command in cmd.read() {
-        let (_runner_ent, runner_tfm, _runner_net_ent, _runner_name) = match players
+        let (_runner_ent, runner_tfm, _runner_net_ent, runner_name, runner_account) = match players
             .iter()
-            .find(|(_, _, &id, _)| id == command.event.runner)
+            .find(|(_, _, &id, _, _)| id == command.event.runner)
         {
             Some(s) => s,
             None => continue,
@@ -256,7 +307,9 @@ fn on_chat_command(
                             npc_type: unit.enemy_type.clone(),
                         },
                         ent_id: NetEntId(rand::random()),
+                        faction: unit.faction.unwrap_or(unit.enemy_type.faction()),
                         health: unit.enemy_type.get_base_health(),
+                        effects: StatusEffects::default(),
                         transform: Transform::from_translation(
                             runner_tfm.translation * Vec3::new(1., 0., 1.),
                         ),
@@ -283,6 +336,55 @@ fn on_chat_command(
                 });
                 send_event_to_server(&sr.handler, command.endpoint, &event);
             }
+            ChatCommand::Kick(CmdKick { name }) => {
+                let name = name.join(" ");
+                let admin = runner_account.is_some_and(|x| x.is_admin(&config));
+                let text = match list_player_query
+                    .iter()
+                    .find(|(_, player)| player.name == name)
+                {
+                    _ if !admin => "You are not allowed to kick anyone".to_string(),
+                    Some((&ent, _)) => {
+                        kick.send(PlayerDisconnect {
+                            ent,
+                            reason: Some(format!("Kicked by {}", runner_name.name)),
+                        });
+                        format!("Kicked {name}")
+                    }
+                    None => format!("Nobody is called {name}"),
+                };
+
+                let event = EventToClient::Chat(Chat { source: None, text });
+                send_event_to_server(&sr.handler, command.endpoint, &event);
+            }
+            ChatCommand::Netstats => {
+                let value = |endpoint, stat| {
+                    endpoint_diagnostics
+                        .path(endpoint, stat)
+                        .and_then(|x| diagnostics.get(&x))
+                        .and_then(|x| x.value())
+                        .map_or("-".to_string(), |x| format!("{x:.1}"))
+                };
+                let mut lines = vec![];
+                for (ConnectedPlayerName { name }, PlayerEndpoint(endpoint)) in &connected_players {
+                    lines.push(format!(
+                        "{name}: ping {} ms, loss {}%, up {} kbps, down {} kbps",
+                        value(*endpoint, "rtt"),
+                        value(*endpoint, "loss"),
+                        value(*endpoint, "sent"),
+                        value(*endpoint, "received"),
+                    ));
+                }
+                if lines.is_empty() {
+                    lines.push("Nobody is connected".to_string());
+                }
+
+                let event = EventToClient::Chat(Chat {
+                    source: None,
+                    text: lines.join("\n"),
+                });
+                send_event_to_server(&sr.handler, command.endpoint, &event);
+            }
             ChatCommand::Start => {
                 info!("gaming");
                 next_game_manager_state.set(GameManagerState::Playing);
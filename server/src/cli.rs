@@ -0,0 +1,8 @@
+use bevy::ecs::system::Resource;
+use shared::netlib::conditioner::LinkConditions;
+
+#[derive(clap::Parser, Resource, Debug)]
+pub struct CliArgs {
+    #[command(flatten)]
+    pub link_conditions: LinkConditions,
+}
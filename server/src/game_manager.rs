@@ -11,14 +11,17 @@ impl Plugin for GamePlugin {
                 TimerMode::Repeating,
             )))
             .add_systems(
-                Update,
+                FixedUpdate,
                 (tick_game_manager).run_if(in_state(GameManagerState::Playing)),
             );
     }
 }
 
 use rand::Rng;
-use shared::event::{client::SpawnUnit, spells::NPC, NetEntId, UnitData};
+use shared::{
+    event::{client::SpawnUnit, spells::NPC, NetEntId, UnitData},
+    status_effects::StatusEffects,
+};
 
 #[derive(Resource, Clone, Debug)]
 pub struct SpawnTimer(Timer);
@@ -34,7 +37,7 @@ pub enum GameManagerState {
 fn tick_game_manager(
     mut spawn_npc: EventWriter<SpawnUnit>,
     mut timer: ResMut<SpawnTimer>,
-    time: Res<Time<Virtual>>,
+    time: Res<Time>,
 ) {
     timer.0.tick(time.delta());
 
@@ -51,7 +54,9 @@ fn tick_game_manager(
                     npc_type: enemy_type.clone(),
                 },
                 ent_id: NetEntId(rand::random()),
+                faction: enemy_type.faction(),
                 health: enemy_type.get_base_health(),
+                effects: StatusEffects::default(),
                 transform: Transform::from_translation(location),
             },
         });
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use shared::{
+    event::{
+        client::{LeaveInterest, SpawnUnit},
+        spells::NPC,
+        NetEntId, UnitData, UnitType,
+    },
+    faction::Faction,
+    netlib::{send_event_to_server_batch, EventToClient, EventToServer, ServerResources},
+    stats::Health,
+    status_effects::StatusEffects,
+    AnyUnit,
+};
+
+use crate::{ConnectedPlayerName, PlayerEndpoint, ServerState};
+
+/// Side length of a grid cell. Doesn't change what anyone sees, only how many cells we check.
+const CELL_SIZE: f32 = 32.0;
+
+/// Units only leave your view once they are this much further out than the radius, so something
+/// walking along the edge doesn't keep popping in and out.
+const LEAVE_MARGIN: f32 = 1.1;
+
+pub struct InterestPlugin;
+
+impl Plugin for InterestPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InterestGrid>().add_systems(
+            FixedUpdate,
+            (rebuild_interest_grid, update_interest)
+                .chain()
+                .run_if(in_state(ServerState::Running)),
+        );
+    }
+}
+
+/// Every unit, bucketed by where it is, so finding what is near a client doesn't have to look at
+/// the whole world.
+#[derive(Resource, Debug, Default)]
+pub struct InterestGrid {
+    cells: HashMap<IVec2, Vec<(NetEntId, Vec3)>>,
+    entities: HashMap<NetEntId, Entity>,
+}
+
+impl InterestGrid {
+    fn cell(pos: Vec3) -> IVec2 {
+        (pos.xz() / CELL_SIZE).floor().as_ivec2()
+    }
+
+    fn insert(&mut self, id: NetEntId, entity: Entity, pos: Vec3) {
+        self.cells
+            .entry(Self::cell(pos))
+            .or_default()
+            .push((id, pos));
+        self.entities.insert(id, entity);
+    }
+
+    /// Every unit within `radius` of `center`, ignoring height
+    pub fn near(&self, center: Vec3, radius: f32) -> impl Iterator<Item = NetEntId> + '_ {
+        let min = Self::cell(center - Vec3::splat(radius));
+        let max = Self::cell(center + Vec3::splat(radius));
+
+        (min.x..=max.x)
+            .flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(move |(_, pos)| pos.xz().distance_squared(center.xz()) <= radius * radius)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn contains(&self, id: &NetEntId) -> bool {
+        self.entities.contains_key(id)
+    }
+
+    pub fn entity(&self, id: &NetEntId) -> Option<Entity> {
+        self.entities.get(id).copied()
+    }
+}
+
+/// What a single client knows about. Anything they can't see, they don't get told about.
+#[derive(Component, Debug)]
+pub struct Interest {
+    center: Vec3,
+    radius: f32,
+    visible: HashSet<NetEntId>,
+}
+
+impl Interest {
+    pub fn new(center: Vec3, radius: f32, visible: HashSet<NetEntId>) -> Self {
+        Self {
+            center,
+            radius,
+            visible,
+        }
+    }
+
+    /// Does this client have `id` spawned on their side?
+    pub fn sees(&self, id: &NetEntId) -> bool {
+        self.visible.contains(id)
+    }
+
+    /// For things that aren't units, like interactables
+    pub fn sees_point(&self, pos: Vec3) -> bool {
+        pos.xz().distance_squared(self.center.xz()) <= self.radius * self.radius
+    }
+}
+
+fn rebuild_interest_grid(
+    mut grid: ResMut<InterestGrid>,
+    units: Query<(Entity, &NetEntId, &Transform), With<AnyUnit>>,
+) {
+    grid.cells.clear();
+    grid.entities.clear();
+    for (entity, id, tfm) in &units {
+        grid.insert(*id, entity, tfm.translation);
+    }
+}
+
+fn update_interest(
+    grid: Res<InterestGrid>,
+    mut clients: Query<(&PlayerEndpoint, &Transform, &mut Interest)>,
+    units: Query<(&Transform, &Health, &Faction), With<AnyUnit>>,
+    effects: Query<&StatusEffects>,
+    players: Query<&ConnectedPlayerName>,
+    npcs: Query<&NPC>,
+    sr: Res<ServerResources<EventToServer>>,
+) {
+    for (endpoint, tfm, mut interest) in &mut clients {
+        interest.center = tfm.translation;
+
+        let mut visible: HashSet<_> = grid.near(tfm.translation, interest.radius).collect();
+        // Keep anything that is only just outside of the radius
+        visible.extend(
+            grid.near(tfm.translation, interest.radius * LEAVE_MARGIN)
+                .filter(|id| interest.visible.contains(id)),
+        );
+
+        let mut events = vec![];
+        for id in visible.difference(&interest.visible) {
+            let Some(ent) = grid.entity(id) else {
+                continue;
+            };
+            let Ok((tfm, &health, &faction)) = units.get(ent) else {
+                continue;
+            };
+
+            let unit = if let Ok(ConnectedPlayerName { name }) = players.get(ent) {
+                UnitType::Player { name: name.clone() }
+            } else if let Ok(npc_type) = npcs.get(ent) {
+                UnitType::NPC {
+                    npc_type: npc_type.clone(),
+                }
+            } else {
+                continue;
+            };
+            events.push(EventToClient::SpawnUnit(SpawnUnit {
+                data: UnitData {
+                    unit,
+                    ent_id: *id,
+                    faction,
+                    health,
+                    effects: effects.get(ent).cloned().unwrap_or_default(),
+                    transform: *tfm,
+                },
+            }));
+        }
+
+        // Units that died or disconnected already told the client about it
+        events.extend(
+            interest
+                .visible
+                .difference(&visible)
+                .filter(|id| grid.contains(id))
+                .map(|id| EventToClient::LeaveInterest(LeaveInterest { id: *id })),
+        );
+
+        if !events.is_empty() {
+            send_event_to_server_batch(&sr.handler, endpoint.0, &events);
+        }
+        interest.visible = visible;
+    }
+}
@@ -0,0 +1,695 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicI16, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_time::common_conditions::on_timer;
+use message_io::network::Endpoint;
+use shared::{
+    animations::AnimationTimer,
+    event::{
+        client::{
+            ConnectRejected, Disconnect, HeartbeatResponse, PlayerDisconnected, SomeoneMoved,
+            WorldData, YourMovementResult,
+        },
+        server::{Cast, ChangeMovement, ConnectRequest, Heartbeat},
+        spells::NPC,
+        NetEntId, UnitData, UnitType, ERFE, PROTOCOL_VERSION,
+    },
+    faction::{Faction, FactionRules},
+    movement::{apply_input, facing, speed_multiplier, MAX_INPUT_DT},
+    netlib::{
+        send_event_to_server, stats::NetDiagnosticsPlugin, EventToClient, EventToServer,
+        NetworkListenTargets, ServerResources,
+    },
+    replication::Replicator,
+    skills::{Skills, SkillsPlugin},
+    stats::Health,
+    status_effects::StatusEffects,
+    tick::{ServerTick, TICK_HZ},
+    unit::MovementIntention,
+    Config, ConfigPlugin, Controlled,
+};
+
+use crate::{
+    accounts::{Accounts, PendingLogin, MAX_PENDING_LOGINS},
+    interest::Interest,
+    session::{PlayerTimedOut, Session},
+    validation::Violation,
+};
+
+/// How often to run the system
+const HEARTBEAT_MILLIS: u64 = 200;
+/// How long until disconnect
+const HEARTBEAT_TIMEOUT: u64 = 1000;
+/// How long do you have to connect, as a multipler of the heartbeart timeout.
+/// If the timeout is 1000 ms, then `5` would mean you have `5000ms` to connect.
+const HEARTBEAT_CONNECTION_GRACE_PERIOD: u64 = 5;
+
+#[derive(States, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum ServerState {
+    #[default]
+    NotReady,
+    Starting,
+    Running,
+}
+
+#[derive(Resource, Default)]
+struct HeartbeatList {
+    heartbeats: HashMap<NetEntId, Arc<AtomicI16>>,
+}
+
+#[derive(Resource, Default)]
+struct EndpointToNetId {
+    map: HashMap<Endpoint, NetEntId>,
+}
+
+/// Everyone still waiting on their password to be checked
+#[derive(Resource, Default)]
+struct PendingLogins(Vec<(Endpoint, ConnectRequest, PendingLogin)>);
+
+#[derive(Debug, Component)]
+pub struct ConnectedPlayerName {
+    pub name: String,
+}
+
+#[derive(Debug, Component)]
+pub struct PlayerEndpoint(pub Endpoint);
+
+/// How much movement time a client can bank up. This lets them catch up after a lag spike, but
+/// not move faster than real time for long.
+const MAX_INPUT_BUDGET: f32 = 0.5;
+
+/// Where we are in processing a client's movement inputs
+#[derive(Debug, Component, Default)]
+struct InputState {
+    last_input: Option<u32>,
+    /// Seconds of movement this client is still allowed to use
+    time_budget: f32,
+}
+
+#[derive(Event)]
+struct PlayerDisconnect {
+    ent: NetEntId,
+    /// If set, tell the player why they are being disconnected
+    reason: Option<String>,
+}
+
+/// Set from the Ctrl-C handler, which runs on its own thread
+#[derive(Resource, Default)]
+pub struct ShutdownRequested(pub Arc<AtomicBool>);
+
+pub mod accounts;
+pub mod casting_spells;
+pub mod chat;
+pub mod game_manager;
+pub mod interest;
+pub mod npc;
+pub mod replication;
+pub mod session;
+pub mod status_effects;
+pub mod validation;
+
+/// The whole game server. The binary adds logging and Ctrl-C handling on top of this, tests run it
+/// as is, see `server/tests/harness`.
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        shared::event::server::register_events(app);
+        app.insert_resource(EndpointToNetId::default())
+            .init_resource::<PendingLogins>()
+            .insert_resource(HeartbeatList::default())
+            .insert_resource(Time::<Fixed>::from_hz(TICK_HZ))
+            .init_resource::<ServerTick>()
+            .init_resource::<ShutdownRequested>()
+            .add_event::<PlayerDisconnect>()
+            .add_plugins((
+                ConfigPlugin,
+                SkillsPlugin,
+                accounts::AccountsPlugin,
+                casting_spells::CastingPlugin,
+                chat::ChatPlugin,
+                npc::NPCPlugin,
+                game_manager::GamePlugin,
+                interest::InterestPlugin,
+                replication::ReplicationPlugin,
+                session::SessionPlugin,
+                status_effects::StatusEffectsPlugin,
+                validation::ValidationPlugin,
+                NetDiagnosticsPlugin::<EventToServer>::default(),
+                //StatusPlugin,
+            ))
+            .init_state::<ServerState>()
+            .add_systems(
+                Startup,
+                (
+                    add_network_connection_info_from_config,
+                    add_faction_rules_from_config,
+                    |mut state: ResMut<NextState<ServerState>>| state.set(ServerState::Starting),
+                ),
+            )
+            .add_systems(
+                OnEnter(ServerState::Starting),
+                (
+                    shared::netlib::setup_server::<EventToServer>,
+                    |mut state: ResMut<NextState<ServerState>>| state.set(ServerState::Running),
+                ),
+            )
+            .add_systems(
+                FixedFirst,
+                advance_tick.run_if(in_state(ServerState::Running)),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    on_player_disconnect,
+                    on_client_disconnect,
+                    on_player_connect,
+                    finish_logins,
+                    on_player_heartbeat,
+                    shared::event::server::drain_events,
+                    refill_input_budget,
+                    on_movement,
+                )
+                    .run_if(in_state(ServerState::Running)),
+            )
+            .add_systems(
+                Update,
+                (
+                    check_heartbeats.run_if(on_timer(Duration::from_millis(200))),
+                    check_shutdown_requested,
+                ),
+            )
+            .add_systems(Last, on_shutdown);
+    }
+}
+
+fn add_network_connection_info_from_config(config: Res<Config>, mut commands: Commands) {
+    commands.insert_resource(NetworkListenTargets(config.listen_targets()));
+}
+
+fn add_faction_rules_from_config(config: Res<Config>, mut commands: Commands) {
+    commands.insert_resource(FactionRules { pvp: config.pvp() });
+}
+
+fn on_player_connect(
+    mut new_players: ERFE<ConnectRequest>,
+    endpoint_to_net_id: Res<EndpointToNetId>,
+    sessions: Query<&Session>,
+    // Includes players waiting to reconnect
+    names: Query<&ConnectedPlayerName>,
+    accounts: Res<Accounts>,
+    mut pending: ResMut<PendingLogins>,
+    sr: Res<ServerResources<EventToServer>>,
+) {
+    for player in new_players.read() {
+        info!("Got packet");
+        if player.event.protocol_version != PROTOCOL_VERSION {
+            warn!(
+                ?player.endpoint,
+                their_version = player.event.protocol_version,
+                our_version = PROTOCOL_VERSION,
+                "Rejecting client with a different protocol version"
+            );
+            let event = EventToClient::ConnectRejected(ConnectRejected {
+                server_protocol_version: PROTOCOL_VERSION,
+                reason: format!(
+                    "Version mismatch: server is {:016x}, you are {:016x}. Update your game!",
+                    PROTOCOL_VERSION, player.event.protocol_version,
+                ),
+            });
+            send_event_to_server(&sr.handler, player.endpoint, &event);
+            continue;
+        }
+
+        // Already playing, `session::resend_world_data` answers them
+        if endpoint_to_net_id.map.contains_key(&player.endpoint) {
+            continue;
+        }
+
+        // Picked up by `session::on_resume` instead
+        if let Some(token) = player.event.resume {
+            if sessions.iter().any(|x| x.token == token) {
+                continue;
+            }
+        }
+
+        if pending
+            .0
+            .iter()
+            .any(|(endpoint, ..)| *endpoint == player.endpoint)
+        {
+            continue;
+        }
+        if pending.0.len() >= MAX_PENDING_LOGINS {
+            debug!(?player.endpoint, "Too many logins at once, they will ask again");
+            continue;
+        }
+
+        let login = accounts.login(
+            player.event.name.as_deref(),
+            player.event.password.as_deref(),
+            names.iter().map(|x| x.name.as_str()),
+        );
+        match login {
+            Ok(login) => pending
+                .0
+                .push((player.endpoint, player.event.clone(), login)),
+            Err(reason) => reject_login(&sr, player.endpoint, reason),
+        }
+    }
+}
+
+fn reject_login(sr: &ServerResources<EventToServer>, endpoint: Endpoint, reason: String) {
+    warn!(?endpoint, ?reason, "Rejecting login");
+    let event = EventToClient::ConnectRejected(ConnectRejected {
+        server_protocol_version: PROTOCOL_VERSION,
+        reason,
+    });
+    send_event_to_server(&sr.handler, endpoint, &event);
+}
+
+/// Spawn everyone whose [PendingLogin] is done
+fn finish_logins(
+    mut pending: ResMut<PendingLogins>,
+    mut heartbeat_mapping: ResMut<HeartbeatList>,
+    mut endpoint_to_net_id: ResMut<EndpointToNetId>,
+    clients: Query<(
+        &Transform,
+        &PlayerEndpoint,
+        &NetEntId,
+        &ConnectedPlayerName,
+        &Health,
+        &Faction,
+        Entity,
+    )>,
+    npcs: Query<(&Transform, &NetEntId, &Health, &Faction, &NPC, Entity)>,
+    effects: Query<&StatusEffects>,
+    // Includes players waiting to reconnect
+    names: Query<&ConnectedPlayerName>,
+    mut accounts: ResMut<Accounts>,
+    sr: Res<ServerResources<EventToServer>>,
+    config: Res<Config>,
+    skills: Res<Skills>,
+    rules: Res<FactionRules>,
+    mut commands: Commands,
+) {
+    let radius = config.interest_radius();
+    let (done, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut pending.0)
+        .into_iter()
+        .partition(|(.., login)| login.is_finished());
+    pending.0 = waiting;
+
+    // Units spawned here only show up in `names` next tick
+    let mut joined: Vec<String> = vec![];
+    for (endpoint, request, login) in done {
+        let online = names
+            .iter()
+            .map(|x| x.name.as_str())
+            .chain(joined.iter().map(String::as_str));
+        let login = match login.join().and_then(|x| accounts.finish_login(x, online)) {
+            Ok(login) => login,
+            Err(reason) => {
+                reject_login(&sr, endpoint, reason);
+                continue;
+            }
+        };
+        joined.push(login.name.clone());
+        let name = login.name.clone();
+
+        let session = rand::random();
+
+        //if they are too far, just put them at the spawn
+        let default_spawn = request
+            .my_location
+            .with_translation(Vec3::new(0.0, 0.0, 0.0));
+
+        let spawn_location = if let Some(position) = login.account.as_ref().and_then(|x| x.position)
+        {
+            // Back where they left off
+            default_spawn.with_translation(position)
+        } else if request
+            .my_location
+            .translation
+            .distance_squared(default_spawn.translation)
+            > 10.0
+        {
+            default_spawn
+        } else {
+            request.my_location
+        };
+
+        let health = login
+            .account
+            .as_ref()
+            .and_then(|x| x.health)
+            .filter(|x| x.0 > 0)
+            .unwrap_or_default();
+
+        let new_player_data = UnitData {
+            ent_id: NetEntId::random(),
+            faction: Faction::Players,
+            health,
+            effects: StatusEffects::default(),
+            transform: spawn_location,
+            unit: UnitType::Player { name: name.clone() },
+        };
+
+        info!(?name, ?new_player_data.ent_id, "Player Connected");
+
+        // The new player we just spawned is the first unit in the list that we send to the client.
+        let mut unit_list = vec![new_player_data.clone()];
+        let in_range = |tfm: &Transform| {
+            tfm.translation
+                .xz()
+                .distance_squared(spawn_location.translation.xz())
+                <= radius * radius
+        };
+
+        // Everyone else finds out about the new player from `interest::update_interest`
+        for (c_tfm, _, &ent_id, ConnectedPlayerName { name: c_name }, &health, &faction, ent) in
+            clients.iter().filter(|(tfm, ..)| in_range(tfm))
+        {
+            unit_list.push(UnitData {
+                unit: UnitType::Player {
+                    name: c_name.clone(),
+                },
+                ent_id,
+                faction,
+                health,
+                effects: effects.get(ent).cloned().unwrap_or_default(),
+                transform: *c_tfm,
+            });
+        }
+
+        for (&transform, &ent_id, &health, &faction, npc_type, ent) in
+            npcs.iter().filter(|(tfm, ..)| in_range(tfm))
+        {
+            unit_list.push(UnitData {
+                unit: UnitType::NPC {
+                    npc_type: npc_type.clone(),
+                },
+                ent_id,
+                faction,
+                health,
+                effects: effects.get(ent).cloned().unwrap_or_default(),
+                transform,
+            });
+        }
+
+        // Directly spawn the unit here, instead of sending a SpawnUnit event.
+        let mut unit = commands.spawn((
+            ConnectedPlayerName { name },
+            new_player_data.ent_id,
+            new_player_data.faction,
+            new_player_data.health,
+            new_player_data.transform,
+            PlayerEndpoint(endpoint),
+            // Used as a target for some AI
+            Controlled,
+            MovementIntention(Vec2::ZERO),
+            InputState::default(),
+            Replicator::default(),
+            Interest::new(
+                spawn_location.translation,
+                radius,
+                unit_list.iter().map(|x| x.ent_id).collect(),
+            ),
+            Session { token: session },
+            // Transform component used for generic systems
+            shared::AnyUnit,
+        ));
+        if let Some(logged_in) = login.component() {
+            unit.insert(logged_in);
+        }
+
+        heartbeat_mapping
+            .heartbeats
+            .insert(new_player_data.ent_id, new_heartbeat());
+
+        endpoint_to_net_id
+            .map
+            .insert(endpoint, new_player_data.ent_id);
+        sr.handler.mark_connected(endpoint);
+
+        // Finally, tell the client all this info.
+        let event = EventToClient::WorldData(WorldData {
+            your_unit_id: new_player_data.ent_id,
+            unit_data: unit_list,
+            session,
+            skills_checksum: skills.checksum(),
+            faction_rules: *rules,
+        });
+        send_event_to_server(&sr.handler, endpoint, &event);
+    }
+}
+
+/// Each time we miss a heartbeat, we increment the Atomic counter.
+/// So, we initially set this to negative number to give extra time for the initial
+/// connection.
+fn new_heartbeat() -> Arc<AtomicI16> {
+    let hb_grace_period =
+        (HEARTBEAT_CONNECTION_GRACE_PERIOD - 1) * (HEARTBEAT_TIMEOUT / HEARTBEAT_MILLIS);
+
+    Arc::new(AtomicI16::new(-(hb_grace_period as i16)))
+}
+
+fn check_heartbeats(
+    heartbeat_mapping: Res<HeartbeatList>,
+    mut on_timeout: EventWriter<PlayerTimedOut>,
+) {
+    for (ent_id, beats_missed) in &heartbeat_mapping.heartbeats {
+        let beats = beats_missed.fetch_add(1, std::sync::atomic::Ordering::Acquire);
+        trace!(?ent_id, ?beats, "hb");
+        if beats >= (HEARTBEAT_TIMEOUT / HEARTBEAT_MILLIS) as i16 {
+            warn!("Missed {beats} beats, disconnecting {ent_id:?}");
+            on_timeout.send(PlayerTimedOut { ent: *ent_id });
+        }
+    }
+}
+
+fn on_player_disconnect(
+    mut pd: EventReader<PlayerDisconnect>,
+    // Players waiting to reconnect have no endpoint
+    clients: Query<(Entity, Option<&PlayerEndpoint>, &NetEntId), With<ConnectedPlayerName>>,
+    mut commands: Commands,
+    mut heartbeat_mapping: ResMut<HeartbeatList>,
+    mut endpoint_mapping: ResMut<EndpointToNetId>,
+    sr: Res<ServerResources<EventToServer>>,
+) {
+    for player in pd.read() {
+        heartbeat_mapping.heartbeats.remove(&player.ent);
+
+        let event = EventToClient::PlayerDisconnected(PlayerDisconnected { id: player.ent });
+        for (_c_ent, c_net_client, _c_net_ent) in &clients {
+            if _c_net_ent == &player.ent {
+                commands.entity(_c_ent).despawn_recursive();
+                let Some(c_net_client) = c_net_client else {
+                    continue;
+                };
+
+                if let Some(reason) = &player.reason {
+                    let event = EventToClient::Disconnect(Disconnect {
+                        reason: reason.clone(),
+                    });
+                    send_event_to_server(&sr.handler, c_net_client.0, &event);
+                }
+
+                endpoint_mapping.map.remove(&c_net_client.0);
+                // Don't keep resending them things they will never ack
+                sr.handler.forget(c_net_client.0);
+                continue;
+            }
+            if let Some(c_net_client) = c_net_client {
+                send_event_to_server(&sr.handler, c_net_client.0, &event);
+            }
+        }
+    }
+}
+
+/// The client told us they are leaving
+fn on_client_disconnect(
+    mut disconnects: ERFE<shared::event::server::Disconnect>,
+    endpoint_mapping: Res<EndpointToNetId>,
+    mut on_disconnect: EventWriter<PlayerDisconnect>,
+) {
+    for disconnect in disconnects.read() {
+        if let Some(id) = endpoint_mapping.map.get(&disconnect.endpoint) {
+            info!(?id, %disconnect.event.reason, "Player left");
+            on_disconnect.send(PlayerDisconnect {
+                ent: *id,
+                reason: None,
+            });
+        }
+    }
+}
+
+fn check_shutdown_requested(requested: Res<ShutdownRequested>, mut exit: EventWriter<AppExit>) {
+    if requested.0.load(Ordering::Acquire) {
+        info!("Shutting down");
+        exit.send(AppExit);
+    }
+}
+
+/// Say goodbye to everyone before the app exits, so they don't sit there waiting for us.
+fn on_shutdown(
+    mut exit: EventReader<AppExit>,
+    clients: Query<&PlayerEndpoint>,
+    sr: Option<Res<ServerResources<EventToServer>>>,
+) {
+    if exit.read().next().is_none() {
+        return;
+    }
+    let Some(sr) = sr else {
+        return;
+    };
+
+    let event = EventToClient::Disconnect(Disconnect {
+        reason: "Server is shutting down".into(),
+    });
+    for endpoint in &clients {
+        send_event_to_server(&sr.handler, endpoint.0, &event);
+    }
+    sr.handler.shutdown();
+}
+
+fn on_player_heartbeat(
+    mut pd: ERFE<Heartbeat>,
+    heartbeat_mapping: Res<HeartbeatList>,
+    endpoint_mapping: Res<EndpointToNetId>,
+    sr: Res<ServerResources<EventToServer>>,
+    tick: Res<ServerTick>,
+) {
+    for hb in pd.read() {
+        // TODO tryblocks?
+        if let Some(id) = endpoint_mapping.map.get(&hb.endpoint) {
+            if let Some(beats) = heartbeat_mapping.heartbeats.get(id) {
+                beats.fetch_min(0, std::sync::atomic::Ordering::Release);
+            }
+
+            let event = EventToClient::HeartbeatResponse(HeartbeatResponse {
+                client_time: hb.event.client_time,
+                tick: tick.0,
+            });
+            send_event_to_server(&sr.handler, hb.endpoint, &event);
+        }
+    }
+}
+
+/// Start the next simulation step, and stamp everything we send during it with the new tick.
+fn advance_tick(mut tick: ResMut<ServerTick>, sr: Res<ServerResources<EventToServer>>) {
+    tick.0 += 1;
+    sr.handler.set_tick(tick.0);
+}
+
+fn refill_input_budget(mut clients: Query<&mut InputState>, time: Res<Time>) {
+    for mut state in &mut clients {
+        state.time_budget = (state.time_budget + time.delta_seconds()).min(MAX_INPUT_BUDGET);
+    }
+}
+
+fn on_movement(
+    mut pd: ERFE<ChangeMovement>,
+    endpoint_mapping: Res<EndpointToNetId>,
+    mut clients: Query<
+        (
+            &PlayerEndpoint,
+            &NetEntId,
+            &mut Transform,
+            &mut MovementIntention,
+            &mut InputState,
+            Option<(&AnimationTimer, &Cast)>,
+            Option<&StatusEffects>,
+        ),
+        With<ConnectedPlayerName>,
+    >,
+    watchers: Query<(&PlayerEndpoint, &NetEntId, &Interest)>,
+    sr: Res<ServerResources<EventToServer>>,
+    skills: Res<Skills>,
+    mut violations: EventWriter<Violation>,
+) {
+    for movement in pd.read() {
+        let Some(moved_net_id) = endpoint_mapping.map.get(&movement.endpoint) else {
+            continue;
+        };
+
+        let inputs = match &movement.event {
+            ChangeMovement::Inputs(inputs) => inputs,
+            ChangeMovement::SetTransform(_) | ChangeMovement::Move2d(_) => {
+                violations.send(Violation {
+                    endpoint: movement.endpoint,
+                    reason: "Tried to set their own position".into(),
+                });
+                continue;
+            }
+            ChangeMovement::StandStill | ChangeMovement::AttackIntent(_) => {
+                // Just rebroadcast the packet to everyone else
+                let event = EventToClient::SomeoneMoved(SomeoneMoved {
+                    id: *moved_net_id,
+                    movement: movement.event.clone(),
+                });
+                for (c_net_client, c_net_ent, interest) in &watchers {
+                    if moved_net_id != c_net_ent && interest.sees(moved_net_id) {
+                        send_event_to_server(&sr.handler, c_net_client.0, &event);
+                    }
+                }
+                continue;
+            }
+        };
+
+        let Some((c_net_client, _, mut c_tfm, mut intent, mut state, casting, effects)) = clients
+            .iter_mut()
+            .find(|(_, c_net_ent, ..)| *c_net_ent == moved_net_id)
+        else {
+            continue;
+        };
+
+        // One NaN would be stuck in their position for good
+        if !inputs
+            .iter()
+            .all(|x| x.dt.is_finite() && x.intention.is_finite())
+        {
+            violations.send(Violation {
+                endpoint: movement.endpoint,
+                reason: "Sent inputs that aren't numbers".into(),
+            });
+            continue;
+        }
+
+        let speed_multiplier = speed_multiplier(&skills, casting, effects);
+        for input in inputs {
+            // Clients resend inputs until we ack them, so we will see most of them twice
+            if state.last_input.is_some_and(|last| input.seq <= last) {
+                continue;
+            }
+
+            let mut input = *input;
+            input.dt = input.dt.clamp(0.0, MAX_INPUT_DT).min(state.time_budget);
+            state.time_budget -= input.dt;
+
+            c_tfm.translation = apply_input(c_tfm.translation, &input, speed_multiplier);
+            if input.intention != Vec2::ZERO {
+                c_tfm.rotation = facing(input.intention);
+            }
+            intent.0 = input.intention.clamp_length_max(1.0) * speed_multiplier;
+            state.last_input = Some(input.seq);
+        }
+
+        let Some(last_input) = state.last_input else {
+            continue;
+        };
+
+        // Everyone else finds out where we went through replication
+        let event = EventToClient::YourMovementResult(YourMovementResult {
+            last_input,
+            transform: *c_tfm,
+        });
+        send_event_to_server(&sr.handler, c_net_client.0, &event);
+    }
+}
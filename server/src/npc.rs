@@ -1,17 +1,13 @@
 use std::time::Duration;
 
 use bevy::prelude::*;
-use bevy_time::common_conditions::on_timer;
 use shared::{
     animations::DoCast, event::{
-        client::{SomeoneCast, SomeoneMoved, SpawnUnit}, server::Cast, spells::AIType, NetEntId
-    }, netlib::{
-        send_event_to_server, send_event_to_server_batch, EventToClient, EventToServer,
-        ServerResources,
-    }, unit::{AttackIntention, MovementIntention}, AnyUnit, Controlled
+        client::{SomeoneCast, SpawnUnit}, server::Cast, spells::AIType, NetEntId
+    }, faction::{Faction, FactionRules, Relation}, movement::PLAYER_SPEED, status_effects::StatusEffects, unit::{AttackIntention, MovementIntention}, AnyUnit, Controlled
 };
 
-use crate::{ConnectedPlayerName, PlayerEndpoint, ServerState};
+use crate::ServerState;
 
 pub struct NPCPlugin;
 impl Plugin for NPCPlugin {
@@ -19,16 +15,10 @@ impl Plugin for NPCPlugin {
         app.add_event::<SpawnUnit>()
             .add_event::<AIFinishAttack>()
             .add_systems(
-                Update,
+                FixedUpdate,
                 (on_ai_tick, apply_npc_movement_intents, on_unit_spawn, on_ai_finish_attack)
                     //.run_if(on_timer(Duration::from_millis(10)))
                     .run_if(in_state(ServerState::Running)),
-            )
-            .add_systems(
-                Update,
-                (send_networked_npc_move)
-                    .run_if(in_state(ServerState::Running))
-                    .run_if(on_timer(Duration::from_millis(50))),
             );
     }
 }
@@ -37,8 +27,6 @@ fn on_unit_spawn(
     mut spawns: EventReader<SpawnUnit>,
     mut commands: Commands,
     //players: Query<(Entity, &Transform, &NetEntId, &ConnectedPlayerName)>,
-    sr: Res<ServerResources<EventToServer>>,
-    clients: Query<&PlayerEndpoint, With<AnyUnit>>,
 ) {
     for spawn in spawns.read() {
         let mut base = commands.spawn((
@@ -46,6 +34,7 @@ fn on_unit_spawn(
             MovementIntention(Vec2::ZERO),
             AttackIntention::None,
             spawn.data.ent_id,
+            spawn.data.faction,
             spawn.data.health,
             spawn.data.transform,
         ));
@@ -61,45 +50,51 @@ fn on_unit_spawn(
                 base.insert((npc_type.clone(), npc_type.get_ai_component()));
             }
         };
-
-        let event = EventToClient::SpawnUnit(SpawnUnit {
-            data: spawn.data.clone(),
-        });
-        for endpoint in &clients {
-            send_event_to_server(&sr.handler, endpoint.0, &event);
-        }
+        // Clients nearby find out about it from `interest::update_interest`
     }
 }
 // Every unit has the same hitbox size for now
-const HITBOX_SIZE: f32 = 2.0;
+pub(crate) const HITBOX_SIZE: f32 = 2.0;
 const HITBOX_SIZE_SQ: f32 = HITBOX_SIZE * HITBOX_SIZE;
 
 #[derive(Event, Debug)]
 struct AIFinishAttack(DoCast);
 
 fn on_ai_tick(
-    mut ai_units: Query<(&NetEntId, &mut Transform, &mut MovementIntention, &mut AttackIntention, &AIType), Without<Controlled>>,
+    mut ai_units: Query<(&NetEntId, &mut Transform, &mut MovementIntention, &mut AttackIntention, &AIType, &Faction), Without<Controlled>>,
     mut ai_unit_finished_attack: EventWriter<AIFinishAttack>,
-    non_ai: Query<(&Transform, &MovementIntention), With<Controlled>>,
+    non_ai: Query<(&NetEntId, &Transform, &Faction), With<Controlled>>,
+    rules: Res<FactionRules>,
     time: Res<Time>,
 ) {
-    let all_player_positions: Vec<(&Transform, &MovementIntention)> = non_ai.iter().collect();
-    for (ne_id, mut unit_tfm, mut unit_mi, mut unit_atk, ai_type) in &mut ai_units {
+    // Anyone could be someone's enemy, including other npcs
+    let all_unit_positions: Vec<(NetEntId, Vec3, Faction)> = ai_units
+        .iter()
+        .map(|(id, tfm, .., faction)| (*id, tfm.translation, *faction))
+        .chain(non_ai.iter().map(|(id, tfm, faction)| (*id, tfm.translation, *faction)))
+        .collect();
+    for (ne_id, mut unit_tfm, mut unit_mi, mut unit_atk, ai_type, faction) in &mut ai_units {
         match ai_type {
             AIType::None => {}
             AIType::WalkToNearestPlayer => {
-                let closest = all_player_positions.iter().reduce(|acc, x| {
-                    let dist_old = unit_tfm.translation.distance(acc.0.translation);
-                    let dist_new = unit_tfm.translation.distance(x.0.translation);
-                    if dist_old < dist_new {
-                        acc
-                    } else {
-                        x
-                    }
-                });
+                let closest = all_unit_positions
+                    .iter()
+                    .filter(|(id, _, other)| {
+                        id != ne_id && rules.relation(*faction, *other) == Relation::Hostile
+                    })
+                    .map(|(_, position, _)| position)
+                    .reduce(|acc, x| {
+                        let dist_old = unit_tfm.translation.distance(*acc);
+                        let dist_new = unit_tfm.translation.distance(*x);
+                        if dist_old < dist_new {
+                            acc
+                        } else {
+                            x
+                        }
+                    });
 
                 if let Some(closest) = closest {
-                    let target = closest.0.translation.xz();
+                    let target = closest.xz();
                     let our_pos = unit_tfm.translation.xz();
 
                     let dir = target - our_pos;
@@ -158,14 +153,15 @@ fn on_ai_finish_attack(
 
 
 fn apply_npc_movement_intents(
-    mut npcs: Query<(&mut Transform, &mut MovementIntention, &AttackIntention), (With<AIType>, Without<Controlled>)>,
+    mut npcs: Query<(&mut Transform, &mut MovementIntention, &AttackIntention, Option<&StatusEffects>), (With<AIType>, Without<Controlled>)>,
     non_ai: Query<(&Transform, &MovementIntention), With<Controlled>>,
     time: Res<Time>,
 ) {
     // Apply all the movement
-    for (mut ply_tfm, ply_intent, attack_intent) in &mut npcs {
+    for (mut ply_tfm, ply_intent, attack_intent, effects) in &mut npcs {
+        let speed = PLAYER_SPEED * effects.map_or(1.0, StatusEffects::speed_multiplier);
         let delta_target =
-            Vec3::new(ply_intent.0.x, 0.0, ply_intent.0.y) * 25.0 * time.delta_seconds();
+            Vec3::new(ply_intent.0.x, 0.0, ply_intent.0.y) * speed * time.delta_seconds();
         ply_tfm.translation += delta_target;
         if let AttackIntention::AutoAttack(timer) = attack_intent {
             // TODO path taken by unit is equal to
@@ -182,7 +178,7 @@ fn apply_npc_movement_intents(
 
         // First, we need to collection all the positions of all units so we can check.
         let mut all_npc_positions = Vec::with_capacity(npcs.iter().len());
-        for (ply_tfm, _, _) in &npcs {
+        for (ply_tfm, ..) in &npcs {
             all_npc_positions.push(ply_tfm.translation);
         }
         let all_player_positions: Vec<_> = non_ai.iter().map(|x| x.0.translation).collect();
@@ -193,7 +189,7 @@ fn apply_npc_movement_intents(
         // Now, check for all other collisions
         // TODO: This is O(n^3). If len npcs > 1000, maybe log a warning that we need to rewrite
         // this?
-        for (mut ply_tfm, mut ply_intent, _) in &mut npcs {
+        for (mut ply_tfm, mut ply_intent, ..) in &mut npcs {
             let new_pos = ply_tfm.translation;
             for &other_unit in all_positions() {
 
@@ -226,41 +222,3 @@ fn apply_npc_movement_intents(
     }
     debug!("Too many collisions this frame");
 }
-
-fn send_networked_npc_move(
-    npcs: Query<
-        (&Transform, &MovementIntention, &AttackIntention, &NetEntId),
-        (
-            With<AIType>,
-            Or<(Changed<Transform>, Changed<MovementIntention>, Changed<AttackIntention>)>,
-        ),
-    >,
-    clients: Query<&PlayerEndpoint, With<ConnectedPlayerName>>,
-    sr: Res<ServerResources<EventToServer>>,
-) {
-    let mut all_events = vec![];
-    for (&movement, mi, attack_intent, &id) in &npcs {
-        all_events.extend([
-            EventToClient::SomeoneMoved(SomeoneMoved {
-                id,
-                movement: shared::event::server::ChangeMovement::SetTransform(movement),
-            }),
-            EventToClient::SomeoneMoved(SomeoneMoved {
-                id,
-                movement: shared::event::server::ChangeMovement::Move2d(mi.0),
-            }),
-            EventToClient::SomeoneMoved(SomeoneMoved {
-                id,
-                movement: shared::event::server::ChangeMovement::AttackIntent(attack_intent.clone()),
-            }),
-        ]);
-    }
-
-    if !all_events.is_empty() {
-        for event_list in all_events.chunks(250) {
-            for endpoint in &clients {
-                send_event_to_server_batch(&sr.handler, endpoint.0, event_list);
-            }
-        }
-    }
-}
@@ -0,0 +1,81 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+use bevy_time::common_conditions::on_timer;
+use shared::{
+    event::{server::SnapshotAck, NetEntId, ERFE},
+    netlib::{send_event_to_server, EventToClient, EventToServer, ServerResources},
+    replication::{Replicator, UnitState},
+    tick::ServerTick,
+    unit::{AttackIntention, MovementIntention},
+    AnyUnit,
+};
+
+use crate::{interest::Interest, ConnectedPlayerName, PlayerEndpoint, ServerState};
+
+pub struct ReplicationPlugin;
+
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            on_snapshot_ack.run_if(in_state(ServerState::Running)),
+        )
+        .add_systems(
+            Update,
+            send_world_snapshots
+                .run_if(in_state(ServerState::Running))
+                .run_if(on_timer(Duration::from_millis(50))),
+        );
+    }
+}
+
+fn on_snapshot_ack(
+    mut acks: ERFE<SnapshotAck>,
+    mut clients: Query<(&PlayerEndpoint, &mut Replicator)>,
+) {
+    for ack in acks.read() {
+        for (endpoint, mut replicator) in &mut clients {
+            if endpoint.0 == ack.endpoint {
+                replicator.ack(ack.event.id);
+            }
+        }
+    }
+}
+
+fn send_world_snapshots(
+    units: Query<
+        (
+            &NetEntId,
+            &Transform,
+            &MovementIntention,
+            Option<&AttackIntention>,
+        ),
+        With<AnyUnit>,
+    >,
+    mut clients: Query<
+        (&PlayerEndpoint, &NetEntId, &Interest, &mut Replicator),
+        With<ConnectedPlayerName>,
+    >,
+    sr: Res<ServerResources<EventToServer>>,
+    tick: Res<ServerTick>,
+) {
+    let world: HashMap<_, _> = units
+        .iter()
+        .map(|(id, tfm, intent, attack)| (*id, UnitState::new(tfm, intent, attack)))
+        .collect();
+
+    for (endpoint, client_id, interest, mut replicator) in &mut clients {
+        // Clients predict their own movement, see `YourMovementResult`
+        let their_world = world
+            .iter()
+            .filter(|(id, _)| *id != client_id && interest.sees(id))
+            .map(|(id, state)| (*id, *state))
+            .collect();
+
+        if let Some(snapshot) = replicator.snapshot(tick.0, their_world) {
+            let event = EventToClient::WorldSnapshot(snapshot);
+            send_event_to_server(&sr.handler, endpoint.0, &event);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::{collections::HashSet, time::Duration};
+
+use bevy::prelude::*;
+use shared::{
+    event::{
+        client::WorldData, server::ConnectRequest, spells::NPC, NetEntId, UnitData, UnitType, ERFE,
+        PROTOCOL_VERSION,
+    },
+    faction::{Faction, FactionRules},
+    netlib::{send_event_to_server, EventToClient, EventToServer, ServerResources},
+    replication::Replicator,
+    skills::Skills,
+    stats::Health,
+    status_effects::StatusEffects,
+    unit::MovementIntention,
+    AnyUnit, Config,
+};
+
+use crate::{
+    interest::Interest, new_heartbeat, on_player_connect, ConnectedPlayerName, EndpointToNetId,
+    HeartbeatList, InputState, PlayerDisconnect, PlayerEndpoint, ServerState,
+};
+
+/// How long a player who lost connection has to come back before their unit is removed
+const SESSION_GRACE: Duration = Duration::from_secs(30);
+
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayerTimedOut>().add_systems(
+            FixedUpdate,
+            (
+                on_player_timeout,
+                on_resume,
+                resend_world_data.before(on_player_connect),
+                expire_sessions,
+            )
+                .run_if(in_state(ServerState::Running)),
+        );
+    }
+}
+
+/// Lets a client that lost connection get their unit back, see [ConnectRequest::resume]
+#[derive(Component, Debug)]
+pub struct Session {
+    pub token: u64,
+}
+
+/// The player lost connection. Their unit stays in the world until this runs out.
+#[derive(Component, Debug)]
+struct AwaitingReconnect(Timer);
+
+/// The player stopped sending heartbeats
+#[derive(Event, Debug)]
+pub struct PlayerTimedOut {
+    pub ent: NetEntId,
+}
+
+fn on_player_timeout(
+    mut timeouts: EventReader<PlayerTimedOut>,
+    mut players: Query<(Entity, &NetEntId, &PlayerEndpoint, &mut MovementIntention)>,
+    mut heartbeat_mapping: ResMut<HeartbeatList>,
+    mut endpoint_mapping: ResMut<EndpointToNetId>,
+    sr: Res<ServerResources<EventToServer>>,
+    mut commands: Commands,
+) {
+    for timeout in timeouts.read() {
+        heartbeat_mapping.heartbeats.remove(&timeout.ent);
+
+        for (ent, id, endpoint, mut intent) in &mut players {
+            if *id != timeout.ent {
+                continue;
+            }
+
+            info!(?id, "Keeping their unit around in case they come back");
+            // Don't leave them running off into the distance
+            intent.0 = Vec2::ZERO;
+            endpoint_mapping.map.remove(&endpoint.0);
+            sr.handler.forget(endpoint.0);
+            commands
+                .entity(ent)
+                .remove::<(PlayerEndpoint, Interest, Replicator, InputState)>()
+                .insert(AwaitingReconnect(Timer::new(
+                    SESSION_GRACE,
+                    TimerMode::Once,
+                )));
+        }
+    }
+}
+
+fn on_resume(
+    mut requests: ERFE<ConnectRequest>,
+    sessions: Query<(Entity, &Session, &NetEntId, Option<&PlayerEndpoint>)>,
+    players: Query<(
+        &ConnectedPlayerName,
+        &Transform,
+        &Health,
+        &Faction,
+        Option<&StatusEffects>,
+    )>,
+    mut heartbeat_mapping: ResMut<HeartbeatList>,
+    mut endpoint_mapping: ResMut<EndpointToNetId>,
+    sr: Res<ServerResources<EventToServer>>,
+    config: Res<Config>,
+    skills: Res<Skills>,
+    rules: Res<FactionRules>,
+    mut commands: Commands,
+) {
+    for request in requests.read() {
+        let Some(token) = request.event.resume else {
+            continue;
+        };
+        // `on_player_connect` rejects them
+        if request.event.protocol_version != PROTOCOL_VERSION {
+            continue;
+        }
+        let Some((ent, _, &id, old_endpoint)) = sessions
+            .iter()
+            .find(|(_, session, ..)| session.token == token)
+        else {
+            continue;
+        };
+        let Ok((ConnectedPlayerName { name }, &transform, &health, &faction, effects)) =
+            players.get(ent)
+        else {
+            continue;
+        };
+
+        if let Some(old_endpoint) = old_endpoint {
+            if old_endpoint.0 == request.endpoint {
+                // Answered by `resend_world_data`
+                continue;
+            }
+            // They noticed the connection was gone before we did
+            endpoint_mapping.map.remove(&old_endpoint.0);
+            sr.handler.forget(old_endpoint.0);
+        }
+
+        info!(?name, ?id, "Player reconnected");
+
+        // Everything around them gets sent by `interest::update_interest`
+        commands.entity(ent).remove::<AwaitingReconnect>().insert((
+            PlayerEndpoint(request.endpoint),
+            InputState::default(),
+            Replicator::default(),
+            Interest::new(
+                transform.translation,
+                config.interest_radius(),
+                HashSet::from([id]),
+            ),
+        ));
+        heartbeat_mapping.heartbeats.insert(id, new_heartbeat());
+        endpoint_mapping.map.insert(request.endpoint, id);
+        sr.handler.mark_connected(request.endpoint);
+
+        let event = EventToClient::WorldData(WorldData {
+            your_unit_id: id,
+            unit_data: vec![UnitData {
+                unit: UnitType::Player { name: name.clone() },
+                ent_id: id,
+                faction,
+                health,
+                effects: effects.cloned().unwrap_or_default(),
+                transform,
+            }],
+            session: token,
+            skills_checksum: skills.checksum(),
+            faction_rules: *rules,
+        });
+        send_event_to_server(&sr.handler, request.endpoint, &event);
+    }
+}
+
+/// Clients resend their [ConnectRequest] until the [WorldData] gets to them, so answer the ones
+/// we already let in again, instead of logging them in twice. They get everything they can see
+/// now, in case the first answer was lost.
+fn resend_world_data(
+    mut requests: ERFE<ConnectRequest>,
+    endpoint_mapping: Res<EndpointToNetId>,
+    players: Query<(&NetEntId, &Session, &Interest)>,
+    units: Query<(Entity, &NetEntId, &Transform, &Health, &Faction), With<AnyUnit>>,
+    effects: Query<&StatusEffects>,
+    names: Query<&ConnectedPlayerName>,
+    npcs: Query<&NPC>,
+    sr: Res<ServerResources<EventToServer>>,
+    skills: Res<Skills>,
+    rules: Res<FactionRules>,
+) {
+    for request in requests.read() {
+        let Some(id) = endpoint_mapping.map.get(&request.endpoint) else {
+            continue;
+        };
+        let Some((_, session, interest)) = players.iter().find(|(x, ..)| *x == id) else {
+            continue;
+        };
+
+        let mut unit_data: Vec<_> = units
+            .iter()
+            .filter(|(_, x, ..)| interest.sees(x))
+            .filter_map(|(ent, &ent_id, &transform, &health, &faction)| {
+                let unit = if let Ok(ConnectedPlayerName { name }) = names.get(ent) {
+                    UnitType::Player { name: name.clone() }
+                } else if let Ok(npc_type) = npcs.get(ent) {
+                    UnitType::NPC {
+                        npc_type: npc_type.clone(),
+                    }
+                } else {
+                    return None;
+                };
+                Some(UnitData {
+                    unit,
+                    ent_id,
+                    faction,
+                    health,
+                    effects: effects.get(ent).cloned().unwrap_or_default(),
+                    transform,
+                })
+            })
+            .collect();
+        // Their own unit goes first
+        unit_data.sort_by_key(|x| x.ent_id != *id);
+
+        debug!(?id, "Sending the world data again");
+        let event = EventToClient::WorldData(WorldData {
+            your_unit_id: *id,
+            unit_data,
+            session: session.token,
+            skills_checksum: skills.checksum(),
+            faction_rules: *rules,
+        });
+        send_event_to_server(&sr.handler, request.endpoint, &event);
+    }
+}
+
+fn expire_sessions(
+    mut waiting: Query<(&NetEntId, &mut AwaitingReconnect)>,
+    time: Res<Time>,
+    mut on_disconnect: EventWriter<PlayerDisconnect>,
+) {
+    for (id, mut timer) in &mut waiting {
+        if timer.0.tick(time.delta()).just_finished() {
+            info!(?id, "They did not come back in time");
+            on_disconnect.send(PlayerDisconnect {
+                ent: *id,
+                reason: None,
+            });
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Giving units [StatusEffects] and ticking them. Clients only ever find out about them from us.
+
+use bevy::{prelude::*, utils::HashMap};
+use shared::{
+    animations::DoDamage,
+    event::{
+        client::SomeoneUpdateComponent,
+        spells::{UpdateSharedComponent, NPC},
+        NetEntId,
+    },
+    netlib::{send_event_to_server, EventToClient, EventToServer, ServerResources},
+    stats::Health,
+    status_effects::{ApplyEffect, StatusEffects},
+    AnyUnit,
+};
+
+use crate::{interest::Interest, PlayerEndpoint, ServerState};
+
+pub struct StatusEffectsPlugin;
+
+impl Plugin for StatusEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ApplyEffect>().add_systems(
+            FixedUpdate,
+            (apply_effects, tick_effects)
+                .chain()
+                .run_if(in_state(ServerState::Running)),
+        );
+    }
+}
+
+/// Tell everyone who can see `id` what it has now
+fn send_update(
+    sr: &ServerResources<EventToServer>,
+    clients: &Query<(&PlayerEndpoint, &Interest)>,
+    id: NetEntId,
+    update: UpdateSharedComponent,
+) {
+    let event = EventToClient::SomeoneUpdateComponent(SomeoneUpdateComponent { id, update });
+    for (c_net_client, interest) in clients {
+        if interest.sees(&id) {
+            send_event_to_server(&sr.handler, c_net_client.0, &event);
+        }
+    }
+}
+
+fn apply_effects(
+    mut applied: EventReader<ApplyEffect>,
+    units: Query<(Entity, &NetEntId, Option<&StatusEffects>), With<AnyUnit>>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
+    sr: Res<ServerResources<EventToServer>>,
+    mut commands: Commands,
+) {
+    // Units getting their first effect only have the component once commands are applied, so
+    // collect everything they get this frame first
+    let mut changed: HashMap<Entity, (NetEntId, StatusEffects)> = HashMap::new();
+    for ApplyEffect { target, effect } in applied.read() {
+        let Some((ent, id, effects)) = units.iter().find(|(_, id, _)| *id == target) else {
+            continue;
+        };
+        debug!(?id, ?effect, "Unit got an effect");
+
+        let (_, effects) = changed
+            .entry(ent)
+            .or_insert_with(|| (*id, effects.cloned().unwrap_or_default()));
+        effects.apply(effect);
+    }
+
+    for (ent, (id, effects)) in changed {
+        send_update(
+            &sr,
+            &clients,
+            id,
+            UpdateSharedComponent::StatusEffects(effects.clone()),
+        );
+        commands.entity(ent).insert(effects);
+    }
+}
+
+fn tick_effects(
+    mut units: Query<(&NetEntId, &mut StatusEffects, &mut Health, Option<&NPC>), With<AnyUnit>>,
+    clients: Query<(&PlayerEndpoint, &Interest)>,
+    sr: Res<ServerResources<EventToServer>>,
+    time: Res<Time>,
+    mut damage_events: EventWriter<DoDamage>,
+) {
+    for (&id, mut effects, mut hp, npc) in &mut units {
+        let ticked = effects.tick(time.delta());
+
+        if ticked.damage > 0.0 {
+            damage_events.send(DoDamage(id, ticked.damage));
+        }
+        if ticked.heal > 0.0 {
+            // Never past full health, but don't take away anything they got some other way
+            let max = npc.map_or(Health::default(), NPC::get_base_health).0;
+            let healed = hp.0.saturating_add(ticked.heal as u32).min(max.max(hp.0));
+            if healed != hp.0 {
+                hp.0 = healed;
+                send_update(&sr, &clients, id, UpdateSharedComponent::Health(*hp));
+            }
+        }
+        if ticked.expired {
+            send_update(
+                &sr,
+                &clients,
+                id,
+                UpdateSharedComponent::StatusEffects(effects.clone()),
+            );
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Limits on how much clients can send us, and checks that what they send makes sense.
+
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+use bevy_time::common_conditions::on_timer;
+use message_io::network::Endpoint;
+use shared::{
+    event::{server::Cast, NetEntId},
+    faction::Relation,
+    movement::in_world,
+    netlib::{EventToServer, ServerResources},
+    skills::Skills,
+    tick::TICK_HZ,
+};
+
+use crate::{npc::HITBOX_SIZE, EndpointToNetId, PlayerDisconnect, ServerState};
+
+/// How far a client can think they are from where we have them, since they predict their own
+/// movement ahead of us.
+pub const POSITION_TOLERANCE: f32 = 3.0;
+
+/// How far back towards the caster a blocked teleport looks for somewhere free at a time
+const LANDING_STEP: f32 = 0.5;
+
+/// Chat messages longer than this are refused, in characters
+pub const MAX_CHAT_LENGTH: usize = 500;
+
+/// Every violation costs a strike, and anyone out of strikes is kicked. A client with a bug can
+/// make the odd mistake, one that is flooding us runs out within a second or two.
+pub const STRIKES: RateLimit = RateLimit::new(20.0, 0.5);
+
+/// How often buckets nobody has used in a while are thrown away
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct ValidationPlugin;
+
+impl Plugin for ValidationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RateLimiter>()
+            .add_event::<Violation>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    rate_limit_events.before(shared::event::server::drain_events),
+                    punish_violations,
+                )
+                    .run_if(in_state(ServerState::Running)),
+            )
+            .add_systems(
+                Update,
+                forget_idle_buckets.run_if(on_timer(CLEANUP_INTERVAL)),
+            );
+    }
+}
+
+/// How many events a client can send in one go, and how quickly they can send more after that
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: f32,
+    pub per_second: f32,
+}
+
+impl RateLimit {
+    pub const fn new(burst: f32, per_second: f32) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+/// Each type of event gets its own budget, generous enough that a client sending a backlog all at
+/// once after a lag spike stays under it.
+pub fn rate_limit(event: &EventToServer) -> RateLimit {
+    match event {
+        // Resent every second until we answer
+        EventToServer::ConnectRequest(_) => RateLimit::new(5.0, 2.0),
+        // Every 200 ms
+        EventToServer::Heartbeat(_) => RateLimit::new(20.0, 10.0),
+        // Inputs go out every 25 ms
+        EventToServer::ChangeMovement(_) => RateLimit::new(200.0, 100.0),
+        // One for every snapshot we send
+        EventToServer::SnapshotAck(_) => RateLimit::new(2.0 * TICK_HZ as f32, 2.0 * TICK_HZ as f32),
+        EventToServer::Cast(_) => RateLimit::new(10.0, 5.0),
+        EventToServer::SendChat(_) => RateLimit::new(5.0, 1.0),
+        EventToServer::Disconnect(_) => RateLimit::new(2.0, 1.0),
+        // Something new, give it a real limit once we know how often it gets sent
+        _ => RateLimit::new(10.0, 5.0),
+    }
+}
+
+/// Starts full, every event takes a token, and tokens come back at a steady rate. Times are in
+/// seconds, from [Time::elapsed_seconds_f64].
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f32,
+    updated: f64,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit, now: f64) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: f64) {
+        let elapsed = (now - self.updated).max(0.0) as f32;
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst);
+        self.updated = now;
+    }
+
+    /// Take a token if there is one
+    pub fn take(&mut self, now: f64) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Back to how it started, so there is no point keeping it around
+    pub fn is_full(&mut self, now: f64) -> bool {
+        self.refill(now);
+        self.tokens >= self.limit.burst
+    }
+}
+
+/// A client sent something they shouldn't have. Sent by whatever system noticed, see
+/// [punish_violations].
+#[derive(Event, Debug)]
+pub struct Violation {
+    pub endpoint: Endpoint,
+    pub reason: String,
+}
+
+#[derive(Resource, Default)]
+struct RateLimiter {
+    /// By endpoint and [EventToServer::name]
+    events: HashMap<(Endpoint, &'static str), TokenBucket>,
+    strikes: HashMap<Endpoint, TokenBucket>,
+}
+
+/// Throw away events over the [rate_limit] before `drain_events` ever sees them
+fn rate_limit_events(
+    sr: Res<ServerResources<EventToServer>>,
+    time: Res<Time>,
+    mut limiter: ResMut<RateLimiter>,
+    mut violations: EventWriter<Violation>,
+) {
+    let now = time.elapsed_seconds_f64();
+    // One violation for each kind of event someone went over on, however many they sent
+    let mut dropped: HashMap<(Endpoint, &'static str), usize> = HashMap::new();
+
+    sr.event_list.lock().unwrap().retain(|x| {
+        let key = (x.endpoint, x.event.name());
+        let bucket = limiter
+            .events
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(rate_limit(&x.event), now));
+        if bucket.take(now) {
+            return true;
+        }
+        *dropped.entry(key).or_default() += 1;
+        false
+    });
+
+    for ((endpoint, name), count) in dropped {
+        violations.send(Violation {
+            endpoint,
+            reason: format!("Sent {count} {name} over the limit"),
+        });
+    }
+}
+
+fn punish_violations(
+    mut violations: EventReader<Violation>,
+    endpoint_mapping: Res<EndpointToNetId>,
+    time: Res<Time>,
+    mut limiter: ResMut<RateLimiter>,
+    mut kick: EventWriter<PlayerDisconnect>,
+) {
+    let now = time.elapsed_seconds_f64();
+    for Violation { endpoint, reason } in violations.read() {
+        warn!(?endpoint, reason, "Client broke the rules");
+
+        let strikes = limiter
+            .strikes
+            .entry(*endpoint)
+            .or_insert_with(|| TokenBucket::new(STRIKES, now));
+        if strikes.take(now) {
+            continue;
+        }
+
+        // Start over, in case they come back
+        limiter.strikes.remove(endpoint);
+        match endpoint_mapping.map.get(endpoint) {
+            Some(ent) => {
+                warn!(?endpoint, ?ent, "Kicking a client for breaking the rules");
+                kick.send(PlayerDisconnect {
+                    ent: *ent,
+                    reason: Some(format!("Kicked for breaking the rules: {reason}")),
+                });
+            }
+            // All we can do is keep dropping what they send
+            None => warn!(
+                ?endpoint,
+                "Client that never connected keeps breaking the rules"
+            ),
+        }
+    }
+}
+
+fn forget_idle_buckets(time: Res<Time>, mut limiter: ResMut<RateLimiter>) {
+    let now = time.elapsed_seconds_f64();
+    limiter.events.retain(|_, x| !x.is_full(now));
+    limiter.strikes.retain(|_, x| !x.is_full(now));
+}
+
+fn is_near(claimed: Vec3, actual: Vec3) -> bool {
+    claimed.is_finite() && claimed.distance(actual) <= POSITION_TOLERANCE
+}
+
+/// Check that a cast makes sense for a caster standing at `caster`. `find_unit` finds where
+/// another unit is and how the caster gets along with them, if it exists.
+pub fn validate_cast(
+    skills: &Skills,
+    cast: &Cast,
+    caster: Vec3,
+    find_unit: impl Fn(NetEntId) -> Option<(Vec3, Relation)>,
+) -> Result<(), String> {
+    let range = skills.get(cast).range;
+    match cast {
+        Cast::Teleport(target) => {
+            if !target.is_finite() || target.distance(caster) > range + POSITION_TOLERANCE {
+                return Err(format!("Teleport to {target} is out of range"));
+            }
+            if !in_world(*target) {
+                return Err(format!("Teleport to {target} is outside the world"));
+            }
+        }
+        Cast::Shoot(shot) => {
+            if !is_near(shot.shot_from, caster) {
+                return Err(format!("Shot from {} is too far away", shot.shot_from));
+            }
+            // The direction would be NaN
+            if !shot.target.is_finite() || shot.target == shot.shot_from {
+                return Err(format!("Shot at {} makes no sense", shot.target));
+            }
+        }
+        Cast::ShootTargeted(from, target) => {
+            if !is_near(*from, caster) {
+                return Err(format!("Targeted shot from {from} is too far away"));
+            }
+            let Some((position, relation)) = find_unit(*target) else {
+                return Err(format!("Targeted shot at {target:?}, who doesn't exist"));
+            };
+            if position.distance(caster) > range {
+                return Err(format!("Targeted shot at {target:?} is out of range"));
+            }
+            if !relation.can_hurt() {
+                return Err(format!("Targeted shot at {target:?}, who is an ally"));
+            }
+        }
+        Cast::Aoe(center) => {
+            if !is_near(*center, caster) {
+                return Err(format!("Aoe at {center} is too far away"));
+            }
+        }
+        Cast::Melee | Cast::Buff => {}
+    }
+    Ok(())
+}
+
+/// Where a teleport from `from` to `target` actually lands. Nobody can end up inside another unit,
+/// so it stops short of anyone standing in `others`, or stays at `from` if there is no room at all.
+pub fn landing_spot(from: Vec3, target: Vec3, others: &[Vec3]) -> Vec3 {
+    let is_free = |x: Vec3| {
+        others
+            .iter()
+            .all(|other| other.xz().distance(x.xz()) >= HITBOX_SIZE)
+    };
+
+    let steps = ((from.distance(target) / LANDING_STEP).ceil() as usize).max(1);
+    (0..=steps)
+        .map(|i| target.lerp(from, i as f32 / steps as f32))
+        .find(|x| is_free(*x))
+        .unwrap_or(from)
+}
@@ -0,0 +1,37 @@
+use server::accounts::Accounts;
+
+#[cfg(unix)]
+#[test]
+fn only_the_server_can_read_the_accounts() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("private_accounts.yaml");
+    let _ = std::fs::remove_file(&path);
+    Accounts::load(&path).save();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn nobody_can_take_a_guest_name() {
+    let accounts = Accounts::default();
+    for name in ["Player #12", "player #9999"] {
+        let login = accounts.login(Some(name), Some("hunter2"), std::iter::empty());
+        assert!(login.is_err(), "{name} could be registered");
+        let login = accounts.login(Some(name), None, std::iter::empty());
+        assert!(login.is_err(), "{name} could be played as");
+    }
+}
+
+#[test]
+fn guests_are_turned_away_once_every_guest_name_is_taken() {
+    let accounts = Accounts::default();
+    let online: Vec<String> = (1..10000).map(|x| format!("Player #{x}")).collect();
+
+    let login = accounts.login(None, None, online.iter().map(String::as_str));
+    assert!(login.is_err());
+
+    let login = accounts.login(None, None, std::iter::empty());
+    assert!(login.unwrap().join().unwrap().name.starts_with("Player #"));
+}
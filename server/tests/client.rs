@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput, NativeKey},
+        ButtonState,
+    },
+    prelude::*,
+};
+use client::{player::Player, states::GameState};
+use server::{ConnectedPlayerName, PlayerEndpoint};
+use shared::{
+    event::{server::Cast, NetEntId},
+    movement::PendingInputs,
+    netlib::EventToClient,
+    skills::Skills,
+    status_effects::{ApplyEffect, EffectInfo, EffectKind},
+    tick::TICK_HZ,
+};
+
+mod harness;
+
+use harness::{Harness, TestClient};
+
+const SKILLS: &str = include_str!("../../shared/assets/skills.yaml");
+
+/// Press or let go of a key, the way the window would tell the client about it
+fn key(client: &mut TestClient, key_code: KeyCode, state: ButtonState) {
+    client.app.world.send_event(KeyboardInput {
+        key_code,
+        logical_key: Key::Unidentified(NativeKey::Unidentified),
+        state,
+        window: Entity::PLACEHOLDER,
+    });
+}
+
+fn player(client: &TestClient) -> EntityRef<'_> {
+    let mut entities = client.app.world.iter_entities();
+    entities.find(|x| x.contains::<Player>()).unwrap()
+}
+
+fn player_position(client: &TestClient) -> Vec2 {
+    player(client).get::<Transform>().unwrap().translation.xz()
+}
+
+fn told(client: &TestClient, notification: &str) -> bool {
+    client.notifications().iter().any(|x| x == notification)
+}
+
+#[test]
+fn walking_is_predicted_and_ends_up_where_the_server_says() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let a_id = h.clients[a].unit_id();
+    let start = player_position(&h.clients[a]);
+
+    key(&mut h.clients[a], KeyCode::KeyW, ButtonState::Pressed);
+    h.step();
+    // We moved before the server heard about it
+    assert_ne!(player_position(&h.clients[a]), start);
+    let server = h.server_unit::<Transform>(a_id).unwrap();
+    assert_eq!(server.translation.xz(), start);
+
+    h.run_for(Duration::from_secs(1));
+    key(&mut h.clients[a], KeyCode::KeyW, ButtonState::Released);
+    h.run_until("the server has caught up", |h| {
+        let pending = player(&h.clients[a]).get::<PendingInputs>().unwrap();
+        pending.is_empty()
+    });
+    h.run_for(Duration::from_millis(100));
+
+    let ours = player_position(&h.clients[a]);
+    let server = h.server_unit::<Transform>(a_id).unwrap().translation.xz();
+    assert!(ours.distance(start) > 1.0, "Only walked to {ours}");
+    assert!(ours.distance(server) < 0.01, "{ours} is not {server}");
+}
+
+#[test]
+fn a_refused_cast_is_undone_and_the_player_is_told_why() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let a_id = h.clients[a].unit_id();
+
+    h.server.world.send_event(ApplyEffect {
+        target: a_id,
+        effect: EffectInfo {
+            kind: EffectKind::Stun,
+            magnitude: 0.0,
+            duration: Duration::from_secs(10),
+        },
+    });
+    h.run_for(Duration::from_millis(100));
+
+    key(&mut h.clients[a], KeyCode::KeyT, ButtonState::Pressed);
+    h.run_until("the cast starts", |h| {
+        player(&h.clients[a]).contains::<Cast>()
+    });
+    key(&mut h.clients[a], KeyCode::KeyT, ButtonState::Released);
+    // Without waiting for the server
+    assert!(!told(&h.clients[a], "You are stunned"));
+
+    h.run_until("the cast is refused", |h| {
+        told(&h.clients[a], "You are stunned")
+    });
+    h.step();
+    assert!(!player(&h.clients[a]).contains::<Cast>());
+}
+
+#[test]
+fn different_skills_send_the_player_back_to_the_menu() {
+    let mut h = Harness::new();
+    let a = h.add_client("A", None, Vec3::ZERO);
+    let rebalanced = SKILLS.replace("damage: 25.0", "damage: 26.0");
+    h.clients[a]
+        .app
+        .insert_resource(Skills::from_yaml(&rebalanced).unwrap());
+
+    h.run_until("they notice", |h| {
+        told(
+            &h.clients[a],
+            "Your skills.yaml is different from the server's",
+        )
+    });
+    assert!(h.clients[a].world_data().is_some());
+    assert_ne!(*h.clients[a].state(), GameState::ClientConnected);
+}
+
+#[test]
+fn losing_the_connection_gets_the_same_unit_back() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let a_id = h.clients[a].unit_id();
+
+    // Only the server runs, until it stops waiting for their heartbeats. After that nothing the
+    // client sends gets an answer, so it has to notice on its own.
+    for frame in 0.. {
+        let mut endpoints = h.server.world.query::<(&NetEntId, &PlayerEndpoint)>();
+        if !endpoints.iter(&h.server.world).any(|(x, _)| *x == a_id) {
+            break;
+        }
+        assert!(
+            frame < 10 * TICK_HZ as usize,
+            "The server is still waiting for A"
+        );
+        h.server.update();
+    }
+
+    h.run_until("they reconnect", |h| told(&h.clients[a], "Reconnected"));
+    h.run_until("they are playing again", |h| {
+        *h.clients[a].state() == GameState::ClientConnected
+    });
+
+    let world_data: Vec<_> = h.clients[a]
+        .received()
+        .iter()
+        .filter_map(|x| match x {
+            EventToClient::WorldData(x) => Some(x),
+            _ => None,
+        })
+        .collect();
+    // Once when they first connected, and again for each request while reconnecting
+    assert!(world_data.len() >= 2);
+    assert!(world_data.iter().all(|x| x.your_unit_id == a_id));
+
+    let players = h
+        .server
+        .world
+        .query::<&ConnectedPlayerName>()
+        .iter(&h.server.world)
+        .count();
+    assert_eq!(players, 1);
+}
@@ -0,0 +1,401 @@
+use std::time::Duration;
+
+use bevy::{app::AppExit, diagnostic::DiagnosticsStore, prelude::*};
+use server::ConnectedPlayerName;
+use shared::{
+    event::{
+        client::{
+            Chat, ConnectRejected, PlayerDisconnected, SomeoneTeleported, SomeoneUpdateComponent,
+            YourCastResult,
+        },
+        server::{Cast, ConnectRequest, Disconnect, SendChat},
+        spells::{ShootingData, UpdateSharedComponent},
+        NetEntId, PROTOCOL_VERSION,
+    },
+    faction::FactionRules,
+    netlib::{stats::EndpointDiagnostics, EventToClient, EventToServer},
+    stats::Health,
+    status_effects::{EffectKind, StatusEffects},
+};
+
+mod harness;
+
+use harness::{Harness, TestClient, ADMIN};
+
+fn world_data_count(client: &TestClient) -> usize {
+    client
+        .received()
+        .iter()
+        .filter(|x| matches!(x, EventToClient::WorldData(_)))
+        .count()
+}
+
+fn saw_health(client: &TestClient, id: NetEntId, health: Health) -> bool {
+    client.received().iter().any(|x| {
+        matches!(
+            x,
+            EventToClient::SomeoneUpdateComponent(SomeoneUpdateComponent {
+                id: x,
+                update: UpdateSharedComponent::Health(hp),
+            }) if *x == id && *hp == health
+        )
+    })
+}
+
+#[test]
+fn players_nearby_are_in_the_world_data() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::new(2.0, 0.0, 0.0));
+
+    let a_id = h.clients[a].unit_id();
+    let world = h.clients[b].world_data().unwrap();
+    assert_eq!(world.unit_data[0].ent_id, h.clients[b].unit_id());
+    assert!(world.unit_data.iter().any(|x| x.ent_id == a_id));
+}
+
+#[test]
+fn a_name_already_playing_is_rejected() {
+    let mut h = Harness::new();
+    h.connect("A", Vec3::ZERO);
+    let b = h.add_client("A", None, Vec3::ZERO);
+
+    h.run_until("the second A is turned away", |h| {
+        h.clients[b]
+            .received()
+            .iter()
+            .any(|x| matches!(x, EventToClient::ConnectRejected(ConnectRejected { .. })))
+    });
+    assert!(h.clients[b].world_data().is_none());
+}
+
+#[test]
+fn asking_to_connect_again_gets_the_same_answer() {
+    let mut h = Harness::new();
+    let named = h.connect("A", Vec3::ZERO);
+    // No name makes them a guest
+    let guest = h.connect("", Vec3::ZERO);
+
+    for (i, name) in [(named, "A"), (guest, "")] {
+        let id = h.clients[i].unit_id();
+        // As if the world data was still on its way when the request was resent
+        h.clients[i].send(&EventToServer::ConnectRequest(ConnectRequest {
+            protocol_version: PROTOCOL_VERSION,
+            name: Some(name.to_string()),
+            my_location: Transform::IDENTITY,
+            resume: None,
+            password: None,
+        }));
+
+        h.run_until("the world data is sent again", |h| {
+            world_data_count(&h.clients[i]) == 2
+        });
+        for x in h.clients[i].received() {
+            match x {
+                EventToClient::WorldData(x) => assert_eq!(x.your_unit_id, id),
+                EventToClient::ConnectRejected(x) => panic!("Rejected: {}", x.reason),
+                _ => {}
+            }
+        }
+    }
+
+    let players = h
+        .server
+        .world
+        .query::<&ConnectedPlayerName>()
+        .iter(&h.server.world)
+        .count();
+    assert_eq!(players, 2);
+}
+
+#[test]
+fn shooting_someone_damages_them_for_everyone() {
+    let mut h = Harness::new();
+    h.server.world.insert_resource(FactionRules { pvp: true });
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::new(2.0, 0.0, 0.0));
+    let b_id = h.clients[b].unit_id();
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Shoot(ShootingData {
+        shot_from: Vec3::ZERO,
+        target: Vec3::new(2.0, 0.0, 0.0),
+    })));
+
+    let damaged = Health(90);
+    h.run_until("the server applies the damage", |h| {
+        h.clients.iter().all(|x| saw_health(x, b_id, damaged))
+    });
+    assert_eq!(h.server_unit::<Health>(b_id), Some(damaged));
+}
+
+#[test]
+fn targeted_shots_fly_into_their_target() {
+    let mut h = Harness::new();
+    h.server.world.insert_resource(FactionRules { pvp: true });
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::new(2.0, 0.0, 2.0));
+    let b_id = h.clients[b].unit_id();
+
+    h.clients[a].send(&EventToServer::Cast(Cast::ShootTargeted(Vec3::ZERO, b_id)));
+
+    let damaged = Health(92);
+    h.run_until("the shot lands", |h| {
+        h.clients.iter().all(|x| saw_health(x, b_id, damaged))
+    });
+    assert_eq!(h.server_unit::<Health>(b_id), Some(damaged));
+}
+
+#[test]
+fn without_pvp_shots_go_through_other_players() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::new(2.0, 0.0, 0.0));
+    let b_id = h.clients[b].unit_id();
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Shoot(ShootingData {
+        shot_from: Vec3::ZERO,
+        target: Vec3::new(2.0, 0.0, 0.0),
+    })));
+    h.run_until("the shot goes off", |h| {
+        h.clients[a].received().iter().any(|x| {
+            matches!(
+                x,
+                EventToClient::YourCastResult(
+                    YourCastResult::OffsetBy(..) | YourCastResult::Ok(..)
+                )
+            )
+        })
+    });
+    h.run_for(Duration::from_secs(2));
+
+    assert_eq!(h.server_unit::<Health>(b_id), Some(Health::default()));
+    assert!(!saw_health(&h.clients[b], b_id, Health(90)));
+}
+
+#[test]
+fn casting_again_during_the_cooldown_is_refused() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Buff));
+    // The cooldown starts at the cast point
+    h.run_for(Duration::from_secs(2));
+    h.clients[a].send(&EventToServer::Cast(Cast::Buff));
+
+    h.run_until("the second cast is refused", |h| {
+        h.clients[a]
+            .received()
+            .iter()
+            .any(|x| matches!(x, EventToClient::YourCastResult(YourCastResult::No(_))))
+    });
+}
+
+#[test]
+fn buffing_makes_allies_hit_harder() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::new(2.0, 0.0, 0.0));
+    let a_id = h.clients[a].unit_id();
+    let b_id = h.clients[b].unit_id();
+
+    h.clients[b].send(&EventToServer::Cast(Cast::Buff));
+    h.run_until("everyone sees A get buffed", |h| {
+        h.clients.iter().all(|client| {
+            client.received().iter().any(|x| {
+                matches!(
+                    x,
+                    EventToClient::SomeoneUpdateComponent(SomeoneUpdateComponent {
+                        id,
+                        update: UpdateSharedComponent::StatusEffects(effects),
+                    }) if *id == a_id && effects.get(EffectKind::DamageUp).is_some()
+                )
+            })
+        })
+    });
+    let effects = h.server_unit::<StatusEffects>(b_id).unwrap();
+    assert!(effects.get(EffectKind::SpeedUp).is_some());
+
+    // Allies can't hurt each other, so fall out before testing it
+    h.server.world.insert_resource(FactionRules { pvp: true });
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Shoot(ShootingData {
+        shot_from: Vec3::ZERO,
+        target: Vec3::new(2.0, 0.0, 0.0),
+    })));
+
+    // 10 damage, times 1.25
+    let damaged = Health(88);
+    h.run_until("the buffed shot lands", |h| {
+        saw_health(&h.clients[b], b_id, damaged)
+    });
+}
+
+/// Where `client` was told `id` teleported to, if they were
+fn saw_teleport(client: &TestClient, id: NetEntId) -> Option<Vec3> {
+    client.received().iter().find_map(|x| match x {
+        EventToClient::SomeoneTeleported(SomeoneTeleported { id: x, transform }) if *x == id => {
+            Some(transform.translation)
+        }
+        _ => None,
+    })
+}
+
+#[test]
+fn teleporting_moves_you_for_everyone() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    h.connect("B", Vec3::new(0.0, 0.0, 10.0));
+    let a_id = h.clients[a].unit_id();
+
+    let target = Vec3::new(10.0, 0.0, 0.0);
+    h.clients[a].send(&EventToServer::Cast(Cast::Teleport(target)));
+    h.run_until("everyone sees A land", |h| {
+        h.clients
+            .iter()
+            .all(|x| saw_teleport(x, a_id) == Some(target))
+    });
+    let position = h.server_unit::<Transform>(a_id).unwrap().translation;
+    assert_eq!(position, target);
+}
+
+#[test]
+fn teleporting_onto_someone_lands_next_to_them() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    // Close enough to spawn that the server lets them start there
+    h.connect("B", Vec3::new(2.5, 0.0, 0.0));
+    let a_id = h.clients[a].unit_id();
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Teleport(Vec3::new(
+        2.5, 0.0, 0.0,
+    ))));
+    h.run_until("A lands", |h| saw_teleport(&h.clients[a], a_id).is_some());
+
+    let landed = saw_teleport(&h.clients[a], a_id).unwrap();
+    assert!(landed.x > 0.0 && landed.x <= 0.5, "A landed at {landed}");
+    assert_eq!(
+        h.server_unit::<Transform>(a_id).unwrap().translation,
+        landed
+    );
+}
+
+#[test]
+fn chat_reaches_everyone() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    h.connect("B", Vec3::ZERO);
+    let a_id = h.clients[a].unit_id();
+
+    h.clients[a].send(&EventToServer::SendChat(SendChat {
+        text: "hello".into(),
+    }));
+
+    h.run_until("everyone gets the message", |h| {
+        h.clients.iter().all(|client| {
+            client.received().iter().any(|x| {
+                matches!(x, EventToClient::Chat(Chat { source: Some(id), text })
+                    if *id == a_id && text == "hello")
+            })
+        })
+    });
+}
+
+#[test]
+fn leaving_tells_everyone_else() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::ZERO);
+    let a_id = h.clients[a].unit_id();
+
+    h.clients[a].send(&EventToServer::Disconnect(Disconnect {
+        reason: "bye".into(),
+    }));
+
+    h.run_until("B hears that A left", |h| {
+        h.clients[b].received().iter().any(|x| {
+            matches!(x, EventToClient::PlayerDisconnected(PlayerDisconnected { id }) if *id == a_id)
+        })
+    });
+    assert_eq!(h.server_unit::<Health>(a_id), None);
+}
+
+#[test]
+fn only_admins_can_kick() {
+    let mut h = Harness::new();
+    let admin = h.connect_as(ADMIN, Some("hunter2"), Vec3::ZERO);
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::ZERO);
+    let b_id = h.clients[b].unit_id();
+
+    h.clients[a].send(&EventToServer::SendChat(SendChat {
+        text: "/kick B".into(),
+    }));
+    h.run_until("A is told they can't kick", |h| {
+        h.clients[a].received().iter().any(|x| {
+            matches!(x, EventToClient::Chat(Chat { source: None, text }) if text == "You are not allowed to kick anyone")
+        })
+    });
+    assert!(h.server_unit::<Health>(b_id).is_some());
+
+    h.clients[admin].send(&EventToServer::SendChat(SendChat {
+        text: "/kick B".into(),
+    }));
+    h.run_until("B is told why they were kicked", |h| {
+        h.clients[b].received().iter().any(
+            |x| matches!(x, EventToClient::Disconnect(x) if x.reason == format!("Kicked by {ADMIN}")),
+        )
+    });
+    assert!(h.clients[admin].received().iter().any(|x| {
+        matches!(x, EventToClient::Chat(Chat { source: None, text }) if text == "Kicked B")
+    }));
+    assert_eq!(h.server_unit::<Health>(b_id), None);
+}
+
+fn endpoints_with_diagnostics(h: &Harness) -> usize {
+    let slots = h
+        .server
+        .world
+        .resource::<EndpointDiagnostics<EventToServer>>();
+    slots.len()
+}
+
+#[test]
+fn players_who_leave_make_room_for_the_next_ones_stats() {
+    let mut h = Harness::new();
+    for name in ["A", "B", "C"] {
+        let i = h.connect(name, Vec3::ZERO);
+        h.run_until("their stats are recorded", |h| {
+            endpoints_with_diagnostics(h) == 1
+        });
+
+        h.clients[i].send(&EventToServer::SendChat(SendChat {
+            text: "/netstats".into(),
+        }));
+        h.run_until("the stats are sent", |h| {
+            h.clients[i].received().iter().any(|x| {
+                matches!(x, EventToClient::Chat(Chat { source: None, text }) if text.starts_with(&format!("{name}: ping")) && !text.contains("up -"))
+            })
+        });
+
+        // Quit the way the game does, which tells the server first
+        h.clients[i].app.world.send_event(AppExit);
+        h.step();
+        h.clients.pop();
+        h.run_until("their stats are dropped", |h| {
+            endpoints_with_diagnostics(h) == 0
+        });
+    }
+
+    // Everyone had the same ones
+    let store = h.server.world.resource::<DiagnosticsStore>();
+    let paths: Vec<_> = store
+        .iter()
+        .map(|x| x.path().as_str())
+        .filter(|x| x.starts_with("net/endpoint/"))
+        .collect();
+    assert!(!paths.is_empty());
+    assert!(
+        paths.iter().all(|x| x.starts_with("net/endpoint/0/")),
+        "{paths:?}"
+    );
+}
@@ -0,0 +1,344 @@
+//! Runs a real server and headless clients in one process, talking over a [MemoryNetwork].
+//!
+//! Every app gets a fixed frame time and nothing is delivered between steps, so the simulation
+//! only moves when a test calls [Harness::step], and always moves the same way. Clients run the
+//! same plugins as the game, minus the window, sound and rendering, and connect on their own.
+
+use std::{
+    sync::Once,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    audio::AudioPlugin,
+    gilrs::GilrsPlugin,
+    log::LogPlugin,
+    prelude::*,
+    render::{settings::WgpuSettings, RenderPlugin},
+    time::TimeUpdateStrategy,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use client::{
+    cameras::{self, notifications::Notification},
+    cli::CliArgs,
+    menu, network,
+    player::Player,
+    skills,
+    states::{self, GameState},
+};
+use server::{accounts::Accounts, ServerPlugin, ServerState};
+use shared::{
+    event::{client::WorldData, NetEntId},
+    netlib::{
+        memory::MemoryNetwork, rpc::RequestId, secure::ServerIdentity, send_event_to_server,
+        EventToClient, EventToServer, MainServerEndpoint, NetworkListenAddrs, ServerResources,
+    },
+    skills::Skills,
+    tick::TICK_HZ,
+    Config,
+};
+
+/// How much game time passes every [Harness::step], one server tick
+const FRAME: Duration = Duration::from_nanos((1_000_000_000.0 / TICK_HZ) as u64);
+
+/// Give up on [Harness::run_until] after this many steps
+const MAX_STEPS: usize = 1000;
+
+/// Give up on [Harness::connect_as] after this long. Passwords are checked on a thread of their own,
+/// so that takes real time instead of steps.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whoever logs in with this name can kick people
+pub const ADMIN: &str = "Admin";
+
+static SETUP: Once = Once::new();
+
+/// Keep everything the server and clients save out of the repo, and make the server key and
+/// skill file before any test can race to create them.
+fn setup() {
+    SETUP.call_once(|| {
+        std::env::set_current_dir(env!("CARGO_TARGET_TMPDIR")).unwrap();
+        ServerIdentity::load_or_generate_from_main_dir().unwrap();
+        // One left behind by an older build would have outdated skills
+        let _ = std::fs::remove_file("skills.yaml");
+        Skills::load_from_main_dir();
+    });
+}
+
+/// Encryption has its own tests in `shared`, and would have clients pinning the key of every
+/// test server they see.
+fn config() -> Config {
+    Config {
+        ip: "127.0.0.1".into(),
+        port: 0,
+        encryption: Some(false),
+        admins: Some(vec![ADMIN.to_string()]),
+        ..Default::default()
+    }
+}
+
+/// Every event a client got, in the order they were delivered
+#[derive(Resource, Default)]
+pub struct Received(pub Vec<EventToClient>);
+
+/// The events from [Received] that answered a request, with its id
+#[derive(Resource, Default)]
+pub struct Answers(pub Vec<(RequestId, EventToClient)>);
+
+/// Every notification a client showed, in order
+#[derive(Resource, Default)]
+pub struct Notifications(pub Vec<String>);
+
+/// Runs before `drain_events` turns the events into bevy events, which only live for two frames
+fn record_received(
+    sr: Res<ServerResources<EventToClient>>,
+    mut received: ResMut<Received>,
+    mut answers: ResMut<Answers>,
+) {
+    let list = sr.event_list.lock().unwrap();
+    received.0.extend(list.iter().map(|x| x.event.clone()));
+    answers.0.extend(
+        list.iter()
+            .filter_map(|x| Some((x.request?, x.event.clone()))),
+    );
+}
+
+fn record_notifications(
+    mut notifications: EventReader<Notification>,
+    mut recorded: ResMut<Notifications>,
+) {
+    recorded.0.extend(notifications.read().map(|x| x.0.clone()));
+}
+
+/// Everything in `DefaultPlugins` that works without a window or a graphics card
+fn headless_plugins() -> impl PluginGroup {
+    DefaultPlugins
+        .build()
+        // Only one app per process gets to set up logging
+        .disable::<LogPlugin>()
+        .disable::<WinitPlugin>()
+        .disable::<AudioPlugin>()
+        .disable::<GilrsPlugin>()
+        .set(WindowPlugin {
+            primary_window: None,
+            exit_condition: ExitCondition::DontExit,
+            close_when_requested: false,
+        })
+        .set(RenderPlugin {
+            render_creation: WgpuSettings {
+                backends: None,
+                ..default()
+            }
+            .into(),
+            ..default()
+        })
+}
+
+/// A client with the game's own plugins, minus anything that needs a window. It connects to the
+/// server by itself, the same way `--autoconnect` does.
+pub struct TestClient {
+    pub app: App,
+}
+
+impl TestClient {
+    fn new(
+        network: &MemoryNetwork,
+        addr: std::net::SocketAddr,
+        name: &str,
+        password: Option<&str>,
+        location: Vec3,
+    ) -> Self {
+        let mut app = App::new();
+        app.add_plugins(headless_plugins())
+            .add_plugins((
+                cameras::CameraPlugin,
+                cameras::notifications::NotificationPlugin,
+                shared::skills::SkillsPlugin,
+                states::StatePlugin,
+                menu::MenuPlugin,
+                skills::SkillsPlugin,
+                network::NetworkingPlugin,
+            ))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME))
+            .insert_resource(Config {
+                name: Some(name.to_string()),
+                password: password.map(str::to_string),
+                ..config()
+            })
+            .insert_resource(Skills::default())
+            .insert_resource(network.clone())
+            .insert_resource(CliArgs {
+                autoconnect: Some(addr.to_string()),
+                name_override: None,
+                opts: vec![],
+                print_binds: false,
+                print_config: false,
+                link_conditions: default(),
+            })
+            .init_resource::<Received>()
+            .init_resource::<Answers>()
+            .init_resource::<Notifications>()
+            .add_systems(
+                Update,
+                record_received
+                    .before(shared::event::client::drain_events)
+                    .run_if(
+                        in_state(GameState::ClientSendRequestPacket)
+                            .or_else(in_state(GameState::ClientConnected))
+                            .or_else(in_state(GameState::ClientReconnecting)),
+                    ),
+            )
+            .add_systems(Update, record_notifications);
+        // Spawn our player at the menu, then move them to where they should start out
+        app.update();
+        app.world
+            .query_filtered::<&mut Transform, With<Player>>()
+            .single_mut(&mut app.world)
+            .translation = location;
+
+        Self { app }
+    }
+
+    /// Not every test sends events by hand
+    #[allow(dead_code)]
+    pub fn send(&self, event: &EventToServer) {
+        let sr = self.app.world.resource::<ServerResources<EventToClient>>();
+        let endpoint = self.app.world.resource::<MainServerEndpoint>().0;
+        send_event_to_server(&sr.handler, endpoint, event);
+    }
+
+    pub fn received(&self) -> &[EventToClient] {
+        &self.app.world.resource::<Received>().0
+    }
+
+    pub fn notifications(&self) -> &[String] {
+        &self.app.world.resource::<Notifications>().0
+    }
+
+    pub fn state(&self) -> &GameState {
+        self.app.world.resource::<State<GameState>>().get()
+    }
+
+    pub fn world_data(&self) -> Option<&WorldData> {
+        self.received().iter().find_map(|x| match x {
+            EventToClient::WorldData(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    /// Panics if we are not connected yet
+    pub fn unit_id(&self) -> NetEntId {
+        self.world_data().expect("Not connected yet").your_unit_id
+    }
+}
+
+/// A server and everyone connected to it
+pub struct Harness {
+    pub server: App,
+    pub clients: Vec<TestClient>,
+    network: MemoryNetwork,
+}
+
+impl Harness {
+    /// Start a server, and wait until it is running
+    pub fn new() -> Self {
+        setup();
+
+        let network = MemoryNetwork::default();
+        let mut server = App::new();
+        server
+            .add_plugins(MinimalPlugins)
+            .add_plugins(ServerPlugin)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME))
+            .insert_resource(config())
+            // Whatever is in the skill file is for playing with, tests use the defaults
+            .insert_resource(Skills::default())
+            .insert_resource(network.clone())
+            // Nobody is registered, and nothing gets saved
+            .insert_resource(Accounts::default());
+
+        let mut harness = Self {
+            server,
+            clients: vec![],
+            network,
+        };
+        harness.run_until("the server starts", |h| {
+            *h.server.world.resource::<State<ServerState>>() == ServerState::Running
+        });
+        harness
+    }
+
+    fn server_addr(&self) -> std::net::SocketAddr {
+        self.server.world.resource::<NetworkListenAddrs>().0[0]
+    }
+
+    /// Start connecting a new client standing at `location`, without waiting to be let in.
+    /// Returns their index in [Harness::clients].
+    pub fn add_client(&mut self, name: &str, password: Option<&str>, location: Vec3) -> usize {
+        let client = TestClient::new(&self.network, self.server_addr(), name, password, location);
+        self.clients.push(client);
+        self.clients.len() - 1
+    }
+
+    /// Like [Harness::add_client], but waits for the server to let them in
+    pub fn connect(&mut self, name: &str, location: Vec3) -> usize {
+        self.connect_as(name, None, location)
+    }
+
+    /// Like [Harness::connect], but with a password logs in to the account called `name`,
+    /// registering it if nobody has it yet
+    pub fn connect_as(&mut self, name: &str, password: Option<&str>, location: Vec3) -> usize {
+        let i = self.add_client(name, password, location);
+        let started = Instant::now();
+        while *self.clients[i].state() != GameState::ClientConnected {
+            assert!(
+                started.elapsed() < LOGIN_TIMEOUT,
+                "Gave up waiting for {name} to connect, they were told {:?}",
+                self.clients[i].notifications(),
+            );
+            std::thread::sleep(Duration::from_millis(1));
+            self.step();
+        }
+        i
+    }
+
+    /// Run one frame on the server and then every client, delivering what each side sent
+    /// before the other side runs.
+    pub fn step(&mut self) {
+        self.server.update();
+        self.network.deliver();
+        for client in &mut self.clients {
+            client.app.update();
+        }
+        self.network.deliver();
+    }
+
+    /// Step until at least `duration` of game time has passed
+    pub fn run_for(&mut self, duration: Duration) {
+        for _ in 0..duration.div_duration_f64(FRAME).ceil() as usize {
+            self.step();
+        }
+    }
+
+    /// Step until `done`, panicking if it takes more than [MAX_STEPS]
+    pub fn run_until(&mut self, what: &str, done: impl Fn(&Harness) -> bool) {
+        for _ in 0..MAX_STEPS {
+            if done(self) {
+                return;
+            }
+            self.step();
+        }
+        panic!("Gave up waiting for {what}");
+    }
+
+    /// Look up a unit on the server
+    pub fn server_unit<T: Component + Clone>(&mut self, id: NetEntId) -> Option<T> {
+        self.server
+            .world
+            .query::<(&NetEntId, &T)>()
+            .iter(&self.server.world)
+            .find(|(x, _)| **x == id)
+            .map(|(_, x)| x.clone())
+    }
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use shared::{
+    event::{
+        client::{Chat, YourCastResult},
+        server::{Cast, SendChat},
+    },
+    netlib::{
+        rpc::{send_request, PendingRequests, RequestId, RequestTimedOut, Rpc},
+        EventToClient, MainServerEndpoint, ServerResources,
+    },
+};
+
+mod harness;
+
+use harness::{Answers, Harness, TestClient};
+
+/// Send `request` the way a real client would, returning its id
+fn request<R: Rpc>(client: &mut TestClient, request: R) -> RequestId {
+    let world = &mut client.app.world;
+    let handler = world
+        .resource::<ServerResources<EventToClient>>()
+        .handler
+        .clone();
+    let endpoint = world.resource::<MainServerEndpoint>().0;
+    let time = *world.resource::<Time<Real>>();
+    let mut pending = world.resource_mut::<PendingRequests>();
+    send_request(&handler, endpoint, &mut pending, &time, request)
+}
+
+/// What the server answered the request with `id` with, if it has yet
+fn answer(client: &TestClient, id: RequestId) -> Option<&EventToClient> {
+    let answers = &client.app.world.resource::<Answers>().0;
+    answers.iter().find(|x| x.0 == id).map(|x| &x.1)
+}
+
+fn is_waiting(client: &TestClient, id: RequestId) -> bool {
+    client
+        .app
+        .world
+        .resource::<PendingRequests>()
+        .is_waiting(id)
+}
+
+#[test]
+fn a_cast_is_answered_with_its_request_id() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+
+    let first = request(&mut h.clients[a], Cast::Melee);
+    h.run_until("the first cast is answered", |h| {
+        answer(&h.clients[a], first).is_some()
+    });
+    assert!(matches!(
+        answer(&h.clients[a], first),
+        Some(EventToClient::YourCastResult(YourCastResult::Ok(_)))
+    ));
+    let a_id = h.clients[a].unit_id();
+    assert!(h.server_unit::<Cast>(a_id).is_some());
+
+    // Still winding up the first one
+    let second = request(&mut h.clients[a], Cast::Melee);
+    h.run_until("the second cast is answered", |h| {
+        answer(&h.clients[a], second).is_some()
+    });
+    assert!(matches!(
+        answer(&h.clients[a], second),
+        Some(EventToClient::YourCastResult(YourCastResult::No(_)))
+    ));
+}
+
+#[test]
+fn chat_commands_are_answered() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+
+    let id = request(
+        &mut h.clients[a],
+        SendChat {
+            text: "/not-a-command".into(),
+        },
+    );
+    h.run_until("the command is answered", |h| {
+        answer(&h.clients[a], id).is_some()
+    });
+    let Some(EventToClient::Chat(Chat { text, .. })) = answer(&h.clients[a], id) else {
+        panic!("Answered with something other than a chat");
+    };
+    assert!(text.starts_with("Error in /not-a-command"));
+    assert!(!is_waiting(&h.clients[a], id));
+}
+
+#[derive(Resource, Default)]
+struct TimedOut(Vec<RequestId>);
+
+fn record_timeouts(
+    mut timeouts: EventReader<RequestTimedOut<SendChat>>,
+    mut seen: ResMut<TimedOut>,
+) {
+    seen.0.extend(timeouts.read().map(|x| x.id));
+}
+
+#[test]
+fn requests_nobody_answers_time_out() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    h.clients[a]
+        .app
+        .init_resource::<TimedOut>()
+        .add_systems(Update, record_timeouts);
+
+    // Normal chat is never answered
+    let id = request(
+        &mut h.clients[a],
+        SendChat {
+            text: "hello?".into(),
+        },
+    );
+    h.run_for(Duration::from_secs(1));
+    assert!(h.clients[a].app.world.resource::<TimedOut>().0.is_empty());
+
+    h.run_until("the chat times out", |h| {
+        h.clients[a].app.world.resource::<TimedOut>().0 == [id]
+    });
+    assert!(!is_waiting(&h.clients[a], id));
+}
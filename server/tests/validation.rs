@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use server::validation::{landing_spot, validate_cast, RateLimit, TokenBucket};
+use shared::{
+    event::{
+        client::{Chat, YourCastResult},
+        server::{Cast, SendChat},
+        spells::ShootingData,
+        NetEntId,
+    },
+    netlib::{EventToClient, EventToServer},
+    skills::Skills,
+    status_effects::{ApplyEffect, EffectInfo, EffectKind, StatusEffects},
+};
+
+mod harness;
+
+use harness::{Harness, TestClient};
+
+/// Why the server refused our last cast, if it did
+fn refusal(client: &TestClient) -> Option<&str> {
+    client.received().iter().find_map(|x| match x {
+        EventToClient::YourCastResult(YourCastResult::Denied(reason)) => Some(reason.as_str()),
+        _ => None,
+    })
+}
+
+fn was_refused(client: &TestClient) -> bool {
+    refusal(client).is_some()
+}
+
+fn was_kicked(client: &TestClient) -> bool {
+    client
+        .received()
+        .iter()
+        .any(|x| matches!(x, EventToClient::Disconnect(_)))
+}
+
+#[test]
+fn token_bucket_allows_a_burst_then_refills() {
+    let mut bucket = TokenBucket::new(RateLimit::new(3.0, 2.0), 0.0);
+    assert!((0..3).all(|_| bucket.take(0.0)));
+    assert!(!bucket.take(0.0));
+
+    // One token every half second
+    assert!(!bucket.take(0.4));
+    assert!(bucket.take(0.5));
+    assert!(!bucket.take(0.5));
+
+    assert!(!bucket.is_full(1.0));
+    assert!(bucket.is_full(100.0));
+    // Never more than the burst, however long it waited
+    assert!((0..3).all(|_| bucket.take(100.0)));
+    assert!(!bucket.take(100.0));
+}
+
+#[test]
+fn flooding_chat_gets_you_kicked() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::ZERO);
+
+    // Every frame they go over the limit costs a strike
+    let mut sent = 0;
+    while !was_kicked(&h.clients[a]) {
+        for _ in 0..10 {
+            h.clients[a].send(&EventToServer::SendChat(SendChat {
+                text: format!("spam {sent}"),
+            }));
+            sent += 1;
+        }
+        h.step();
+        assert!(sent < 10_000, "A was never kicked");
+    }
+
+    let heard = h.clients[b]
+        .received()
+        .iter()
+        .filter(|x| {
+            matches!(
+                x,
+                EventToClient::Chat(Chat {
+                    source: Some(_),
+                    ..
+                })
+            )
+        })
+        .count();
+    assert!(heard < sent / 2, "B heard {heard} of {sent} messages");
+}
+
+#[test]
+fn teleporting_out_of_range_is_refused() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let a_id = h.clients[a].unit_id();
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Teleport(Vec3::new(
+        1000.0, 0.0, 0.0,
+    ))));
+    h.run_until("the teleport is refused", |h| was_refused(&h.clients[a]));
+
+    h.run_for(Duration::from_secs(5));
+    let position = h.server_unit::<Transform>(a_id).unwrap().translation;
+    assert!(position.xz().length() < 1.0, "A ended up at {position}");
+}
+
+#[test]
+fn teleporting_out_of_the_world_is_refused() {
+    let skills = Skills::default();
+    let caster = Vec3::new(495.0, 0.0, 0.0);
+    let nobody = |_| None;
+
+    let inside = Cast::Teleport(Vec3::new(500.0, 0.0, 0.0));
+    assert!(validate_cast(&skills, &inside, caster, nobody).is_ok());
+    let outside = Cast::Teleport(Vec3::new(510.0, 0.0, 0.0));
+    assert!(validate_cast(&skills, &outside, caster, nobody).is_err());
+}
+
+#[test]
+fn teleports_stop_short_of_whoever_is_in_the_way() {
+    let from = Vec3::ZERO;
+    let target = Vec3::new(10.0, 0.0, 0.0);
+
+    assert_eq!(landing_spot(from, target, &[]), target);
+    assert_eq!(
+        landing_spot(from, target, &[Vec3::new(0.0, 0.0, 10.0)]),
+        target
+    );
+
+    let landed = landing_spot(from, target, &[target]);
+    assert!(landed.x < 8.5 && landed.x > 7.0, "Landed at {landed}");
+
+    // Nowhere to go at all
+    let crowd: Vec<Vec3> = (0..=10).map(|x| Vec3::new(x as f32, 0.0, 0.0)).collect();
+    assert_eq!(landing_spot(from, target, &crowd), from);
+}
+
+#[test]
+fn shooting_from_somewhere_else_is_refused() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Shoot(ShootingData {
+        shot_from: Vec3::new(20.0, 0.0, 0.0),
+        target: Vec3::new(21.0, 0.0, 0.0),
+    })));
+    h.run_until("the shot is refused", |h| was_refused(&h.clients[a]));
+}
+
+#[test]
+fn targeting_someone_who_does_not_exist_is_refused() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+
+    h.clients[a].send(&EventToServer::Cast(Cast::ShootTargeted(
+        Vec3::ZERO,
+        NetEntId::random(),
+    )));
+    h.run_until("the shot is refused", |h| was_refused(&h.clients[a]));
+}
+
+#[test]
+fn targeting_an_ally_is_refused() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let b = h.connect("B", Vec3::new(2.0, 0.0, 0.0));
+    let b_id = h.clients[b].unit_id();
+
+    h.clients[a].send(&EventToServer::Cast(Cast::ShootTargeted(Vec3::ZERO, b_id)));
+    h.run_until("the shot is refused", |h| was_refused(&h.clients[a]));
+}
+
+#[test]
+fn casting_while_stunned_says_why() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+    let a_id = h.clients[a].unit_id();
+
+    h.server.world.send_event(ApplyEffect {
+        target: a_id,
+        effect: EffectInfo {
+            kind: EffectKind::Stun,
+            magnitude: 0.0,
+            duration: Duration::from_secs(10),
+        },
+    });
+    h.run_for(Duration::from_millis(100));
+    let effects = h.server_unit::<StatusEffects>(a_id).unwrap();
+    assert!(effects.stunned().is_some());
+
+    h.clients[a].send(&EventToServer::Cast(Cast::Melee));
+    h.run_until("the cast is refused", |h| was_refused(&h.clients[a]));
+    assert_eq!(refusal(&h.clients[a]), Some("You are stunned"));
+}
+
+#[test]
+fn casting_twice_at_once_only_casts_once() {
+    let mut h = Harness::new();
+    let a = h.connect("A", Vec3::ZERO);
+
+    for _ in 0..2 {
+        h.clients[a].send(&EventToServer::Cast(Cast::Melee));
+    }
+    h.run_until("the second cast is refused", |h| was_refused(&h.clients[a]));
+    assert_eq!(refusal(&h.clients[a]), Some("You are already casting"));
+
+    let started = h.clients[a]
+        .received()
+        .iter()
+        .filter(|x| matches!(x, EventToClient::SomeoneCast(_)))
+        .count();
+    assert_eq!(started, 1);
+}
@@ -1,95 +1,169 @@
-use quote::{format_ident, quote};
-use regex::Regex;
-use std::{env, fs, path::Path};
+use std::{collections::BTreeMap, env, fs, path::Path};
 
-fn generate_code(req: &GenerateRequest) -> String {
-    let contents = std::fs::read_to_string(req.source).unwrap();
+use quote::ToTokens;
+use syn::{punctuated::Punctuated, Attribute, Fields, Item, Token};
 
-    // loop through the source file and look for all struct definitions.
-    let all_types: Vec<_> = req
-        .struct_search_regex
-        .captures_iter(&contents)
-        .map(|x| format_ident!("{}", &x[1]))
-        .collect();
+/// Bump this whenever netlib or net_macros change how they put things on the wire, since that is
+/// not part of any type's layout.
+const WIRE_FORMAT: u64 = 1;
+
+/// FNV-1a. We can't use `DefaultHasher` because it is allowed to change between rust versions,
+/// and the client and server are not always built with the same compiler.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
 
-    let all_types_lowercase: Vec<_> = all_types
+/// Only serde and the event ids change what goes over the wire, doc comments don't
+fn wire_attrs(attrs: &[Attribute]) -> String {
+    attrs
         .iter()
-        .map(|x| format_ident!("writer_{}", x.to_string().to_lowercase()))
-        .collect();
+        .filter(|x| x.path().is_ident("serde") || x.path().is_ident("net_event"))
+        .map(|x| x.to_token_stream().to_string() + " ")
+        .collect()
+}
 
-    let typename = format_ident!("EventTo{}", req.output_type_name);
+fn is_serialized(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter(|x| x.path().is_ident("derive"))
+        .any(|x| {
+            x.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
+                .unwrap()
+                .iter()
+                .filter_map(|x| x.segments.last())
+                .any(|x| x.ident == "Serialize" || x.ident == "Deserialize")
+        })
+}
 
-    let code = quote!(
-        #[derive(Debug, Clone, Serialize, Deserialize)]
-        #[non_exhaustive]
-        pub enum #typename {
-            #( #all_types ( #all_types ) ),*
-        }
+/// Field names, types and order, which is all postcard needs to agree on
+fn fields_layout(fields: &Fields) -> String {
+    let layout: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            let name = field
+                .ident
+                .as_ref()
+                .map(|x| format!("{x}: "))
+                .unwrap_or_default();
+            format!(
+                "{}{name}{}",
+                wire_attrs(&field.attrs),
+                field.ty.to_token_stream()
+            )
+        })
+        .collect();
+    match fields {
+        Fields::Named(_) => format!("{{ {} }}", layout.join(", ")),
+        Fields::Unnamed(_) => format!("({})", layout.join(", ")),
+        Fields::Unit => String::new(),
+    }
+}
 
-        pub fn drain_events (
-            sr: Res<ServerResources<#typename>>,
-            #(
-                mut #all_types_lowercase: EventWriter<EventFromEndpoint< #all_types >>
-            ),*
-        ) {
-            let mut new_events = sr.event_list.lock().unwrap();
-            let new_events = std::mem::replace(new_events.as_mut(), vec![]);
-            for (endpoint, event) in new_events {
-                match event {
-                    #(
-                        #typename :: #all_types (data) => {
-                            #all_types_lowercase . send(EventFromEndpoint::new(endpoint, data));
-                        }
-                    ),*
+/// Every struct and enum in `items` that serde can see, keyed by where it is
+fn collect_layouts(path: &str, items: &[Item], layouts: &mut BTreeMap<String, String>) {
+    for item in items {
+        let (attrs, ident, generics, body) = match item {
+            Item::Struct(x) => (&x.attrs, &x.ident, &x.generics, fields_layout(&x.fields)),
+            Item::Enum(x) => {
+                let variants: Vec<String> = x
+                    .variants
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "{}{}{}",
+                            wire_attrs(&x.attrs),
+                            x.ident,
+                            fields_layout(&x.fields)
+                        )
+                    })
+                    .collect();
+                (
+                    &x.attrs,
+                    &x.ident,
+                    &x.generics,
+                    format!("{{ {} }}", variants.join(", ")),
+                )
+            }
+            Item::Mod(x) => {
+                if let Some((_, items)) = &x.content {
+                    collect_layouts(&format!("{path}::{}", x.ident), items, layouts);
                 }
+                continue;
             }
+            _ => continue,
+        };
+        if !is_serialized(attrs) {
+            continue;
         }
-
-        pub fn register_events(app: &mut App) {
-            #(
-                app.add_event::< EventFromEndpoint< #all_types > >();
-            )*
-        }
-    );
-
-    let code = syn::parse_file(&code.to_string()).unwrap();
-
-    prettyplease::unparse(&code)
-    //code.to_string()
+        let kind = if matches!(item, Item::Enum(_)) {
+            "enum"
+        } else {
+            "struct"
+        };
+        layouts.insert(
+            format!("{path}::{ident}"),
+            format!(
+                "{}{kind} {ident}{} {body}",
+                wire_attrs(attrs),
+                generics.to_token_stream()
+            ),
+        );
+    }
 }
 
-fn generate_systems(req: GenerateRequest) {
-    let code_str = generate_code(&req);
+/// Hash the wire layout of every event and everything that goes inside of one: their ids, type
+/// names and field types. If any of this changes, old clients will not be able to decode our
+/// packets. Comments, formatting and the order things are declared in don't matter.
+fn generate_protocol_version(sources: &[&str]) {
+    let mut layouts = BTreeMap::new();
+    for source in sources {
+        let file = syn::parse_file(&fs::read_to_string(source).unwrap()).unwrap();
+        collect_layouts(source, &file.items, &mut layouts);
+    }
 
-    let out_dir = env::var_os("OUT_DIR").unwrap();
-    let dest_path = Path::new(&out_dir).join(req.output_filename);
-    fs::write(dest_path, code_str).unwrap();
-}
+    let mut hash = fnv1a(0xcbf29ce484222325, &WIRE_FORMAT.to_le_bytes());
+    for (path, layout) in layouts {
+        hash = fnv1a(hash, path.as_bytes());
+        hash = fnv1a(hash, layout.as_bytes());
+    }
 
-struct GenerateRequest<'a> {
-    output_filename: &'a str,
-    output_type_name: &'a str,
-    source: &'a str,
-    struct_search_regex: &'a Regex,
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("./protocol_version.rs");
+    fs::write(
+        dest_path,
+        format!(
+            "/// Hash of the network protocol, generated by `build.rs`\npub const PROTOCOL_VERSION: u64 = {hash:#018x};\n"
+        ),
+    )
+    .unwrap();
 }
 
 fn main() {
-    let r = Regex::new(r#"(?:struct|enum) (\w+?) \{"#).unwrap();
-    generate_systems(GenerateRequest {
-        source: "src/event/client.rs",
-        output_filename: "./client_event.rs",
-        output_type_name: "Client",
-        struct_search_regex: &r,
-    });
-
-    generate_systems(GenerateRequest {
-        source: "src/event/server.rs",
-        output_filename: "./server_event.rs",
-        output_type_name: "Server",
-        struct_search_regex: &r,
-    });
+    let protocol_sources = [
+        "src/event.rs",
+        "src/event/client.rs",
+        "src/event/server.rs",
+        "src/event/spells.rs",
+        "src/faction.rs",
+        "src/movement.rs",
+        "src/netlib.rs",
+        "src/netlib/fragment.rs",
+        "src/netlib/reliability.rs",
+        "src/netlib/rpc.rs",
+        "src/netlib/secure.rs",
+        "src/replication.rs",
+        "src/unit.rs",
+        "src/stats.rs",
+        "src/status_effects.rs",
+    ];
+    generate_protocol_version(&protocol_sources);
 
     println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=src/event/server.rs");
-    println!("cargo:rerun-if-changed=src/event/client.rs");
+    for source in protocol_sources {
+        println!("cargo:rerun-if-changed={source}");
+    }
 }
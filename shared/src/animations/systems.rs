@@ -20,7 +20,7 @@ pub fn tick_casts(
     )>,
     mut commands: Commands,
     mut do_cast: EventWriter<DoCast>,
-    time: Res<Time<Virtual>>,
+    time: Res<Time>,
 ) {
     for (ent, net_ent_id, mut cast_timer, mut anim_timer, cast, cast_net_id) in &mut casting_units {
         cast_timer.0.tick(time.delta());
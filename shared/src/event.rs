@@ -3,7 +3,9 @@ use bevy::prelude::*;
 use message_io::network::Endpoint;
 use serde::{Deserialize, Serialize};
 
-use crate::stats::Health;
+use crate::{
+    faction::Faction, netlib::rpc::RequestId, stats::Health, status_effects::StatusEffects,
+};
 
 use self::spells::NPC;
 
@@ -11,6 +13,8 @@ pub mod client;
 pub mod server;
 pub mod spells;
 
+include!(concat!(env!("OUT_DIR"), "/protocol_version.rs"));
+
 #[derive(Debug, Clone, Copy, Component, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct NetEntId(pub u64);
 impl NetEntId {
@@ -23,6 +27,10 @@ impl NetEntId {
 pub struct EventFromEndpoint<E> {
     pub event: E,
     pub endpoint: Endpoint,
+    /// The server tick this was sent on. Only set for events that came in a batch from the server.
+    pub tick: Option<u64>,
+    /// Set if this was sent as a request, or is the response to one. See [crate::netlib::rpc].
+    pub request: Option<RequestId>,
 }
 
 /// Event Reader with endpoint data.
@@ -30,7 +38,12 @@ pub type ERFE<'w, 's, E> = EventReader<'w, 's, EventFromEndpoint<E>>;
 
 impl<E> EventFromEndpoint<E> {
     pub fn new(endpoint: Endpoint, e: E) -> Self {
-        EventFromEndpoint { event: e, endpoint }
+        EventFromEndpoint {
+            event: e,
+            endpoint,
+            tick: None,
+            request: None,
+        }
     }
 }
 
@@ -39,6 +52,16 @@ pub enum UnitType {
     Player { name: String },
     NPC { npc_type: NPC },
 }
+
+impl UnitType {
+    /// The side units like this are on, unless they were spawned on another one
+    pub fn default_faction(&self) -> Faction {
+        match self {
+            UnitType::Player { .. } => Faction::Players,
+            UnitType::NPC { npc_type } => npc_type.faction(),
+        }
+    }
+}
 //fn components(&self, e: &mut EntityCommands) {
 //match self {
 //UnitType::Player { name } => {
@@ -54,6 +77,8 @@ pub enum UnitType {
 pub struct UnitData {
     pub unit: UnitType,
     pub ent_id: NetEntId,
+    pub faction: Faction,
     pub health: Health,
+    pub effects: StatusEffects,
     pub transform: Transform,
 }
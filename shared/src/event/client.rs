@@ -1,90 +1,198 @@
-use std::time::Duration;
-
-use crate::event::EventFromEndpoint;
-use crate::netlib::ServerResources;
-use bevy::prelude::*;
-use serde::{Deserialize, Serialize};
-
-use super::{
-    server::{Cast, ChangeMovement},
-    spells::UpdateSharedComponent,
-    NetEntId, UnitData,
-};
-
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct WorldData {
-    pub your_unit_id: NetEntId,
-    pub unit_data: Vec<UnitData>,
-}
+use net_macros::net_events;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct SpawnUnit {
-    pub data: UnitData,
-}
+pub use self::events::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct PlayerDisconnected {
-    pub id: NetEntId,
-}
+/// Everything the server can send us. The ids are what goes over the wire, so they must never
+/// change or be reused. New events get the next free one.
+#[net_events(to = Client)]
+mod events {
+    use std::time::Duration;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct SomeoneMoved {
-    pub id: NetEntId,
-    pub movement: ChangeMovement,
-}
+    use bevy::prelude::*;
+    use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct SomeoneCast {
-    pub caster_id: NetEntId,
-    pub cast_id: NetEntId,
-    pub cast: Cast,
-}
+    use crate::{
+        event::{
+            server::{Cast, ChangeMovement},
+            spells::UpdateSharedComponent,
+            NetEntId, UnitData,
+        },
+        faction::FactionRules,
+        netlib::rpc::RequestId,
+        replication::UnitDelta,
+    };
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub enum YourCastResult {
-    /// Go ahead with cast
-    Ok(NetEntId),
-    /// Go ahread with cast, but you had some extra cd to account for
-    OffsetBy(Duration, NetEntId),
-    /// You can't cast.
-    No(Duration),
-}
+    /// The server refused to let us join.
+    ///
+    /// This must keep id 0 and its fields must never change, so a client built from a different
+    /// commit can still decode it.
+    #[net_event(id = 0)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct ConnectRejected {
+        pub server_protocol_version: u64,
+        pub reason: String,
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event, Hash, PartialEq, Eq)]
-pub struct BulletHit {
-    pub bullet: NetEntId,
-    pub player: NetEntId,
-}
+    #[net_event(id = 1)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct WorldData {
+        pub your_unit_id: NetEntId,
+        pub unit_data: Vec<UnitData>,
+        /// Send this back in [crate::event::server::ConnectRequest::resume] to get the same unit
+        /// back after losing connection
+        pub session: u64,
+        /// See [crate::skills::Skills::checksum]. Playing with different skills than the server
+        /// only looks like lag.
+        pub skills_checksum: u64,
+        pub faction_rules: FactionRules,
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct SomeoneUpdateComponent {
-    pub id: NetEntId,
-    pub update: UpdateSharedComponent,
-}
+    #[net_event(id = 2)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SpawnUnit {
+        pub data: UnitData,
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct Chat {
-    pub source: Option<NetEntId>,
-    pub text: String,
-}
+    #[net_event(id = 3)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct PlayerDisconnected {
+        pub id: NetEntId,
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct UnitDie {
-    pub id: NetEntId,
-    pub disappear: bool,
-}
+    /// The server is done with us, because we were kicked or it is shutting down. Nothing else will
+    /// be sent to us after this.
+    #[net_event(id = 4)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct Disconnect {
+        pub reason: String,
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct SpawnInteractable {
-    pub id: NetEntId,
-    pub location: Vec3,
-    // TODO
-    //pub interaction_type: T
-}
+    #[net_event(id = 5)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SomeoneMoved {
+        pub id: NetEntId,
+        pub movement: ChangeMovement,
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct DespawnInteractable {
-    pub id: NetEntId,
-}
+    /// Where everyone else is. See [crate::replication]
+    #[net_event(id = 6)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct WorldSnapshot {
+        pub id: u32,
+        /// The snapshot this is a delta against, or None if it contains everything
+        pub baseline: Option<u32>,
+        pub tick: u64,
+        pub units: Vec<UnitDelta>,
+        /// Units that were in the baseline but are gone now
+        pub removed: Vec<NetEntId>,
+    }
+
+    #[net_event(id = 7)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SomeoneCast {
+        pub caster_id: NetEntId,
+        pub cast_id: NetEntId,
+        pub cast: Cast,
+    }
 
-include!(concat!(env!("OUT_DIR"), "/client_event.rs"));
+    #[net_event(id = 8)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub enum YourCastResult {
+        /// Go ahead with cast
+        Ok(NetEntId),
+        /// Go ahread with cast, but you had some extra cd to account for
+        OffsetBy(Duration, NetEntId),
+        /// You can't cast yet, try again after this long
+        No(Duration),
+        /// You can't cast for some reason other than a cooldown, which is shown to the player
+        Denied(String),
+    }
+
+    /// Where the server thinks we are, after applying all of our inputs up to `last_input`
+    #[net_event(id = 9)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct YourMovementResult {
+        pub last_input: u32,
+        pub transform: Transform,
+    }
+
+    /// Answer to a [crate::event::server::Heartbeat], used to keep our clock in sync with the
+    /// server
+    #[net_event(id = 10)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct HeartbeatResponse {
+        /// Copied from the heartbeat
+        pub client_time: f64,
+        /// The tick the server was on when it answered
+        pub tick: u64,
+    }
+
+    #[net_event(id = 11)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event, Hash, PartialEq, Eq)]
+    pub struct BulletHit {
+        pub bullet: NetEntId,
+        pub player: NetEntId,
+    }
+
+    #[net_event(id = 12)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SomeoneUpdateComponent {
+        pub id: NetEntId,
+        pub update: UpdateSharedComponent,
+    }
+
+    #[net_event(id = 13)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct Chat {
+        pub source: Option<NetEntId>,
+        pub text: String,
+    }
+
+    #[net_event(id = 14)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct UnitDie {
+        pub id: NetEntId,
+        pub disappear: bool,
+    }
+
+    /// The unit is too far away for us to hear about it anymore, so forget it until it comes back.
+    #[net_event(id = 15)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct LeaveInterest {
+        pub id: NetEntId,
+    }
+
+    #[net_event(id = 16)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SpawnInteractable {
+        pub id: NetEntId,
+        pub location: Vec3,
+        // TODO
+        //pub interaction_type: T
+    }
+
+    #[net_event(id = 17)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct DespawnInteractable {
+        pub id: NetEntId,
+    }
+
+    /// Wraps the answer to a [crate::event::server::Request] with the same id. It is unwrapped as
+    /// soon as it arrives, so nothing ever reads this.
+    #[net_event(id = 18, wrapper)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct Response {
+        pub id: RequestId,
+        #[serde(deserialize_with = "EventToClient::deserialize_wrapped")]
+        pub event: Box<EventToClient>,
+    }
+
+    /// Where a unit ended up after teleporting, which might not be where it asked to go. See
+    /// [crate::event::server::Cast::Teleport]
+    #[net_event(id = 19)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SomeoneTeleported {
+        pub id: NetEntId,
+        pub transform: Transform,
+    }
+}
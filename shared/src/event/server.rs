@@ -1,41 +1,97 @@
-use crate::{event::EventFromEndpoint, unit::AttackIntention};
-use crate::netlib::ServerResources;
-use bevy::prelude::*;
-use serde::{Deserialize, Serialize};
+use net_macros::net_events;
 
-use super::{spells::ShootingData, NetEntId};
+pub use self::events::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct ConnectRequest {
-    pub name: Option<String>,
-    pub my_location: Transform,
-}
+/// Everything clients can send the server. The ids are what goes over the wire, so they must
+/// never change or be reused. New events get the next free one.
+#[net_events(to = Server)]
+mod events {
+    use bevy::prelude::*;
+    use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct SendChat {
-    pub text: String,
-}
+    use crate::{
+        event::{spells::ShootingData, NetEntId},
+        movement::MovementInput,
+        netlib::rpc::RequestId,
+        unit::AttackIntention,
+    };
 
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub struct Heartbeat {}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Event, Component)]
-pub enum Cast {
-    Teleport(Vec3),
-    Shoot(ShootingData),
-    ShootTargeted(Vec3, NetEntId),
-    Melee,
-    Aoe(Vec3),
-    Buff,
-}
+    /// This must keep id 0, and `protocol_version` must stay its first field. A server built from a
+    /// different commit still needs to be able to read it to reject us.
+    #[net_event(id = 0)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct ConnectRequest {
+        /// See [crate::event::PROTOCOL_VERSION]
+        pub protocol_version: u64,
+        pub name: Option<String>,
+        pub my_location: Transform,
+        /// The session from the last [crate::event::client::WorldData] we got, if we are
+        /// reconnecting
+        pub resume: Option<u64>,
+        /// Logs in to the account called `name`, or registers it if nobody has it yet
+        pub password: Option<String>,
+    }
 
-/// walking and stuff
-#[derive(Debug, Clone, Serialize, Deserialize, Event)]
-pub enum ChangeMovement {
-    StandStill,
-    Move2d(Vec2),
-    SetTransform(Transform),
-    AttackIntent(AttackIntention),
-}
+    #[net_event(id = 1)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SendChat {
+        pub text: String,
+    }
+
+    #[net_event(id = 2)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct Heartbeat {
+        /// Seconds since the client started. The server sends this back so we can measure the RTT.
+        pub client_time: f64,
+    }
 
-include!(concat!(env!("OUT_DIR"), "/server_event.rs"));
+    #[net_event(id = 3)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event, Component)]
+    pub enum Cast {
+        Teleport(Vec3),
+        Shoot(ShootingData),
+        ShootTargeted(Vec3, NetEntId),
+        Melee,
+        Aoe(Vec3),
+        Buff,
+    }
+
+    /// We have decoded this [crate::event::client::WorldSnapshot], so the server can send deltas
+    /// against it
+    #[net_event(id = 4)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct SnapshotAck {
+        pub id: u32,
+    }
+
+    /// walking and stuff
+    #[net_event(id = 5)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub enum ChangeMovement {
+        StandStill,
+        Move2d(Vec2),
+        /// Only the server is allowed to send this, clients move with [ChangeMovement::Inputs]
+        SetTransform(Transform),
+        AttackIntent(AttackIntention),
+        /// Every input the client has not seen acked in a
+        /// [crate::event::client::YourMovementResult] yet
+        Inputs(Vec<MovementInput>),
+    }
+
+    /// We are leaving, so the server doesn't have to wait for our heartbeats to time out
+    #[net_event(id = 6)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct Disconnect {
+        pub reason: String,
+    }
+
+    /// Wraps an event we want an answer to, see [crate::netlib::rpc]. It is unwrapped as soon as
+    /// it arrives, so nothing ever reads this.
+    #[net_event(id = 7, wrapper)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Event)]
+    pub struct Request {
+        pub id: RequestId,
+        #[serde(deserialize_with = "EventToServer::deserialize_wrapped")]
+        pub event: Box<EventToServer>,
+    }
+}
@@ -1,4 +1,4 @@
-use crate::stats::Health;
+use crate::{faction::Faction, stats::Health, status_effects::StatusEffects};
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +12,7 @@ pub struct ShootingData {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum UpdateSharedComponent {
     Health(Health),
+    StatusEffects(StatusEffects),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Component, Hash, PartialEq, Eq, clap::ValueEnum)]
@@ -42,6 +43,13 @@ impl NPC {
         })
     }
 
+    pub fn faction(&self) -> Faction {
+        match self {
+            NPC::Penguin => Faction::Monsters,
+            NPC::Mage => Faction::Monsters,
+        }
+    }
+
     pub fn get_ai_component(&self) -> AIType {
         match self {
             NPC::Penguin => AIType::WalkToNearestPlayer,
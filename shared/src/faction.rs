@@ -0,0 +1,52 @@
+//! Which side every unit is on, and who is allowed to hurt who.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+pub enum Faction {
+    /// Every player
+    Players,
+    /// NPCs that go after players
+    Monsters,
+    /// NPCs that leave everyone alone, and that nobody goes after on their own
+    Wildlife,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    /// They hurt each other, and AI goes after them
+    Hostile,
+    /// They can hurt each other, but AI leaves them alone
+    Neutral,
+    /// They can't hurt each other, and share buffs
+    Allied,
+}
+
+impl Relation {
+    /// Whether damage from one side lands on the other
+    pub fn can_hurt(self) -> bool {
+        self != Relation::Allied
+    }
+}
+
+/// The server's rules on who fights who. Clients get them in [crate::event::client::WorldData].
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FactionRules {
+    /// Players can hurt each other
+    pub pvp: bool,
+}
+
+impl FactionRules {
+    /// How units of `a` and `b` get along, which is the same both ways around
+    pub fn relation(&self, a: Faction, b: Faction) -> Relation {
+        match (a, b) {
+            (Faction::Players, Faction::Players) if self.pvp => Relation::Hostile,
+            (a, b) if a == b => Relation::Allied,
+            (Faction::Wildlife, _) | (_, Faction::Wildlife) => Relation::Neutral,
+            _ => Relation::Hostile,
+        }
+    }
+}
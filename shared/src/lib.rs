@@ -1,15 +1,23 @@
-use std::{collections::HashMap, env::current_dir, fs::OpenOptions};
+use std::{collections::HashMap, env::current_dir, fs::OpenOptions, time::Duration};
 
 use bevy::prelude::*;
+use netlib::{conditioner::LinkConditions, NetTransport, NetworkConnectionTarget};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
 pub mod animations;
 pub mod casting;
 pub mod event;
+pub mod faction;
 pub mod interactable;
+pub mod movement;
 pub mod netlib;
+pub mod projectile;
+pub mod replication;
+pub mod skills;
 pub mod stats;
+pub mod status_effects;
+pub mod tick;
 pub mod unit;
 
 #[derive(Reflect, Hash, Eq, PartialEq, Clone, Deserialize, Serialize, Debug)]
@@ -54,12 +62,35 @@ pub struct Config {
     pub ip: String,
     pub port: u16,
     pub name: Option<String>,
+    /// Logs in to the account for `name` on the server, or registers it if nobody has it yet.
+    /// Without one you play as a guest.
+    pub password: Option<String>,
     //#[serde(default="default_sens")]
     pub sens: f32,
     //#[serde(default="default_qe_sens")]
     pub qe_sens: f32,
     /// Should sound play on hits?
     pub sound: Option<bool>,
+    /// How far behind the server other units are drawn, in milliseconds. Higher values hide more
+    /// packet loss and jitter at the cost of seeing everyone later.
+    pub interp_delay_ms: Option<u64>,
+    /// Server only. How far away a unit can be before clients stop hearing about it.
+    pub interest_radius: Option<f32>,
+    /// How to connect to `ip` and `port`. Defaults to udp.
+    pub transport: Option<NetTransport>,
+    /// Encrypt the connection to the server. For the server, refuse anyone who doesn't. Defaults
+    /// to true.
+    pub encryption: Option<bool>,
+    /// Simulate a bad connection, for testing. Can also be set with the --sim-* command line flags.
+    pub link_conditions: Option<LinkConditions>,
+    /// Server only. Every address to accept players on, each with its own transport. Defaults to
+    /// just `ip` and `port` with `transport`.
+    pub listen: Option<Vec<NetworkConnectionTarget>>,
+    /// Server only. Let players hurt each other. Defaults to false.
+    pub pvp: Option<bool>,
+    /// Server only. Accounts that can use commands like `/kick` on other players. Defaults to
+    /// nobody.
+    pub admins: Option<Vec<String>>,
 
     pub keybindings: Keybinds, // TODO rust_phf
 }
@@ -130,7 +161,16 @@ impl Default for Config {
             sens: 0.003,
             qe_sens: 3.0,
             name: None,
+            password: None,
             sound: Some(false),
+            interp_delay_ms: Some(100),
+            interest_radius: Some(100.0),
+            transport: Some(NetTransport::Udp),
+            listen: None,
+            pvp: Some(false),
+            admins: None,
+            encryption: Some(true),
+            link_conditions: None,
             keybindings: DEFAULT_BINDS.clone(),
         }
     }
@@ -140,6 +180,54 @@ impl Config {
     pub fn sound(&self) -> bool {
         self.sound.unwrap_or(false)
     }
+
+    pub fn interp_delay(&self) -> Duration {
+        Duration::from_millis(self.interp_delay_ms.unwrap_or(100))
+    }
+
+    pub fn interest_radius(&self) -> f32 {
+        self.interest_radius.unwrap_or(100.0)
+    }
+
+    /// Command line flags win over the config file
+    pub fn override_link_conditions(&mut self, flags: &LinkConditions) {
+        if flags.is_enabled() {
+            self.link_conditions = Some(flags.clone());
+        } else if let Some(conditions) = &mut self.link_conditions {
+            conditions.seed = flags.seed.or(conditions.seed);
+        }
+    }
+
+    pub fn encryption(&self) -> bool {
+        self.encryption.unwrap_or(true)
+    }
+
+    pub fn pvp(&self) -> bool {
+        self.pvp.unwrap_or(false)
+    }
+
+    /// Whether `account` is one of the `admins`, however either is capitalized
+    pub fn is_admin(&self, account: &str) -> bool {
+        self.admins
+            .iter()
+            .flatten()
+            .any(|x| x.to_lowercase() == account.to_lowercase())
+    }
+
+    /// Where the client connects to when playing locally
+    pub fn connection_target(&self) -> NetworkConnectionTarget {
+        NetworkConnectionTarget {
+            ip: self.ip.clone(),
+            port: self.port,
+            transport: self.transport.unwrap_or_default(),
+        }
+    }
+
+    pub fn listen_targets(&self) -> Vec<NetworkConnectionTarget> {
+        self.listen
+            .clone()
+            .unwrap_or_else(|| vec![self.connection_target()])
+    }
 }
 
 pub struct ConfigPlugin;
@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animations::{AnimationState, AnimationTimer},
+    event::server::Cast,
+    skills::Skills,
+    status_effects::StatusEffects,
+};
+
+/// Units per second something moves with a [crate::unit::MovementIntention] of length 1
+pub const PLAYER_SPEED: f32 = 25.;
+
+/// The longest a single input is allowed to last. Anything longer gets clamped so a client can't
+/// move further by claiming one of their frames took forever.
+pub const MAX_INPUT_DT: f32 = 0.1;
+
+/// How far from the origin anything can be along `x` and `z`. The client's floor is this big.
+pub const WORLD_HALF_SIZE: f32 = 500.0;
+
+/// How many unconfirmed inputs the client keeps around before it starts dropping the oldest
+const MAX_PENDING_INPUTS: usize = 128;
+
+/// One frame worth of movement from a client.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MovementInput {
+    pub seq: u32,
+    /// The direction we want to go in, already rotated by the camera. `y` is the world `z`.
+    pub intention: Vec2,
+    /// How long this input was held for, in seconds.
+    pub dt: f32,
+}
+
+/// Casting something slows you down depending on how far into the animation you are, and some
+/// [StatusEffects] speed you up or slow you down too.
+pub fn speed_multiplier(
+    skills: &Skills,
+    casting: Option<(&AnimationTimer, &Cast)>,
+    effects: Option<&StatusEffects>,
+) -> f32 {
+    let effects = effects.map_or(1.0, StatusEffects::speed_multiplier);
+    let Some((anim_timer, cast)) = casting else {
+        return effects;
+    };
+
+    let casting = match skills
+        .get(cast)
+        .get_current_animation(anim_timer.0.elapsed())
+    {
+        AnimationState::FrontSwing => 0.75,
+        AnimationState::WindUp => 0.25,
+        AnimationState::WindDown => 0.5,
+        AnimationState::Backswing => 0.75,
+        AnimationState::Done => 1.0,
+    };
+    casting * effects
+}
+
+/// Where a unit at `translation` ends up after `input`, stopping at the edge of the world. The
+/// client and server both run this, so they always agree as long as they see the same inputs.
+pub fn apply_input(translation: Vec3, input: &MovementInput, speed_multiplier: f32) -> Vec3 {
+    let intention = input.intention.clamp_length_max(1.0);
+    let dt = input.dt.clamp(0.0, MAX_INPUT_DT);
+
+    clamp_to_world(
+        translation
+            + Vec3::new(intention.x, 0.0, intention.y) * PLAYER_SPEED * speed_multiplier * dt,
+    )
+}
+
+/// Whether `position` is inside the world
+pub fn in_world(position: Vec3) -> bool {
+    position.is_finite()
+        && position.x.abs() <= WORLD_HALF_SIZE
+        && position.z.abs() <= WORLD_HALF_SIZE
+}
+
+/// The closest point to `position` that is inside the world
+pub fn clamp_to_world(position: Vec3) -> Vec3 {
+    Vec3::new(
+        position.x.clamp(-WORLD_HALF_SIZE, WORLD_HALF_SIZE),
+        position.y,
+        position.z.clamp(-WORLD_HALF_SIZE, WORLD_HALF_SIZE),
+    )
+}
+
+/// The direction a unit faces while moving along `intention`
+pub fn facing(intention: Vec2) -> Quat {
+    Quat::from_rotation_y(intention.x.atan2(intention.y))
+}
+
+/// Inputs the client has already applied locally that the server has not confirmed yet.
+#[derive(Component, Debug, Default)]
+pub struct PendingInputs {
+    next_seq: u32,
+    last_acked: Option<u32>,
+    /// Each input along with the speed multiplier we predicted it with
+    inputs: VecDeque<(MovementInput, f32)>,
+}
+
+impl PendingInputs {
+    /// Record a new input and return it so it can be applied locally.
+    pub fn push(&mut self, intention: Vec2, dt: f32, speed_multiplier: f32) -> MovementInput {
+        let input = MovementInput {
+            seq: self.next_seq,
+            intention,
+            dt,
+        };
+        self.next_seq += 1;
+
+        if self.inputs.len() >= MAX_PENDING_INPUTS {
+            self.inputs.pop_front();
+        }
+        self.inputs.push_back((input, speed_multiplier));
+
+        input
+    }
+
+    /// Everything the server has not told us about yet. These get resent until they are acked,
+    /// so a single lost packet doesn't cost us any movement.
+    pub fn unacked(&self) -> impl Iterator<Item = &MovementInput> {
+        self.inputs.iter().map(|(input, _)| input)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Forget everything, eg. when the server places us somewhere new.
+    pub fn clear(&mut self) {
+        self.inputs.clear();
+    }
+
+    /// The server says we were at `authoritative` after it processed `last_input`.
+    ///
+    /// Returns where we should be now, after replaying everything the server has not seen yet on
+    /// top of that, or None if this is older than something we already reconciled against.
+    pub fn reconcile(&mut self, authoritative: Vec3, last_input: u32) -> Option<Vec3> {
+        if self.last_acked.is_some_and(|acked| acked > last_input) {
+            return None;
+        }
+        self.last_acked = Some(last_input);
+
+        while self
+            .inputs
+            .front()
+            .is_some_and(|(input, _)| input.seq <= last_input)
+        {
+            self.inputs.pop_front();
+        }
+
+        Some(
+            self.inputs
+                .iter()
+                .fold(authoritative, |pos, (input, speed_multiplier)| {
+                    apply_input(pos, input, *speed_multiplier)
+                }),
+        )
+    }
+}
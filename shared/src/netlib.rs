@@ -4,151 +4,879 @@ use message_io::{
     node::{NodeEvent, NodeHandler},
 };
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use self::{
+    conditioner::{LinkConditioner, LinkConditions},
+    fragment::MAX_DATAGRAM_SIZE,
+    memory::MemoryNetwork,
+    reliability::{InvalidDatagram, Reliability, ReliableChannel},
+    rpc::RequestId,
+    secure::{ClientHandshake, Handshake, ServerIdentity, SEAL_OVERHEAD},
+    stats::NetStats,
+    transport::DatagramTransport,
+};
+
+pub mod conditioner;
+pub mod fragment;
+pub mod memory;
+pub mod reliability;
+pub mod rpc;
+pub mod secure;
+pub mod stats;
+pub mod transport;
+
+/// How often the listener thread checks for reliable messages that need to be resent.
+const RESEND_TICK: Duration = Duration::from_millis(50);
+
+/// How long we wait for the other side to ack everything when shutting down or forgetting them.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long someone who sent us something has to get let in, see
+/// [NetworkHandler::mark_connected]. Otherwise a single datagram from anywhere would have us
+/// keeping a channel around for good.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the listener thread checks for datagrams the link conditioner is done holding on to.
+const CONDITIONER_TICK: Duration = Duration::from_millis(1);
+
+/// How often a shutting down listener thread checks if everything has been acked.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(10);
+
+/// How much of a datagram an event grouping can use, leaving room for the reliability header and
+/// encryption.
+const MAX_BATCH_SIZE: usize = MAX_DATAGRAM_SIZE - 32 - SEAL_OVERHEAD;
+
+/// Worst case size of everything in a [EventGroupingRef::Batch] except the events.
+const BATCH_HEADER_SIZE: usize = 16;
 
 #[derive(Resource, Clone)]
 pub struct ServerResources<T> {
-    pub event_list: Arc<Mutex<Vec<(Endpoint, T)>>>,
-    pub handler: NodeHandler<()>,
+    pub event_list: Arc<Mutex<Vec<EventFromEndpoint<T>>>>,
+    pub handler: NetworkHandler,
+}
+
+/// Signals we send to our own listener thread
+pub enum NetSignal {
+    ResendUnacked,
+    /// Stop once everything is acked, or at this deadline. See [NetworkHandler::shutdown]
+    Shutdown(Instant),
+    /// Deliver whatever the link conditioner is done delaying
+    ReleaseConditioned,
+}
+
+/// Delays and drops datagrams in both directions, see [LinkConditions]
+struct Conditioners {
+    outgoing: Mutex<LinkConditioner<(Endpoint, Vec<u8>)>>,
+    incoming: Mutex<LinkConditioner<(Endpoint, Vec<u8>)>>,
+}
+
+/// The message-io node and the thread reading from it, only there when using message-io. See
+/// [setup_shared]
+type Listener = (NodeHandler<NetSignal>, JoinHandle<()>);
+
+/// The transport, plus the reliability state of everyone we are talking to.
+#[derive(Clone)]
+pub struct NetworkHandler {
+    transport: Arc<dyn DatagramTransport>,
+    channels: Arc<Mutex<HashMap<Endpoint, ReliableChannel>>>,
+    /// Endpoints we have been told to [NetworkHandler::forget], and when to give up on them
+    closing: Arc<Mutex<HashMap<Endpoint, Instant>>>,
+    /// Endpoints that started talking to us and haven't been let in yet, and when to give up on
+    /// them
+    unconnected: Arc<Mutex<HashMap<Endpoint, Instant>>>,
+    /// Stamped onto every batch we send. Only the server has one of these.
+    tick: Arc<Mutex<Option<u64>>>,
+    listener: Arc<Mutex<Option<Listener>>>,
+    /// Only the server has one of these, it is what lets clients encrypt their connection
+    identity: Option<Arc<ServerIdentity>>,
+    /// Throw away anything that isn't encrypted
+    require_secure: bool,
+    /// Only set when simulating a bad connection
+    conditioners: Option<Arc<Conditioners>>,
+    stats: Arc<Mutex<NetStats>>,
+}
+
+impl NetworkHandler {
+    pub fn new(
+        transport: Arc<dyn DatagramTransport>,
+        identity: Option<ServerIdentity>,
+        require_secure: bool,
+        conditions: Option<LinkConditions>,
+    ) -> Self {
+        let conditioners = conditions.filter(|x| x.is_enabled()).map(|conditions| {
+            warn!(?conditions, "Simulating a bad connection");
+            Arc::new(Conditioners {
+                outgoing: Mutex::new(LinkConditioner::new(conditions.clone(), 0)),
+                incoming: Mutex::new(LinkConditioner::new(conditions, 1)),
+            })
+        });
+
+        Self {
+            transport,
+            channels: Default::default(),
+            closing: Default::default(),
+            unconnected: Default::default(),
+            tick: Default::default(),
+            listener: Default::default(),
+            identity: identity.map(Arc::new),
+            require_secure,
+            conditioners,
+            stats: Default::default(),
+        }
+    }
+
+    /// Everything we send goes through here, so the link conditioner gets a say and it gets
+    /// counted in the [NetStats].
+    fn send_datagram(&self, endpoint: Endpoint, datagram: &[u8]) {
+        self.stats
+            .lock()
+            .unwrap()
+            .endpoint(endpoint)
+            .sent
+            .add(datagram.len());
+        match &self.conditioners {
+            Some(conditioners) => conditioners
+                .outgoing
+                .lock()
+                .unwrap()
+                .push((endpoint, datagram.to_vec()), Instant::now()),
+            None => self.transport.send(endpoint, datagram),
+        }
+    }
+
+    fn new_channel(&self) -> ReliableChannel {
+        match self.require_secure {
+            true => ReliableChannel::secure_only(),
+            false => ReliableChannel::default(),
+        }
+    }
+
+    /// Start encrypting the connection to the server. Anything sent before the server answers is
+    /// dropped, or resent afterwards if it was reliable.
+    pub fn start_handshake(&self, endpoint: Endpoint) {
+        let mut channel = ReliableChannel::connecting(ClientHandshake::default());
+        for datagram in channel.resend(Instant::now()) {
+            self.send_datagram(endpoint, &datagram);
+        }
+        self.channels.lock().unwrap().insert(endpoint, channel);
+    }
+
+    /// Set the simulation tick that gets sent along with every batch from now on.
+    pub fn set_tick(&self, tick: u64) {
+        *self.tick.lock().unwrap() = Some(tick);
+    }
+
+    fn send(&self, endpoint: Endpoint, reliability: Reliability, payload: Vec<u8>) {
+        let datagrams = self
+            .channels
+            .lock()
+            .unwrap()
+            .entry(endpoint)
+            .or_insert_with(|| self.new_channel())
+            .wrap(reliability, payload, Instant::now());
+
+        for datagram in datagrams {
+            self.send_datagram(endpoint, &datagram);
+        }
+    }
+
+    /// Returns everyone we gave up on, because they stopped acking what we send them.
+    fn resend_unacked(&self) -> Vec<Endpoint> {
+        let now = Instant::now();
+        let mut channels = self.channels.lock().unwrap();
+        for (endpoint, channel) in channels.iter_mut() {
+            let datagrams = channel.resend(now);
+            self.stats.lock().unwrap().endpoint(*endpoint).resent += datagrams.len() as u64;
+            for datagram in datagrams {
+                self.send_datagram(*endpoint, &datagram);
+            }
+        }
+
+        let mut lost = vec![];
+        channels.retain(|endpoint, channel| {
+            if !channel.is_dead(now) {
+                return true;
+            }
+            warn!(?endpoint, "Nothing we sent got acked, giving up on them");
+            self.stats.lock().unwrap().endpoints.remove(endpoint);
+            lost.push(*endpoint);
+            false
+        });
+
+        self.closing.lock().unwrap().retain(|endpoint, give_up_at| {
+            let done = channels.get(endpoint).is_none_or(|x| x.unacked_len() == 0);
+            if done || now >= *give_up_at {
+                channels.remove(endpoint);
+                self.stats.lock().unwrap().endpoints.remove(endpoint);
+                return false;
+            }
+            true
+        });
+
+        self.unconnected
+            .lock()
+            .unwrap()
+            .retain(|endpoint, give_up_at| {
+                if now < *give_up_at {
+                    return true;
+                }
+                debug!(?endpoint, "Never got let in, dropping their channel");
+                channels.remove(endpoint);
+                self.stats.lock().unwrap().endpoints.remove(endpoint);
+                false
+            });
+
+        lost
+    }
+
+    /// Process a datagram from the peer, answering acks and handshakes. Returns the payloads that
+    /// are ready to be decoded.
+    fn receive(&self, endpoint: Endpoint, data: &[u8]) -> Result<Vec<Vec<u8>>, InvalidDatagram> {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = match channels.entry(endpoint) {
+            Entry::Occupied(x) => x.into_mut(),
+            Entry::Vacant(x) => {
+                self.unconnected
+                    .lock()
+                    .unwrap()
+                    .insert(endpoint, Instant::now() + CONNECT_TIMEOUT);
+                x.insert(self.new_channel())
+            }
+        };
+        let received = channel.receive(data, Instant::now());
+
+        {
+            let mut stats = self.stats.lock().unwrap();
+            let stats = stats.endpoint(endpoint);
+            stats.received.add(data.len());
+            if received.is_err() {
+                stats.invalid += 1;
+            }
+        }
+        let received = received?;
+
+        match received.handshake {
+            Some(Handshake::Hello { public }) => {
+                let Some(identity) = &self.identity else {
+                    warn!(?endpoint, "Got a hello, but we are not a server");
+                    return Ok(vec![]);
+                };
+                if let Some(reply) = channel.accept_handshake(identity, public) {
+                    self.send_datagram(endpoint, &reply);
+                }
+            }
+            Some(Handshake::Reply { server, public }) => {
+                if channel.is_secure() {
+                    // A duplicate of a reply we already used
+                } else if !secure::trust_server(&endpoint.addr().to_string(), server) {
+                    error!(
+                        ?endpoint,
+                        "Not finishing the handshake with an unknown server"
+                    );
+                } else if channel.finish_handshake(server, public) {
+                    info!(?endpoint, "Connection is encrypted");
+                } else {
+                    warn!(?endpoint, "Got a bad handshake reply");
+                }
+            }
+            None => {}
+        }
+
+        if let Some(ack) = received.ack {
+            self.send_datagram(endpoint, &ack);
+        }
+        Ok(received.payloads)
+    }
+
+    /// Count an event we are about to send, or just decoded
+    fn count_event<T: NetworkingEvent>(&self, event: &T, sent: bool) {
+        let size = postcard::experimental::serialized_size(event).unwrap_or_default();
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.event(event.name());
+        match sent {
+            true => stats.sent.add(size),
+            false => stats.received.add(size),
+        }
+    }
+
+    /// Everything sent and received so far, with the current RTT of everyone we are talking to.
+    pub fn stats(&self) -> NetStats {
+        let channels = self.channels.lock().unwrap();
+        let mut stats = self.stats.lock().unwrap().clone();
+        for (endpoint, stats) in stats.endpoints.iter_mut() {
+            stats.rtt = channels.get(endpoint).and_then(|x| x.rtt());
+        }
+        stats
+    }
+
+    fn all_acked(&self) -> bool {
+        self.channels
+            .lock()
+            .unwrap()
+            .values()
+            .all(|x| x.unacked_len() == 0)
+    }
+
+    /// Keep talking to someone who started talking to us. Anyone this isn't called for within
+    /// [CONNECT_TIMEOUT] is forgotten, so call it once they are in the game.
+    pub fn mark_connected(&self, endpoint: Endpoint) {
+        self.unconnected.lock().unwrap().remove(&endpoint);
+    }
+
+    /// Drop all reliability state for this endpoint once everything we sent them has been acked,
+    /// so a goodbye sent right before this still gets there. Call this when a client goes away so
+    /// a new connection from the same address starts fresh.
+    pub fn forget(&self, endpoint: Endpoint) {
+        self.closing
+            .lock()
+            .unwrap()
+            .insert(endpoint, Instant::now() + SHUTDOWN_TIMEOUT);
+    }
+
+    /// Stop the listener thread once everything we sent has been acked, or [SHUTDOWN_TIMEOUT]
+    /// has passed. This blocks until then, so it is safe to exit right after.
+    pub fn shutdown(&self) {
+        let Some((node, listener)) = self.listener.lock().unwrap().take() else {
+            return;
+        };
+
+        node.signals()
+            .send(NetSignal::Shutdown(Instant::now() + SHUTDOWN_TIMEOUT));
+        if listener.join().is_err() {
+            error!("Network listener thread panicked");
+        }
+    }
+
+    /// Stop sending and receiving right away, without waiting for anything to be acked.
+    pub fn stop(&self) {
+        self.transport.stop();
+    }
 }
 
 #[derive(Resource, Clone)]
 pub struct MainServerEndpoint(pub Endpoint);
 
+/// How we talk to the other side. Everything but UDP is for networks that block or mangle UDP,
+/// and for clients that can't use it at all, like browsers.
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, Debug, Default)]
+pub enum NetTransport {
+    #[default]
+    Udp,
+    Tcp,
+    WebSocket,
+}
+
+impl From<NetTransport> for Transport {
+    fn from(value: NetTransport) -> Self {
+        match value {
+            NetTransport::Udp => Transport::Udp,
+            // Plain tcp is a stream, we need to know where each message ends
+            NetTransport::Tcp => Transport::FramedTcp,
+            NetTransport::WebSocket => Transport::Ws,
+        }
+    }
+}
+
+impl FromStr for NetTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "udp" => Ok(Self::Udp),
+            "tcp" => Ok(Self::Tcp),
+            "ws" => Ok(Self::WebSocket),
+            other => Err(format!(
+                "Unknown transport {other:?}, expected udp, tcp or ws"
+            )),
+        }
+    }
+}
+
 /// This type is only used for the inital connection, and then it is removed.
-#[derive(Resource, Debug)]
+#[derive(Reflect, Resource, Clone, Deserialize, Serialize, Debug)]
 pub struct NetworkConnectionTarget {
     pub ip: String,
     pub port: u16,
+    #[serde(default)]
+    pub transport: NetTransport,
 }
 
+/// Everything the server should accept connections on. Removed once the server is listening.
+#[derive(Resource, Debug)]
+pub struct NetworkListenTargets(pub Vec<NetworkConnectionTarget>);
+
+/// Where the server actually ended up listening, in the same order as the
+/// [NetworkListenTargets]. Mostly useful when listening on port 0.
+#[derive(Resource, Debug)]
+pub struct NetworkListenAddrs(pub Vec<SocketAddr>);
+
 pub use crate::event::client::EventToClient;
 pub use crate::event::server::EventToServer;
+use crate::{
+    event::{
+        client::{self, ConnectRejected},
+        server::{self, ConnectRequest},
+        EventFromEndpoint, PROTOCOL_VERSION,
+    },
+    Config,
+};
 
 pub trait NetworkingEvent:
     Clone + Serialize + for<'de> Deserialize<'de> + Send + 'static + core::fmt::Debug
 {
+    fn reliability(&self) -> Reliability;
+
+    /// Which event this is, for [stats::NetStats]
+    fn name(&self) -> &'static str;
+
+    /// Called when a payload could not be decoded, which usually means the other side was built
+    /// with a different [PROTOCOL_VERSION]. Returns the handshake event hidden inside, if any.
+    fn from_other_version(payload: &[u8]) -> Option<Self>;
+
+    /// Unwrap a [rpc] request or response, returning the id it was sent with
+    fn into_rpc(self) -> (Self, Option<RequestId>);
+
+    /// Handed to the game when the other side stops acking, see [ReliableChannel::is_dead]
+    fn connection_lost() -> Self;
 }
-impl NetworkingEvent for EventToServer {}
-impl NetworkingEvent for EventToClient {}
 
+/// Postcard encodes enums by their variant index, so this can decode the first variant of any
+/// event enum without knowing what the rest of that enum looks like.
+#[derive(Deserialize)]
+enum FirstVariant<H> {
+    First(H),
+}
+
+/// Decode the start of a single event, ignoring whatever comes after.
+fn decode_first_event<H: for<'de> Deserialize<'de>>(payload: &[u8]) -> Option<H> {
+    match postcard::take_from_bytes::<EventGroupingOwned<FirstVariant<H>>>(payload) {
+        Ok((EventGroupingOwned::Single(FirstVariant::First(h)), _rest)) => Some(h),
+        _ => None,
+    }
+}
+
+impl NetworkingEvent for EventToServer {
+    fn reliability(&self) -> Reliability {
+        match self {
+            // The client already resends these until it gets a response.
+            EventToServer::ConnectRequest(_) => Reliability::Unreliable,
+            EventToServer::Heartbeat(_) => Reliability::Unreliable,
+            EventToServer::ChangeMovement(_) => Reliability::Unreliable,
+            // Only the newest ack matters
+            EventToServer::SnapshotAck(_) => Reliability::Unreliable,
+            EventToServer::Request(x) => x.event.reliability(),
+            _ => Reliability::ReliableOrdered,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            EventToServer::Request(x) => x.event.name(),
+            _ => EventToServer::name(self),
+        }
+    }
+
+    fn from_other_version(payload: &[u8]) -> Option<Self> {
+        // The version is the first field of the first event
+        let protocol_version = decode_first_event::<u64>(payload)?;
+        if protocol_version == PROTOCOL_VERSION {
+            return None;
+        }
+
+        Some(EventToServer::ConnectRequest(ConnectRequest {
+            protocol_version,
+            name: None,
+            my_location: Transform::default(),
+            resume: None,
+            password: None,
+        }))
+    }
+
+    fn into_rpc(self) -> (Self, Option<RequestId>) {
+        match self {
+            EventToServer::Request(x) => (*x.event, Some(x.id)),
+            x => (x, None),
+        }
+    }
+
+    fn connection_lost() -> Self {
+        EventToServer::Disconnect(server::Disconnect {
+            reason: "Stopped acking".into(),
+        })
+    }
+}
+
+impl NetworkingEvent for EventToClient {
+    fn reliability(&self) -> Reliability {
+        match self {
+            EventToClient::SomeoneMoved(_) => Reliability::Unreliable,
+            // Lost snapshots are covered by the next delta
+            EventToClient::WorldSnapshot(_) => Reliability::Unreliable,
+            EventToClient::YourMovementResult(_) => Reliability::Unreliable,
+            // A late answer is worse than none for measuring the RTT
+            EventToClient::HeartbeatResponse(_) => Reliability::Unreliable,
+            // Hits only make sense for bullets we know about, but order between them is not
+            // important.
+            EventToClient::BulletHit(_) => Reliability::ReliableUnordered,
+            EventToClient::Response(x) => x.event.reliability(),
+            _ => Reliability::ReliableOrdered,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            EventToClient::Response(x) => x.event.name(),
+            _ => EventToClient::name(self),
+        }
+    }
+
+    fn from_other_version(payload: &[u8]) -> Option<Self> {
+        decode_first_event::<ConnectRejected>(payload).map(EventToClient::ConnectRejected)
+    }
+
+    fn into_rpc(self) -> (Self, Option<RequestId>) {
+        match self {
+            EventToClient::Response(x) => (*x.event, Some(x.id)),
+            x => (x, None),
+        }
+    }
+
+    fn connection_lost() -> Self {
+        EventToClient::Disconnect(client::Disconnect {
+            reason: "The server stopped answering".into(),
+        })
+    }
+}
+
+/// `Single` has to stay first and unchanged, see [NetworkingEvent::from_other_version].
 #[derive(Deserialize)]
 pub enum EventGroupingOwned<T> {
     Single(T),
-    Batch(Vec<T>),
+    Batch { tick: Option<u64>, events: Vec<T> },
 }
 
 #[derive(Serialize)]
 pub enum EventGroupingRef<'a, T> {
     Single(&'a T),
-    Batch(&'a [T]),
+    /// `tick` is the server tick the batch was sent on, so the client can put them in order.
+    Batch {
+        tick: Option<u64>,
+        events: &'a [T],
+    },
 }
 
 pub fn send_event_to_server<T: NetworkingEvent>(
-    handler: &NodeHandler<()>,
+    handler: &NetworkHandler,
     endpoint: Endpoint,
     event: &T,
 ) {
     trace!(?event, "Sending event");
-    handler.network().send(
+    handler.count_event(event, true);
+    handler.send(
         endpoint,
-        &postcard::to_stdvec(&EventGroupingRef::Single(event)).unwrap(),
+        event.reliability(),
+        postcard::to_stdvec(&EventGroupingRef::Single(event)).unwrap(),
     );
 }
 
+/// The events are packed into as few datagrams as they fit in, see [pack_batches].
 pub fn send_event_to_server_batch<T: NetworkingEvent>(
-    handler: &NodeHandler<()>,
+    handler: &NetworkHandler,
     endpoint: Endpoint,
     event: &[T],
 ) {
     trace!(?event, "Sending batch event");
-    handler.network().send(
-        endpoint,
-        &postcard::to_stdvec(&EventGroupingRef::Batch(event)).unwrap(),
-    );
+    for x in event {
+        handler.count_event(x, true);
+    }
+    let tick = *handler.tick.lock().unwrap();
+    for (reliability, events) in pack_batches(event) {
+        handler.send(
+            endpoint,
+            reliability,
+            postcard::to_stdvec(&EventGroupingRef::Batch {
+                tick,
+                events: &events,
+            })
+            .unwrap(),
+        );
+    }
 }
 
-pub fn setup_server<T: NetworkingEvent>(commands: Commands, config: Res<NetworkConnectionTarget>) {
-    setup_shared::<T>(commands, &config.ip, config.port, true);
+/// Split events into batches that each fit in a single datagram. Events are only batched with
+/// others of the same [Reliability], so a lost movement update never makes us resend a chat
+/// message. Events keep their order within each reliability. An event too big to fit anywhere
+/// gets a batch of its own, which will be fragmented.
+pub fn pack_batches<T: NetworkingEvent>(events: &[T]) -> Vec<(Reliability, Vec<&T>)> {
+    // (reliability, size so far, events)
+    let mut batches: Vec<(Reliability, usize, Vec<&T>)> = vec![];
+    // The batch we are currently filling for each reliability
+    let mut filling: HashMap<Reliability, usize> = HashMap::new();
+
+    for event in events {
+        let reliability = event.reliability();
+        let size = postcard::to_stdvec(event).unwrap().len();
+        match filling.get(&reliability) {
+            Some(&i) if batches[i].1 + size <= MAX_BATCH_SIZE => {
+                batches[i].1 += size;
+                batches[i].2.push(event);
+            }
+            _ => {
+                filling.insert(reliability, batches.len());
+                batches.push((reliability, BATCH_HEADER_SIZE + size, vec![event]));
+            }
+        }
+    }
+
+    batches
+        .into_iter()
+        .map(|(reliability, _, events)| (reliability, events))
+        .collect()
 }
 
-pub fn setup_client<T: NetworkingEvent>(commands: Commands, config: Res<NetworkConnectionTarget>) {
-    setup_shared::<T>(commands, &config.ip, config.port, false);
+pub fn setup_server<T: NetworkingEvent>(
+    mut commands: Commands,
+    targets: Res<NetworkListenTargets>,
+    config: Res<Config>,
+    memory: Option<Res<MemoryNetwork>>,
+) {
+    let identity = ServerIdentity::load_or_generate_from_main_dir()
+        .expect("Could not load or create the server key");
+    let handler = setup_shared::<T>(
+        &mut commands,
+        true,
+        Some(identity),
+        &config,
+        memory.as_deref(),
+    );
+    commands.remove_resource::<NetworkListenTargets>();
+
+    let mut addrs = vec![];
+    for target in &targets.0 {
+        let addr = handler.transport.listen(target).unwrap();
+        info!(?addr, transport = ?target.transport, "Listening");
+        addrs.push(addr);
+    }
+    commands.insert_resource(NetworkListenAddrs(addrs));
 }
 
-pub fn setup_shared<T: NetworkingEvent>(
+pub fn setup_client<T: NetworkingEvent>(
     mut commands: Commands,
-    ip: &str,
-    port: u16,
-    is_listener: bool,
+    target: Res<NetworkConnectionTarget>,
+    config: Res<Config>,
+    memory: Option<Res<MemoryNetwork>>,
 ) {
-    info!(is_listener, "Seting up networking!");
+    let encrypt = config.encryption();
+    let handler = setup_shared::<T>(&mut commands, false, None, &config, memory.as_deref());
+    commands.remove_resource::<NetworkConnectionTarget>();
+
+    let (endpoint, addr) = handler.transport.connect(&target).unwrap();
+    if encrypt {
+        handler.start_handshake(endpoint);
+    }
+    commands.insert_resource(MainServerEndpoint(endpoint));
+    info!(?addr, transport = ?target.transport, encrypt, "Connected");
+}
+
+/// Start the listener thread and insert the [ServerResources] for it. The caller still has to
+/// listen or connect with the returned handler. With a [MemoryNetwork] there is no thread, the
+/// network delivers everything itself.
+pub fn setup_shared<T: NetworkingEvent>(
+    commands: &mut Commands,
+    is_listener: bool,
+    identity: Option<ServerIdentity>,
+    config: &Config,
+    memory: Option<&MemoryNetwork>,
+) -> NetworkHandler {
+    let require_secure = config.encryption();
+    info!(is_listener, require_secure, "Seting up networking!");
+
+    if let Some(memory) = memory {
+        let transport = memory.transport();
+        let res = ServerResources::<T> {
+            handler: NetworkHandler::new(
+                Arc::new(transport.clone()),
+                identity,
+                require_secure,
+                None,
+            ),
+            event_list: Default::default(),
+        };
+        memory.attach(&transport, res.clone());
+        commands.insert_resource(res.clone());
+        return res.handler;
+    }
 
-    let (handler, listener) = message_io::node::split::<()>();
+    let (handler, listener) = message_io::node::split::<NetSignal>();
 
     let res = ServerResources::<T> {
-        handler: handler.clone(),
+        handler: NetworkHandler::new(
+            Arc::new(handler.clone()),
+            identity,
+            require_secure,
+            config.link_conditions.clone(),
+        ),
         event_list: Default::default(),
     };
 
-    // insert the new endpoints and remove the connection data
+    // insert the new endpoints
     commands.insert_resource(res.clone());
-    commands.remove_resource::<NetworkConnectionTarget>();
 
     info!(
         "Setup server resources for {}",
         std::any::type_name::<ServerResources::<T>>()
     );
 
-    let con_str = (ip, port);
-    if is_listener {
-        let (_, addr) = handler.network().listen(Transport::Udp, con_str).unwrap();
-        info!(?addr, "Listening")
-    } else {
-        let (endpoint, addr) = handler.network().connect(Transport::Udp, con_str).unwrap();
-        commands.insert_resource(MainServerEndpoint(endpoint));
-        info!(?addr, "Connected");
+    handler
+        .signals()
+        .send_with_timer(NetSignal::ResendUnacked, RESEND_TICK);
+    if res.handler.conditioners.is_some() {
+        handler
+            .signals()
+            .send_with_timer(NetSignal::ReleaseConditioned, CONDITIONER_TICK);
     }
 
-    std::thread::spawn(move || {
-        listener.for_each(|event| on_node_event(&res, event));
+    let network_handler = res.handler.clone();
+    let node = handler.clone();
+    let thread = std::thread::spawn(move || {
+        listener.for_each(|event| on_node_event(&res, &node, event));
+        info!("Network listener stopped");
     });
+    *network_handler.listener.lock().unwrap() = Some((handler, thread));
+
+    network_handler
 }
 
-pub fn on_node_event<T: NetworkingEvent>(res: &ServerResources<T>, event: NodeEvent<'_, ()>) {
+pub fn on_node_event<T: NetworkingEvent>(
+    res: &ServerResources<T>,
+    node: &NodeHandler<NetSignal>,
+    event: NodeEvent<'_, NetSignal>,
+) {
     let net_event = match event {
         NodeEvent::Network(n) => n,
-        NodeEvent::Signal(_) => {
-            panic!("MESSAGE SERVER SHUTDOWN SIGNAL RECEIVED!!!");
-            // TODO graceful shutdown
+        NodeEvent::Signal(NetSignal::ResendUnacked) => {
+            resend_unacked(res);
+            node.signals()
+                .send_with_timer(NetSignal::ResendUnacked, RESEND_TICK);
+            return;
+        }
+        NodeEvent::Signal(NetSignal::Shutdown(deadline)) => {
+            if res.handler.all_acked() || Instant::now() >= deadline {
+                node.stop();
+            } else {
+                node.signals()
+                    .send_with_timer(NetSignal::Shutdown(deadline), SHUTDOWN_POLL);
+            }
+            return;
+        }
+        NodeEvent::Signal(NetSignal::ReleaseConditioned) => {
+            if let Some(conditioners) = &res.handler.conditioners {
+                let now = Instant::now();
+                let outgoing = conditioners.outgoing.lock().unwrap().pop_ready(now);
+                for (endpoint, datagram) in outgoing {
+                    res.handler.transport.send(endpoint, &datagram);
+                }
+                let incoming = conditioners.incoming.lock().unwrap().pop_ready(now);
+                for (endpoint, datagram) in incoming {
+                    on_datagram(res, endpoint, &datagram);
+                }
+            }
+            node.signals()
+                .send_with_timer(NetSignal::ReleaseConditioned, CONDITIONER_TICK);
+            return;
         }
     };
 
     match net_event {
-        NetEvent::Connected(endpoint, v) => info!(?endpoint, ?v, "Network Connected"),
+        NetEvent::Connected(endpoint, true) => info!(?endpoint, "Network Connected"),
+        // Only happens for tcp and websockets, udp has nothing to fail
+        NetEvent::Connected(endpoint, false) => error!(?endpoint, "Could not connect"),
         NetEvent::Accepted(endpoint, listener) => {
             info!(?endpoint, ?listener, "Connection Accepted")
         }
-        NetEvent::Message(endpoint, data) => {
-            let event: EventGroupingOwned<T> = match postcard::from_bytes(data) {
-                Ok(e) => e,
-                Err(p) => {
+        NetEvent::Message(endpoint, data) => match &res.handler.conditioners {
+            Some(conditioners) => conditioners
+                .incoming
+                .lock()
+                .unwrap()
+                .push((endpoint, data.to_vec()), Instant::now()),
+            None => on_datagram(res, endpoint, data),
+        },
+        NetEvent::Disconnected(endpoint) => {
+            warn!(?endpoint, "Client disconnected");
+            res.handler.forget(endpoint);
+        }
+    }
+}
+
+/// Resend whatever is due, and tell the game about everyone we gave up on
+fn resend_unacked<T: NetworkingEvent>(res: &ServerResources<T>) {
+    let lost = res.handler.resend_unacked();
+    res.event_list
+        .lock()
+        .unwrap()
+        .extend(lost.into_iter().map(|endpoint| EventFromEndpoint {
+            event: T::connection_lost(),
+            endpoint,
+            tick: None,
+            request: None,
+        }));
+}
+
+fn on_datagram<T: NetworkingEvent>(res: &ServerResources<T>, endpoint: Endpoint, data: &[u8]) {
+    let payloads = match res.handler.receive(endpoint, data) {
+        Ok(r) => r,
+        Err(p) => {
+            warn!(?endpoint, ?p, "Got an invalid datagram from endpoint");
+            return;
+        }
+    };
+
+    for payload in payloads {
+        let event: EventGroupingOwned<T> = match postcard::from_bytes(&payload) {
+            Ok(e) => e,
+            Err(p) => match T::from_other_version(&payload) {
+                Some(e) => {
+                    warn!(
+                        ?endpoint,
+                        ?p,
+                        "Got an event from a different protocol version"
+                    );
+                    EventGroupingOwned::Single(e)
+                }
+                None => {
                     warn!(?endpoint, ?p, "Got invalid json from endpoint");
-                    return;
+                    continue;
                 }
-            };
+            },
+        };
 
-            let mut list = res.event_list.lock().unwrap();
-            match event {
-                EventGroupingOwned::Single(x) => {
-                    let pair = (endpoint, x);
-                    list.push(pair);
-                }
-                EventGroupingOwned::Batch(events) => {
-                    list.extend(events.into_iter().map(|x| (endpoint, x)));
+        let (tick, events) = match event {
+            EventGroupingOwned::Single(x) => (None, vec![x]),
+            EventGroupingOwned::Batch { tick, events } => (tick, events),
+        };
+        let events: Vec<_> = events
+            .into_iter()
+            .map(|x| {
+                let (event, request) = x.into_rpc();
+                res.handler.count_event(&event, false);
+                EventFromEndpoint {
+                    event,
+                    endpoint,
+                    tick,
+                    request,
                 }
-            }
-        }
-        NetEvent::Disconnected(endpoint) => warn!(?endpoint, "Client disconnected"),
+            })
+            .collect();
+
+        res.event_list.lock().unwrap().extend(events);
     }
 }
@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Makes a perfect local connection behave like a bad one, so lag can be reproduced without a bad
+/// network. Every field applies to each direction separately.
+#[derive(clap::Args, Reflect, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LinkConditions {
+    /// Milliseconds added to every datagram
+    #[arg(long = "sim-latency", default_value_t = 0)]
+    pub latency_ms: u64,
+    /// Up to this many extra milliseconds, picked at random for every datagram
+    #[arg(long = "sim-jitter", default_value_t = 0)]
+    pub jitter_ms: u64,
+    /// Chance from 0 to 1 that a datagram is dropped
+    #[arg(long = "sim-loss", default_value_t = 0.0)]
+    pub loss: f64,
+    /// Chance from 0 to 1 that a datagram arrives twice
+    #[arg(long = "sim-duplicate", default_value_t = 0.0)]
+    pub duplicate: f64,
+    /// Chance from 0 to 1 that a datagram is held back until after the ones sent behind it
+    #[arg(long = "sim-reorder", default_value_t = 0.0)]
+    pub reorder: f64,
+    /// Makes the same traffic get dropped and delayed the same way every run
+    #[arg(long = "sim-seed")]
+    pub seed: Option<u64>,
+}
+
+impl LinkConditions {
+    /// Does this change anything at all?
+    pub fn is_enabled(&self) -> bool {
+        self.latency_ms > 0
+            || self.jitter_ms > 0
+            || self.loss > 0.0
+            || self.duplicate > 0.0
+            || self.reorder > 0.0
+    }
+}
+
+/// Holds on to datagrams until the [LinkConditions] say they should arrive.
+#[derive(Debug)]
+pub struct LinkConditioner<T> {
+    conditions: LinkConditions,
+    rng: StdRng,
+    /// (when it arrives, what arrives)
+    queue: Vec<(Instant, T)>,
+}
+
+impl<T: Clone> LinkConditioner<T> {
+    /// `stream` keeps two conditioners with the same seed, like the sending and receiving side,
+    /// from making the exact same choices.
+    pub fn new(conditions: LinkConditions, stream: u64) -> Self {
+        let rng = match conditions.seed {
+            Some(seed) => StdRng::seed_from_u64(seed ^ stream.rotate_left(32)),
+            None => StdRng::from_entropy(),
+        };
+
+        Self {
+            conditions,
+            rng,
+            queue: vec![],
+        }
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        self.rng.gen_bool(p.clamp(0.0, 1.0))
+    }
+
+    fn delay(&mut self) -> Duration {
+        let mut ms = self.conditions.latency_ms + self.rng.gen_range(0..=self.conditions.jitter_ms);
+        if self.chance(self.conditions.reorder) {
+            // Late enough that anything sent right after it gets there first
+            ms += self.conditions.latency_ms + self.conditions.jitter_ms + 1;
+        }
+        Duration::from_millis(ms)
+    }
+
+    pub fn push(&mut self, item: T, now: Instant) {
+        if self.chance(self.conditions.loss) {
+            return;
+        }
+
+        if self.chance(self.conditions.duplicate) {
+            let delay = self.delay();
+            self.queue.push((now + delay, item.clone()));
+        }
+        let delay = self.delay();
+        self.queue.push((now + delay, item));
+    }
+
+    /// Everything that should have arrived by `now`, in the order it arrives.
+    pub fn pop_ready(&mut self, now: Instant) -> Vec<T> {
+        // Stable, so datagrams due at the same time stay in the order they were sent
+        self.queue.sort_by_key(|(due, _)| *due);
+        let ready = self.queue.partition_point(|(due, _)| *due <= now);
+        self.queue.drain(..ready).map(|(_, item)| item).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
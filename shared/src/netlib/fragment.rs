@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::secure::SEAL_OVERHEAD;
+
+/// The biggest datagram we send. Anything bigger might not fit in a single packet on the way to
+/// the other side, and a UDP datagram that gets split up by IP is lost if any piece of it is.
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+
+/// How much of a fragment datagram is left for the data, after the fragment header and
+/// encryption.
+const FRAGMENT_SIZE: usize = MAX_DATAGRAM_SIZE - 32 - SEAL_OVERHEAD;
+
+/// Give up on a message if the rest of it doesn't show up in this long. Reliable messages get
+/// resent as a whole, so the old pieces are useless by then anyways.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Nobody has a reason to send a message bigger than this, so don't let them make us buffer it.
+const MAX_FRAGMENTS: u16 = 1024;
+
+/// How many messages from one peer we put back together at once. Starting another one throws
+/// away the oldest, which is most likely missing a piece that got lost.
+const MAX_PARTIALS: usize = 8;
+
+/// How many bytes of unfinished messages we hold on to for one peer, enough for two of the
+/// biggest ones. Going over throws away the oldest messages.
+const MAX_BUFFERED: usize = 2 * MAX_FRAGMENTS as usize * FRAGMENT_SIZE;
+
+/// One piece of a datagram that was too big to send on its own.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fragment {
+    /// Which message this is part of
+    message: u32,
+    index: u16,
+    count: u16,
+    bytes: Vec<u8>,
+}
+
+/// Cut `data` into fragments that each fit in a datagram.
+pub fn split(message: u32, data: &[u8]) -> Vec<Fragment> {
+    let count = data.len().div_ceil(FRAGMENT_SIZE) as u16;
+    data.chunks(FRAGMENT_SIZE)
+        .enumerate()
+        .map(|(index, bytes)| Fragment {
+            message,
+            index: index as u16,
+            count,
+            bytes: bytes.to_vec(),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Partial {
+    pieces: Vec<Option<Vec<u8>>>,
+    missing: u16,
+    /// Of the pieces we have
+    bytes: usize,
+    started: Instant,
+}
+
+/// Messages we have some, but not all, of the fragments for.
+#[derive(Debug, Default)]
+pub struct Reassembly {
+    partial: HashMap<u32, Partial>,
+}
+
+impl Reassembly {
+    /// Returns the whole message once the last fragment of it arrives.
+    pub fn insert(&mut self, fragment: Fragment, now: Instant) -> Option<Vec<u8>> {
+        self.partial
+            .retain(|_, x| now.duration_since(x.started) < REASSEMBLY_TIMEOUT);
+
+        if fragment.index >= fragment.count
+            || fragment.count > MAX_FRAGMENTS
+            || fragment.bytes.len() > FRAGMENT_SIZE
+        {
+            return None;
+        }
+
+        if !self.partial.contains_key(&fragment.message) {
+            while self.partial.len() >= MAX_PARTIALS && self.drop_oldest(fragment.message).is_some()
+            {
+            }
+        }
+        let buffered: usize = self.partial.values().map(|x| x.bytes).sum();
+        let mut over = (buffered + fragment.bytes.len()).saturating_sub(MAX_BUFFERED);
+        while over > 0 {
+            match self.drop_oldest(fragment.message) {
+                Some(bytes) => over = over.saturating_sub(bytes),
+                None => return None,
+            }
+        }
+
+        let partial = self
+            .partial
+            .entry(fragment.message)
+            .or_insert_with(|| Partial {
+                pieces: vec![None; fragment.count as usize],
+                missing: fragment.count,
+                bytes: 0,
+                started: now,
+            });
+        if partial.pieces.len() != fragment.count as usize {
+            return None;
+        }
+
+        let piece = &mut partial.pieces[fragment.index as usize];
+        if piece.is_none() {
+            partial.bytes += fragment.bytes.len();
+            *piece = Some(fragment.bytes);
+            partial.missing -= 1;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+
+        let partial = self.partial.remove(&fragment.message)?;
+        Some(partial.pieces.into_iter().flatten().flatten().collect())
+    }
+
+    /// Throw away the message we started on first, other than `keep`. Returns how many bytes
+    /// that freed up, or None if there was nothing else.
+    fn drop_oldest(&mut self, keep: u32) -> Option<usize> {
+        let oldest = *self
+            .partial
+            .iter()
+            .filter(|(message, _)| **message != keep)
+            .min_by_key(|(_, x)| x.started)?
+            .0;
+        self.partial.remove(&oldest).map(|x| x.bytes)
+    }
+}
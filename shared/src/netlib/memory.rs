@@ -0,0 +1,204 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Weak},
+};
+
+use bevy::prelude::*;
+use message_io::network::{Endpoint, ResourceId, ResourceType, Transport};
+
+use super::{
+    on_datagram, resend_unacked, transport::DatagramTransport, NetworkConnectionTarget,
+    NetworkingEvent, ServerResources,
+};
+
+/// A network that only exists in memory, for running a server and its clients in one process
+/// without sockets or threads. Insert a clone of the same one into every app before they set up
+/// their networking, and they will use it instead of message-io.
+///
+/// Nothing arrives until [MemoryNetwork::deliver] is called, so tests decide exactly when each
+/// side sees what the other sent. Link conditions are ignored.
+#[derive(Resource, Clone, Default)]
+pub struct MemoryNetwork(Arc<Mutex<MemoryNetworkInner>>);
+
+#[derive(Default)]
+struct MemoryNetworkInner {
+    /// Used for node ids and endpoints alike
+    next_id: usize,
+    nodes: BTreeMap<usize, MemoryNode>,
+    /// Which node is listening on each address
+    listeners: HashMap<SocketAddr, usize>,
+    /// For each endpoint, the node on the other end and the endpoint that node sees us as
+    routes: HashMap<Endpoint, (usize, Endpoint)>,
+    /// Everything sent but not delivered yet, in the order it was sent
+    in_flight: VecDeque<(usize, Endpoint, Vec<u8>)>,
+}
+
+/// Decodes a datagram from an endpoint into the right event list
+type Receive = Arc<dyn Fn(Endpoint, &[u8]) + Send + Sync>;
+
+/// Resends what is due, putting anyone given up on into the right event list
+type Resend = Arc<dyn Fn() + Send + Sync>;
+
+/// One app's networking
+struct MemoryNode {
+    receive: Receive,
+    resend: Resend,
+}
+
+impl MemoryNetworkInner {
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Endpoints are made up, but each one gets its own address so they are easy to tell apart
+    /// in logs and stats.
+    fn new_endpoint(&mut self) -> Endpoint {
+        let id = self.next_id();
+        Endpoint::from_listener(
+            local_udp_resource(id),
+            SocketAddr::from(([127, 0, 0, 1], id as u16)),
+        )
+    }
+}
+
+/// [Endpoint::from_listener] only takes ids of local resources on a transport without
+/// connections, like the ones a UDP listener gets. message-io doesn't let us generate those
+/// ourselves, so this lays them out the same way it does: the adapter in the low 7 bits, then
+/// a bit for local, then the rest of the id.
+fn local_udp_resource(id: usize) -> ResourceId {
+    let resource = ResourceId::from(id << 8 | 1 << 7 | Transport::Udp.id() as usize);
+    debug_assert_eq!(resource.resource_type(), ResourceType::Local);
+    debug_assert_eq!(resource.adapter_id(), Transport::Udp.id());
+    debug_assert_eq!(resource.base_value(), id);
+    resource
+}
+
+impl MemoryNetwork {
+    /// A transport for a new node, which gets nothing until it is [MemoryNetwork::attach]ed.
+    pub(super) fn transport(&self) -> MemoryTransport {
+        MemoryTransport {
+            network: Arc::downgrade(&self.0),
+            node: self.0.lock().unwrap().next_id(),
+        }
+    }
+
+    /// Start delivering whatever is sent to `transport` into the event list of `res`
+    pub(super) fn attach<T: NetworkingEvent>(
+        &self,
+        transport: &MemoryTransport,
+        res: ServerResources<T>,
+    ) {
+        let resend_res = res.clone();
+        let node = MemoryNode {
+            receive: Arc::new(move |endpoint, data| on_datagram(&res, endpoint, data)),
+            resend: Arc::new(move || resend_unacked(&resend_res)),
+        };
+        self.0.lock().unwrap().nodes.insert(transport.node, node);
+    }
+
+    /// Hand every datagram in flight to whoever it was sent to, including the acks and replies
+    /// that causes, until there is nothing left. Then let everyone resend what they have to, and
+    /// drop the endpoints they are done with. Events end up in the same event lists the listener
+    /// thread would put them in, ready for `drain_events`.
+    pub fn deliver(&self) {
+        loop {
+            let (receive, endpoint, datagram) = {
+                let mut network = self.0.lock().unwrap();
+                let Some((node, endpoint, datagram)) = network.in_flight.pop_front() else {
+                    break;
+                };
+                // Sent to someone who has stopped
+                let Some(node) = network.nodes.get(&node) else {
+                    continue;
+                };
+                (node.receive.clone(), endpoint, datagram)
+            };
+            receive(endpoint, &datagram);
+        }
+
+        let resends: Vec<_> = {
+            let network = self.0.lock().unwrap();
+            network.nodes.values().map(|x| x.resend.clone()).collect()
+        };
+        for resend in resends {
+            resend();
+        }
+    }
+}
+
+/// The [DatagramTransport] of one node on a [MemoryNetwork]
+#[derive(Clone)]
+pub struct MemoryTransport {
+    /// Weak, since the network holds on to our handler
+    network: Weak<Mutex<MemoryNetworkInner>>,
+    node: usize,
+}
+
+impl MemoryTransport {
+    fn with_network<R>(&self, f: impl FnOnce(&mut MemoryNetworkInner) -> R) -> Option<R> {
+        let network = self.network.upgrade()?;
+        let mut network = network.lock().unwrap();
+        Some(f(&mut network))
+    }
+}
+
+fn gone() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "The memory network is gone")
+}
+
+impl DatagramTransport for MemoryTransport {
+    /// Only the port is used, port 0 picks a free one.
+    fn listen(&self, target: &NetworkConnectionTarget) -> io::Result<SocketAddr> {
+        self.with_network(|network| {
+            let port = match target.port {
+                0 => network.next_id() as u16,
+                port => port,
+            };
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            if network.listeners.contains_key(&addr) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("Something is already listening on {addr}"),
+                ));
+            }
+            network.listeners.insert(addr, self.node);
+            Ok(addr)
+        })
+        .unwrap_or_else(|| Err(gone()))
+    }
+
+    fn connect(&self, target: &NetworkConnectionTarget) -> io::Result<(Endpoint, SocketAddr)> {
+        let addr = SocketAddr::from(([127, 0, 0, 1], target.port));
+        self.with_network(|network| {
+            let Some(&server) = network.listeners.get(&addr) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("Nothing is listening on {addr}"),
+                ));
+            };
+            let to_server = network.new_endpoint();
+            let to_client = network.new_endpoint();
+            network.routes.insert(to_server, (server, to_client));
+            network.routes.insert(to_client, (self.node, to_server));
+            Ok((to_server, addr))
+        })
+        .unwrap_or_else(|| Err(gone()))
+    }
+
+    fn send(&self, endpoint: Endpoint, datagram: &[u8]) {
+        self.with_network(|network| match network.routes.get(&endpoint) {
+            Some(&(node, from)) => network.in_flight.push_back((node, from, datagram.to_vec())),
+            None => warn!(?endpoint, "Sending to an endpoint that doesn't exist"),
+        });
+    }
+
+    fn stop(&self) {
+        self.with_network(|network| {
+            network.nodes.remove(&self.node);
+            network.listeners.retain(|_, node| *node != self.node);
+        });
+    }
+}
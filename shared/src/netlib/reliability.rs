@@ -0,0 +1,468 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    fragment::{self, Fragment, Reassembly, MAX_DATAGRAM_SIZE},
+    secure::{ClientHandshake, Handshake, Sealed, ServerIdentity, Session, SEAL_OVERHEAD},
+};
+
+/// How long we wait for an ack before sending a reliable message again.
+pub const RESEND_AFTER: Duration = Duration::from_millis(100);
+
+/// If something reliable goes this long without an ack, the other side is gone. See
+/// [ReliableChannel::is_dead]
+pub const GIVE_UP_AFTER: Duration = Duration::from_secs(10);
+
+/// How far past the oldest missing id or order we keep what the peer sends. Anything further ahead
+/// is dropped without an ack, so the peer sends it again once the gap is filled.
+pub const RECEIVE_WINDOW: u32 = 256;
+
+/// How many bytes of ordered payloads we hold on to while waiting for one sent before them. Like
+/// [RECEIVE_WINDOW], whatever doesn't fit is left for the peer to resend.
+const MAX_ORDER_BUFFERED: usize = 4 * 1024 * 1024;
+
+/// How much of the RTT estimate each new sample replaces, out of 8. Same as TCP.
+const RTT_SMOOTHING: u32 = 1;
+
+/// How hard we should try to get an event to the other side.
+///
+/// Ordered from weakest to strongest, so a batch can just take the `max` of its events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Reliability {
+    /// Fire and forget. Use this for things that are resent constantly anyways, like movement.
+    Unreliable,
+    /// Resent until acked, and given to the game as soon as it arrives.
+    ReliableUnordered,
+    /// Resent until acked, and only given to the game once everything sent before it has arrived.
+    ReliableOrdered,
+}
+
+/// This is what actually goes over the wire. The payload is an already encoded event grouping.
+///
+/// Only ever add new variants at the end, older versions need to be able to read the first two to
+/// find out they are out of date.
+#[derive(Debug, Serialize, Deserialize)]
+enum Datagram {
+    Unreliable(Vec<u8>),
+    Reliable {
+        id: u32,
+        /// Only set for [Reliability::ReliableOrdered]
+        order: Option<u32>,
+        payload: Vec<u8>,
+    },
+    Ack(Vec<u32>),
+    /// Part of an encoded [Datagram] that was bigger than [MAX_DATAGRAM_SIZE]
+    Fragment(Fragment),
+    /// Sets up encryption, see [super::secure]
+    Handshake(Handshake),
+    /// Any of the other variants, encrypted
+    Sealed(Sealed),
+}
+
+/// Why a datagram was thrown away
+#[derive(Debug)]
+pub enum InvalidDatagram {
+    Malformed(postcard::Error),
+    /// It failed to decrypt, which means someone changed it, or it is a replay
+    Tampered,
+    /// It should have been encrypted
+    Unencrypted,
+}
+
+impl From<postcard::Error> for InvalidDatagram {
+    fn from(value: postcard::Error) -> Self {
+        Self::Malformed(value)
+    }
+}
+
+/// Where a channel is with encryption
+#[derive(Debug, Default)]
+enum Security {
+    #[default]
+    Plain,
+    /// We sent a hello and are waiting for the reply. Nothing else gets sent until then.
+    Connecting {
+        handshake: ClientHandshake,
+        last_sent: Option<Instant>,
+    },
+    Established {
+        session: Session,
+        /// Server only. Their hello and our reply, in case the reply got lost.
+        answered: Option<([u8; 32], Vec<u8>)>,
+    },
+}
+
+#[derive(Debug)]
+struct Unacked {
+    datagram: Vec<u8>,
+    first_sent: Instant,
+    last_sent: Instant,
+    /// If it was, we can't tell which copy the ack is for, so it says nothing about the RTT
+    resent: bool,
+}
+
+/// The result of feeding a datagram into a [ReliableChannel].
+#[derive(Debug, Default)]
+pub struct Received {
+    /// Payloads that are ready to be given to the game, in the order they should be processed.
+    pub payloads: Vec<Vec<u8>>,
+    /// If set, this needs to be sent back to the peer so they stop resending.
+    pub ack: Option<Vec<u8>>,
+    /// Needs to be looked at by whoever owns the channel, see [ReliableChannel::accept_handshake]
+    /// and [ReliableChannel::finish_handshake].
+    pub handshake: Option<Handshake>,
+}
+
+/// Reliability state for a single remote endpoint.
+///
+/// This does no IO on its own: it only turns payloads into datagrams and datagrams back into
+/// payloads, so it can be driven by message-io or by a fake network in tests.
+#[derive(Debug, Default)]
+pub struct ReliableChannel {
+    next_id: u32,
+    next_send_order: u32,
+    unacked: BTreeMap<u32, Unacked>,
+
+    /// Every reliable id below this has already been received.
+    recv_floor: u32,
+    /// Reliable ids we have received that are above `recv_floor`.
+    recv_above: HashSet<u32>,
+    next_recv_order: u32,
+    /// Ordered payloads that arrived before something that was sent earlier.
+    recv_order_buffer: BTreeMap<u32, Vec<u8>>,
+    /// Total length of the payloads in `recv_order_buffer`
+    recv_order_bytes: usize,
+
+    next_fragmented: u32,
+    reassembly: Reassembly,
+
+    security: Security,
+    /// Refuse anything that isn't encrypted
+    require_secure: bool,
+
+    /// Smoothed time between sending a reliable datagram and getting its ack
+    rtt: Option<Duration>,
+}
+
+fn encode(datagram: &Datagram) -> Vec<u8> {
+    postcard::to_stdvec(datagram).unwrap()
+}
+
+impl ReliableChannel {
+    /// A channel that only accepts encrypted datagrams, for servers that don't allow anything else.
+    pub fn secure_only() -> Self {
+        Self {
+            require_secure: true,
+            ..Default::default()
+        }
+    }
+
+    /// A client channel that starts with a handshake, and only accepts encrypted datagrams. Nothing
+    /// is sent until the server replies, so call [ReliableChannel::resend] to get the hello out.
+    pub fn connecting(handshake: ClientHandshake) -> Self {
+        Self {
+            security: Security::Connecting {
+                handshake,
+                last_sent: None,
+            },
+            require_secure: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        matches!(self.security, Security::Established { .. })
+    }
+
+    /// Answer a client's [Handshake::Hello]. Returns the reply datagram to send back, if any.
+    pub fn accept_handshake(
+        &mut self,
+        identity: &ServerIdentity,
+        hello: [u8; 32],
+    ) -> Option<Vec<u8>> {
+        match &self.security {
+            Security::Plain => {}
+            // Our reply got lost, so they sent the same hello again
+            Security::Established {
+                answered: Some((answered, reply)),
+                ..
+            } if *answered == hello => return Some(reply.clone()),
+            // Don't let anyone swap out the keys of a connection that is already set up
+            _ => return None,
+        }
+
+        let (reply, session) = identity.accept(hello)?;
+        let reply = encode(&Datagram::Handshake(reply));
+        self.security = Security::Established {
+            session,
+            answered: Some((hello, reply.clone())),
+        };
+        Some(reply)
+    }
+
+    /// Finish the handshake with the server's [Handshake::Reply]. Only check the server's key
+    /// before calling this. Returns false if the reply was not usable.
+    pub fn finish_handshake(&mut self, server: [u8; 32], public: [u8; 32]) -> bool {
+        let Security::Connecting { handshake, .. } = &self.security else {
+            return false;
+        };
+        let Some(session) = handshake.finish(server, public) else {
+            return false;
+        };
+
+        self.security = Security::Established {
+            session,
+            answered: None,
+        };
+        // Anything reliable we tried to send before now goes out with the next resend
+        true
+    }
+
+    /// Encrypt a datagram if we can. Returns None if it can't be sent yet.
+    fn seal(&mut self, datagram: Vec<u8>) -> Option<Vec<u8>> {
+        match &mut self.security {
+            Security::Plain => Some(datagram),
+            Security::Connecting { .. } => None,
+            Security::Established { session, .. } => {
+                Some(encode(&Datagram::Sealed(session.seal(&datagram))))
+            }
+        }
+    }
+
+    /// Wrap an encoded payload into datagrams ready to be sent. This is only more than one
+    /// datagram if the payload has to be fragmented. Reliable payloads are remembered until the
+    /// peer acks them.
+    pub fn wrap(
+        &mut self,
+        reliability: Reliability,
+        payload: Vec<u8>,
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let order = match reliability {
+            Reliability::Unreliable => {
+                let datagram = encode(&Datagram::Unreliable(payload));
+                return self.fragment_and_seal(datagram);
+            }
+            Reliability::ReliableUnordered => None,
+            Reliability::ReliableOrdered => {
+                let order = self.next_send_order;
+                self.next_send_order += 1;
+                Some(order)
+            }
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let datagram = encode(&Datagram::Reliable { id, order, payload });
+        self.unacked.insert(
+            id,
+            Unacked {
+                datagram: datagram.clone(),
+                first_sent: now,
+                last_sent: now,
+                resent: false,
+            },
+        );
+
+        self.fragment_and_seal(datagram)
+    }
+
+    /// Split a datagram up if it is too big to send as is, and encrypt every piece. If any
+    /// fragment is lost the whole datagram is, so reliable ones are resent in full.
+    fn fragment_and_seal(&mut self, datagram: Vec<u8>) -> Vec<Vec<u8>> {
+        let datagrams = if datagram.len() <= MAX_DATAGRAM_SIZE - SEAL_OVERHEAD {
+            vec![datagram]
+        } else {
+            let message = self.next_fragmented;
+            self.next_fragmented = self.next_fragmented.wrapping_add(1);
+            fragment::split(message, &datagram)
+                .into_iter()
+                .map(|x| encode(&Datagram::Fragment(x)))
+                .collect()
+        };
+
+        datagrams.into_iter().filter_map(|x| self.seal(x)).collect()
+    }
+
+    /// Process a datagram from the peer.
+    pub fn receive(&mut self, data: &[u8], now: Instant) -> Result<Received, InvalidDatagram> {
+        let mut datagram = postcard::from_bytes(data)?;
+        match datagram {
+            Datagram::Handshake(handshake) => {
+                return Ok(Received {
+                    handshake: Some(handshake),
+                    ..Default::default()
+                })
+            }
+            Datagram::Sealed(sealed) => {
+                let Security::Established { session, .. } = &mut self.security else {
+                    return Err(InvalidDatagram::Tampered);
+                };
+                let opened = session.open(sealed).ok_or(InvalidDatagram::Tampered)?;
+                datagram = postcard::from_bytes(&opened)?;
+            }
+            _ if self.require_secure || !matches!(self.security, Security::Plain) => {
+                return Err(InvalidDatagram::Unencrypted);
+            }
+            _ => {}
+        }
+
+        if let Datagram::Fragment(fragment) = datagram {
+            let Some(whole) = self.reassembly.insert(fragment, now) else {
+                return Ok(Received::default());
+            };
+            datagram = postcard::from_bytes(&whole)?;
+        }
+
+        let mut received = Received::default();
+        match datagram {
+            Datagram::Unreliable(payload) => received.payloads.push(payload),
+            Datagram::Ack(ids) => {
+                for id in ids {
+                    match self.unacked.remove(&id) {
+                        Some(x) if !x.resent => {
+                            self.add_rtt_sample(now.saturating_duration_since(x.last_sent))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Datagram::Reliable { id, order, payload } => {
+                if !self.fits(id, order, payload.len()) {
+                    return Ok(received);
+                }
+
+                // Always ack, even if this is a duplicate: our last ack might have been lost.
+                received.ack = self.seal(encode(&Datagram::Ack(vec![id])));
+
+                if !self.mark_received(id) {
+                    return Ok(received);
+                }
+
+                match order {
+                    None => received.payloads.push(payload),
+                    Some(order) => {
+                        // Only a broken peer sends an order twice, so keep the first
+                        if order >= self.next_recv_order
+                            && !self.recv_order_buffer.contains_key(&order)
+                        {
+                            self.recv_order_bytes += payload.len();
+                            self.recv_order_buffer.insert(order, payload);
+                        }
+                        while let Some(payload) =
+                            self.recv_order_buffer.remove(&self.next_recv_order)
+                        {
+                            self.recv_order_bytes -= payload.len();
+                            received.payloads.push(payload);
+                            self.next_recv_order += 1;
+                        }
+                    }
+                }
+            }
+            // We never fragment fragments, or seal anything twice
+            Datagram::Fragment(_) | Datagram::Handshake(_) | Datagram::Sealed(_) => {}
+        }
+
+        Ok(received)
+    }
+
+    /// Whether we have room to keep a reliable datagram until everything before it arrives. Ones we
+    /// already have always fit, so they get acked again.
+    fn fits(&self, id: u32, order: Option<u32>, len: usize) -> bool {
+        if id < self.recv_floor || self.recv_above.contains(&id) {
+            return true;
+        }
+        if id >= self.recv_floor.saturating_add(RECEIVE_WINDOW) {
+            return false;
+        }
+        match order {
+            None => true,
+            Some(order) if order <= self.next_recv_order => true,
+            Some(order) => {
+                order < self.next_recv_order.saturating_add(RECEIVE_WINDOW)
+                    && self.recv_order_bytes + len <= MAX_ORDER_BUFFERED
+            }
+        }
+    }
+
+    /// Returns false if we have already seen this id.
+    fn mark_received(&mut self, id: u32) -> bool {
+        if id < self.recv_floor || !self.recv_above.insert(id) {
+            return false;
+        }
+
+        while self.recv_above.remove(&self.recv_floor) {
+            self.recv_floor += 1;
+        }
+
+        true
+    }
+
+    /// All the reliable datagrams that have waited at least [RESEND_AFTER] for an ack.
+    pub fn resend(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        if let Security::Connecting {
+            handshake,
+            last_sent,
+        } = &mut self.security
+        {
+            if last_sent.is_some_and(|x| now.duration_since(x) < RESEND_AFTER) {
+                return vec![];
+            }
+            *last_sent = Some(now);
+            return vec![encode(&Datagram::Handshake(handshake.hello()))];
+        }
+
+        let due: Vec<_> = self
+            .unacked
+            .values_mut()
+            .filter(|x| now.duration_since(x.last_sent) >= RESEND_AFTER)
+            .map(|x| {
+                x.last_sent = now;
+                x.resent = true;
+                x.datagram.clone()
+            })
+            .collect();
+
+        due.into_iter()
+            .flat_map(|x| self.fragment_and_seal(x))
+            .collect()
+    }
+
+    fn add_rtt_sample(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * (8 - RTT_SMOOTHING) + sample * RTT_SMOOTHING) / 8,
+            None => sample,
+        });
+    }
+
+    /// None until the peer acks something we only had to send once.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// How many reliable messages the peer has not acked yet.
+    pub fn unacked_len(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// How many reliable messages we are holding on to because something sent before them is
+    /// missing.
+    pub fn waiting_len(&self) -> usize {
+        self.recv_above.len() + self.recv_order_buffer.len()
+    }
+
+    /// Whether we have been resending something for [GIVE_UP_AFTER] without hearing back. Drop
+    /// the channel once it is, there is no point in resending forever.
+    pub fn is_dead(&self, now: Instant) -> bool {
+        // Ids only go up, so the first one is the oldest
+        self.unacked
+            .values()
+            .next()
+            .is_some_and(|x| now.duration_since(x.first_sent) >= GIVE_UP_AFTER)
+    }
+}
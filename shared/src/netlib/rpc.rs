@@ -0,0 +1,173 @@
+//! Requests the client sends the server expecting exactly one answer back.
+//!
+//! A request goes out wrapped in a [Request] with a fresh [RequestId], and the server answers
+//! with [respond], which wraps the answer in a [Response] with the same id. Both are unwrapped as
+//! soon as they arrive, so each side still gets a normal typed event, with
+//! [EventFromEndpoint::request] telling them which request it belongs to.
+//!
+//! The client keeps track of what it is waiting for in [PendingRequests]. Answers that come back
+//! after we stopped waiting are dropped, so every request ends with either its answer or a
+//! [RequestTimedOut], never both.
+
+use std::{collections::HashMap, marker::PhantomData, time::Duration};
+
+use bevy::prelude::*;
+use message_io::network::Endpoint;
+use serde::{Deserialize, Serialize};
+
+use super::{send_event_to_server, EventToClient, EventToServer, NetworkHandler, ServerResources};
+use crate::event::{
+    client::{Chat, Response, YourCastResult},
+    server::{Cast, Request, SendChat},
+    EventFromEndpoint,
+};
+
+/// Tells apart the requests one client has sent, so it knows which one an answer belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RequestId(pub u32);
+
+/// An event the client can send as a request, and what the server answers it with
+pub trait Rpc: Into<EventToServer> + Send + Sync + 'static {
+    type Response: Into<EventToClient>;
+
+    /// How long the client waits for the answer before giving up
+    const TIMEOUT: Duration;
+}
+
+impl Rpc for Cast {
+    type Response = YourCastResult;
+    const TIMEOUT: Duration = Duration::from_secs(2);
+}
+
+/// Only chat commands are sent as requests, normal chat is not answered.
+impl Rpc for SendChat {
+    type Response = Chat;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+}
+
+/// The server didn't answer the request with this id in time
+#[derive(Event, Debug)]
+pub struct RequestTimedOut<R> {
+    pub id: RequestId,
+    _request: PhantomData<R>,
+}
+
+impl<R> RequestTimedOut<R> {
+    fn new(id: RequestId) -> Self {
+        Self {
+            id,
+            _request: PhantomData,
+        }
+    }
+}
+
+/// Sends a [RequestTimedOut] of the right type
+type TimeOut = fn(&mut World, RequestId);
+
+fn time_out<R: Rpc>(world: &mut World, id: RequestId) {
+    world.send_event(RequestTimedOut::<R>::new(id));
+}
+
+/// Everything we have asked the server and not heard back about yet
+#[derive(Resource, Default)]
+pub struct PendingRequests {
+    next_id: u32,
+    /// When we give up on each one, in real seconds
+    waiting: HashMap<RequestId, (f64, TimeOut)>,
+}
+
+impl PendingRequests {
+    pub fn is_waiting(&self, id: RequestId) -> bool {
+        self.waiting.contains_key(&id)
+    }
+}
+
+/// Tracks requests for the client. Only add this once the client's events are registered.
+pub struct RpcPlugin;
+
+impl Plugin for RpcPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingRequests>()
+            .add_event::<RequestTimedOut<Cast>>()
+            .add_event::<RequestTimedOut<SendChat>>()
+            .add_systems(
+                Update,
+                (match_responses, time_out_requests)
+                    .before(crate::event::client::drain_events)
+                    .run_if(resource_exists::<ServerResources<EventToClient>>),
+            );
+    }
+}
+
+/// Send `request` to the server, returning the id its answer will come back with
+pub fn send_request<R: Rpc>(
+    handler: &NetworkHandler,
+    endpoint: Endpoint,
+    pending: &mut PendingRequests,
+    time: &Time<Real>,
+    request: R,
+) -> RequestId {
+    let id = RequestId(pending.next_id);
+    pending.next_id = pending.next_id.wrapping_add(1);
+    let deadline = time.elapsed_seconds_f64() + R::TIMEOUT.as_secs_f64();
+    pending.waiting.insert(id, (deadline, time_out::<R>));
+
+    let event = EventToServer::Request(Request {
+        id,
+        event: Box::new(request.into()),
+    });
+    send_event_to_server(handler, endpoint, &event);
+    id
+}
+
+/// Answer `request`. If the client sent it as a plain event, the answer is sent as a plain event
+/// too.
+pub fn respond<R: Rpc>(
+    handler: &NetworkHandler,
+    request: &EventFromEndpoint<R>,
+    response: R::Response,
+) {
+    let event = response.into();
+    let event = match request.request {
+        Some(id) => EventToClient::Response(Response {
+            id,
+            event: Box::new(event),
+        }),
+        None => event,
+    };
+    send_event_to_server(handler, request.endpoint, &event);
+}
+
+/// Stop waiting for whatever just got answered, and throw away answers we are not waiting for
+fn match_responses(sr: Res<ServerResources<EventToClient>>, mut pending: ResMut<PendingRequests>) {
+    sr.event_list.lock().unwrap().retain(|x| match x.request {
+        Some(id) => {
+            let waiting = pending.waiting.remove(&id).is_some();
+            if !waiting {
+                debug!(?id, event = x.event.name(), "Dropping a late answer");
+            }
+            waiting
+        }
+        None => true,
+    });
+}
+
+fn time_out_requests(world: &mut World) {
+    let now = world.resource::<Time<Real>>().elapsed_seconds_f64();
+    let mut timed_out = vec![];
+    world
+        .resource_mut::<PendingRequests>()
+        .waiting
+        .retain(|id, (deadline, time_out)| {
+            if *deadline > now {
+                return true;
+            }
+            timed_out.push((*id, *time_out));
+            false
+        });
+
+    for (id, time_out) in timed_out {
+        warn!(?id, "The server didn't answer a request");
+        time_out(world, id);
+    }
+}
@@ -0,0 +1,320 @@
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::HashMap, env::current_dir, fs::OpenOptions, io::Read, io::Write, path::Path,
+};
+
+use bevy::log::{error, info};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+
+/// How much bigger a datagram gets once it is sealed. The auth tag is 16 bytes, the rest is the
+/// nonce and the variant and length prefixes.
+pub const SEAL_OVERHEAD: usize = 32;
+
+/// How far behind the newest datagram we still accept one. Anything older is treated as a replay.
+const REPLAY_WINDOW: u64 = 64;
+
+/// Where the server keeps its key between restarts
+const SERVER_KEY_FILE: &str = "server.key";
+
+/// Every server we have connected to before, and the key it had then
+const KNOWN_SERVERS_FILE: &str = "known_servers.yaml";
+
+/// Key exchange messages, sent in the clear before anything else on an encrypted connection.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Handshake {
+    /// Client to server, with a key that is only used for this connection
+    Hello { public: [u8; 32] },
+    /// Server to client. `server` is the long term key the client should remember, `public` is
+    /// only used for this connection.
+    Reply { server: [u8; 32], public: [u8; 32] },
+}
+
+/// An encoded datagram, encrypted and authenticated with the [Session] from the handshake.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sealed {
+    nonce: u64,
+    bytes: Vec<u8>,
+}
+
+/// The long term key of a server, so clients can tell it is the same server as last time.
+pub struct ServerIdentity {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl ServerIdentity {
+    pub fn generate() -> Self {
+        Self::from_secret(StaticSecret::random_from_rng(rand::thread_rng()))
+    }
+
+    fn from_secret(secret: StaticSecret) -> Self {
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn public(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Load the key from [SERVER_KEY_FILE] in the current directory, or make a new one and save
+    /// it there.
+    pub fn load_or_generate_from_main_dir() -> std::io::Result<Self> {
+        let mut path = current_dir()?;
+        path.push(SERVER_KEY_FILE);
+        Self::load_or_generate(&path)
+    }
+
+    pub fn load_or_generate(path: &Path) -> std::io::Result<Self> {
+        match OpenOptions::new().read(true).open(path) {
+            Ok(mut file) => {
+                let mut bytes = [0; 32];
+                file.read_exact(&mut bytes)?;
+                info!(?path, "Loaded server key");
+                Ok(Self::from_secret(StaticSecret::from(bytes)))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let identity = Self::generate();
+                let mut options = OpenOptions::new();
+                options.create_new(true).write(true);
+                // Anyone who can read this can pretend to be us
+                #[cfg(unix)]
+                options.mode(0o600);
+                options.open(path)?.write_all(identity.secret.as_bytes())?;
+                info!(?path, "Generated a new server key");
+                Ok(identity)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Answer a client's [Handshake::Hello]. Returns the reply to send back and the session to
+    /// use from then on, or None if their key is no good.
+    pub fn accept(&self, hello: [u8; 32]) -> Option<(Handshake, Session)> {
+        let client = PublicKey::from(hello);
+        let ephemeral = StaticSecret::random_from_rng(rand::thread_rng());
+        let public = PublicKey::from(&ephemeral);
+
+        let session = Session::derive(
+            ephemeral.diffie_hellman(&client),
+            self.secret.diffie_hellman(&client),
+            &client,
+            &public,
+            false,
+        )?;
+        let reply = Handshake::Reply {
+            server: self.public(),
+            public: public.to_bytes(),
+        };
+        Some((reply, session))
+    }
+}
+
+/// A client waiting for the server to answer its [Handshake::Hello]
+pub struct ClientHandshake {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Default for ClientHandshake {
+    fn default() -> Self {
+        let secret = StaticSecret::random_from_rng(rand::thread_rng());
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+}
+
+impl std::fmt::Debug for ClientHandshake {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientHandshake").finish_non_exhaustive()
+    }
+}
+
+impl ClientHandshake {
+    pub fn hello(&self) -> Handshake {
+        Handshake::Hello {
+            public: self.public.to_bytes(),
+        }
+    }
+
+    /// Returns None if the server's keys are no good
+    pub fn finish(&self, server: [u8; 32], public: [u8; 32]) -> Option<Session> {
+        let server = PublicKey::from(server);
+        let public = PublicKey::from(public);
+        Session::derive(
+            self.secret.diffie_hellman(&public),
+            self.secret.diffie_hellman(&server),
+            &self.public,
+            &public,
+            true,
+        )
+    }
+}
+
+/// Keys for one encrypted connection, one for each direction so the nonces never collide.
+pub struct Session {
+    sealer: ChaCha20Poly1305,
+    opener: ChaCha20Poly1305,
+    next_nonce: u64,
+    replay: ReplayWindow,
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("next_nonce", &self.next_nonce)
+            .field("replay", &self.replay)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Session {
+    /// Mixing in the server's long term key means only whoever holds it can read what the client
+    /// sends, so checking that key with [trust_server] is enough to know who we are talking to.
+    fn derive(
+        ephemeral: SharedSecret,
+        long_term: SharedSecret,
+        client: &PublicKey,
+        server: &PublicKey,
+        is_client: bool,
+    ) -> Option<Self> {
+        // Someone sent a key that makes the shared secret predictable
+        if !ephemeral.was_contributory() || !long_term.was_contributory() {
+            return None;
+        }
+
+        let mut material = Vec::with_capacity(128);
+        material.extend_from_slice(ephemeral.as_bytes());
+        material.extend_from_slice(long_term.as_bytes());
+        material.extend_from_slice(client.as_bytes());
+        material.extend_from_slice(server.as_bytes());
+        let master = blake3::derive_key("crate netlib session 2024-05 master", &material);
+
+        let to_server = blake3::derive_key("crate netlib session 2024-05 to server", &master);
+        let to_client = blake3::derive_key("crate netlib session 2024-05 to client", &master);
+        let (seal, open) = match is_client {
+            true => (to_server, to_client),
+            false => (to_client, to_server),
+        };
+
+        Some(Self {
+            sealer: ChaCha20Poly1305::new(Key::from_slice(&seal)),
+            opener: ChaCha20Poly1305::new(Key::from_slice(&open)),
+            next_nonce: 0,
+            replay: ReplayWindow::default(),
+        })
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Sealed {
+        let nonce = self.next_nonce;
+        self.next_nonce += 1;
+
+        let bytes = self
+            .sealer
+            .encrypt(&nonce_bytes(nonce), plaintext)
+            .expect("Encrypting can only fail if the message is gigabytes long");
+        Sealed { nonce, bytes }
+    }
+
+    /// Returns None if this was tampered with, sealed with a different key, or is a replay.
+    pub fn open(&mut self, sealed: Sealed) -> Option<Vec<u8>> {
+        if self.replay.too_old(sealed.nonce) {
+            return None;
+        }
+        let plaintext = self
+            .opener
+            .decrypt(&nonce_bytes(sealed.nonce), sealed.bytes.as_slice())
+            .ok()?;
+        // Only after decrypting, or anyone could mark nonces as used
+        self.replay.mark(sealed.nonce).then_some(plaintext)
+    }
+}
+
+fn nonce_bytes(nonce: u64) -> Nonce {
+    let mut bytes = [0; 12];
+    bytes[4..].copy_from_slice(&nonce.to_le_bytes());
+    *Nonce::from_slice(&bytes)
+}
+
+/// Which of the last [REPLAY_WINDOW] nonces we have already seen.
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: u64,
+    /// Bit `n` is set if we have seen `highest - n`
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn too_old(&self, nonce: u64) -> bool {
+        nonce < self.highest && self.highest - nonce >= REPLAY_WINDOW
+    }
+
+    /// Returns false if we have already seen this nonce.
+    fn mark(&mut self, nonce: u64) -> bool {
+        if nonce > self.highest {
+            let shift = nonce - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.highest = nonce;
+        }
+
+        let bit = 1 << (self.highest - nonce);
+        if self.seen & bit != 0 {
+            return false;
+        }
+        self.seen |= bit;
+        true
+    }
+}
+
+/// Remember the key of every server we connect to, and refuse to talk to one whose key changed.
+/// Someone in the middle would have to have been there the first time we connected, too.
+pub fn trust_server(addr: &str, key: [u8; 32]) -> bool {
+    let Ok(mut path) = current_dir() else {
+        return false;
+    };
+    path.push(KNOWN_SERVERS_FILE);
+
+    let mut known: HashMap<String, String> = OpenOptions::new()
+        .read(true)
+        .open(&path)
+        .ok()
+        .and_then(|file| serde_yaml::from_reader(file).ok())
+        .unwrap_or_default();
+
+    let key: String = key.iter().map(|x| format!("{x:02x}")).collect();
+    match known.get(addr) {
+        Some(known_key) if *known_key == key => true,
+        Some(_) => {
+            error!(
+                ?addr,
+                "The server's key changed since we last connected. If you expect this, remove it \
+                 from {KNOWN_SERVERS_FILE}"
+            );
+            false
+        }
+        None => {
+            info!(?addr, "First time connecting, remembering the server's key");
+            known.insert(addr.to_string(), key);
+            let saved = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|file| serde_yaml::to_writer(file, &known).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                error!(?path, ?e, "Could not save the server's key");
+            }
+            true
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    diagnostic::{
+        Diagnostic, DiagnosticMeasurement, DiagnosticPath, DiagnosticsStore, RegisterDiagnostic,
+    },
+    prelude::*,
+    time::common_conditions::on_timer,
+};
+use message_io::network::Endpoint;
+
+use super::{NetworkingEvent, ServerResources};
+
+/// How often the counters are turned into diagnostics
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A minute of measurements is plenty to graph
+const DIAGNOSTICS_HISTORY: usize = 60;
+
+/// Most endpoints that get diagnostics of their own at once. Anyone past this only counts towards
+/// the totals until someone leaves.
+pub const MAX_ENDPOINT_DIAGNOSTICS: usize = 64;
+
+/// The last part of the path of every diagnostic an endpoint gets
+const ENDPOINT_STATS: [&str; 6] = [
+    "rtt",
+    "loss",
+    "sent",
+    "received",
+    "packets_sent",
+    "packets_received",
+];
+
+/// Round trip time to whoever we are talking to in milliseconds, averaged if that is several
+/// clients.
+pub const NET_RTT: DiagnosticPath = DiagnosticPath::const_new("net/rtt");
+/// Percent of reliable datagrams that had to be resent
+pub const NET_LOSS: DiagnosticPath = DiagnosticPath::const_new("net/loss");
+/// Kilobits per second
+pub const NET_SENT: DiagnosticPath = DiagnosticPath::const_new("net/sent");
+/// Kilobits per second
+pub const NET_RECEIVED: DiagnosticPath = DiagnosticPath::const_new("net/received");
+pub const NET_PACKETS_SENT: DiagnosticPath = DiagnosticPath::const_new("net/packets_sent");
+pub const NET_PACKETS_RECEIVED: DiagnosticPath = DiagnosticPath::const_new("net/packets_received");
+
+/// How much went one way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    /// Datagrams for an endpoint, events for an event type
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl Traffic {
+    pub fn add(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes as u64;
+    }
+
+    /// What was added since `earlier`
+    pub fn since(&self, earlier: &Traffic) -> Traffic {
+        Traffic {
+            count: self.count.saturating_sub(earlier.count),
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+        }
+    }
+}
+
+/// Everything we know about the connection to one endpoint
+#[derive(Debug, Clone, Default)]
+pub struct EndpointStats {
+    pub sent: Traffic,
+    pub received: Traffic,
+    /// Reliable datagrams we had to send again because they were not acked in time
+    pub resent: u64,
+    /// Datagrams that were thrown away, see [super::reliability::InvalidDatagram]
+    pub invalid: u64,
+    pub rtt: Option<Duration>,
+}
+
+impl EndpointStats {
+    /// Percent of what we sent since `earlier` that had to be resent. This is the best guess we
+    /// have, since nothing tells us about lost unreliable datagrams.
+    pub fn loss_since(&self, earlier: &EndpointStats) -> f64 {
+        let sent = self.sent.since(&earlier.sent).count;
+        let resent = self.resent.saturating_sub(earlier.resent);
+        match sent {
+            0 => 0.0,
+            sent => (resent as f64 / sent as f64 * 100.0).min(100.0),
+        }
+    }
+}
+
+/// Sent and received counts for one type of event
+#[derive(Debug, Clone, Default)]
+pub struct EventStats {
+    pub sent: Traffic,
+    pub received: Traffic,
+}
+
+/// Counters for everything a [super::NetworkHandler] sent and received, since it was created.
+#[derive(Debug, Clone, Default)]
+pub struct NetStats {
+    pub endpoints: HashMap<Endpoint, EndpointStats>,
+    /// By [NetworkingEvent::name]. Event sizes don't include any of the headers.
+    pub events: HashMap<&'static str, EventStats>,
+}
+
+impl NetStats {
+    pub fn endpoint(&mut self, endpoint: Endpoint) -> &mut EndpointStats {
+        self.endpoints.entry(endpoint).or_default()
+    }
+
+    pub fn event(&mut self, name: &'static str) -> &mut EventStats {
+        self.events.entry(name).or_default()
+    }
+
+    /// Every endpoint added together, with the RTT averaged
+    pub fn total(&self) -> EndpointStats {
+        let mut total = EndpointStats::default();
+        let mut rtts = vec![];
+        for stats in self.endpoints.values() {
+            total.sent.count += stats.sent.count;
+            total.sent.bytes += stats.sent.bytes;
+            total.received.count += stats.received.count;
+            total.received.bytes += stats.received.bytes;
+            total.resent += stats.resent;
+            total.invalid += stats.invalid;
+            rtts.extend(stats.rtt);
+        }
+        if !rtts.is_empty() {
+            total.rtt = Some(rtts.iter().sum::<Duration>() / rtts.len() as u32);
+        }
+        total
+    }
+}
+
+/// Which endpoint the diagnostics under `net/endpoint/<slot>/` belong to, for the
+/// [ServerResources] of `T`. Bevy can't remove a diagnostic, so when an endpoint goes away its
+/// slot is handed to the next one instead of a new one piling up for every address we ever hear
+/// from.
+#[derive(Resource)]
+pub struct EndpointDiagnostics<T> {
+    slots: Vec<Option<Endpoint>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for EndpointDiagnostics<T> {
+    fn default() -> Self {
+        Self {
+            slots: vec![],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> EndpointDiagnostics<T> {
+    /// Where the diagnostics for `endpoint` are, if it has any. `stat` is the last part of one of
+    /// the `NET_*` paths, like `rtt`.
+    pub fn path(&self, endpoint: Endpoint, stat: &str) -> Option<DiagnosticPath> {
+        let slot = self.slots.iter().position(|x| *x == Some(endpoint))?;
+        Some(slot_path(slot, stat))
+    }
+
+    /// How many endpoints have diagnostics right now
+    pub fn len(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The slot of `endpoint`, giving it a free one if it doesn't have one yet
+    fn assign(&mut self, endpoint: Endpoint) -> Option<usize> {
+        if let Some(slot) = self.slots.iter().position(|x| *x == Some(endpoint)) {
+            return Some(slot);
+        }
+        if let Some(slot) = self.slots.iter().position(Option::is_none) {
+            self.slots[slot] = Some(endpoint);
+            return Some(slot);
+        }
+        if self.slots.len() < MAX_ENDPOINT_DIAGNOSTICS {
+            self.slots.push(Some(endpoint));
+            return Some(self.slots.len() - 1);
+        }
+        None
+    }
+
+    /// Free the slot of every endpoint that isn't `still_here`, wiping their last figures so
+    /// whoever gets it next doesn't start out with them.
+    fn release(&mut self, store: &mut DiagnosticsStore, still_here: impl Fn(&Endpoint) -> bool) {
+        for (slot, endpoint) in self.slots.iter_mut().enumerate() {
+            if endpoint.as_ref().is_some_and(|x| !still_here(x)) {
+                *endpoint = None;
+                for stat in ENDPOINT_STATS {
+                    if let Some(diagnostic) = store.get_mut(&slot_path(slot, stat)) {
+                        diagnostic.clear_history();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn slot_path(slot: usize, stat: &str) -> DiagnosticPath {
+    DiagnosticPath::new(format!("net/endpoint/{slot}/{stat}"))
+}
+
+/// Where the diagnostics for a single event type go. `stat` is the last part of the path, like
+/// `sent`.
+pub fn event_path(name: &str, stat: &str) -> DiagnosticPath {
+    DiagnosticPath::new(format!("net/event/{name}/{stat}"))
+}
+
+/// Records the [NetStats] of the [ServerResources] for `T` as bevy diagnostics, under `net/`.
+pub struct NetDiagnosticsPlugin<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for NetDiagnosticsPlugin<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: NetworkingEvent> Plugin for NetDiagnosticsPlugin<T> {
+    fn build(&self, app: &mut App) {
+        for (path, suffix) in [
+            (NET_RTT, "ms"),
+            (NET_LOSS, "%"),
+            (NET_SENT, "kbps"),
+            (NET_RECEIVED, "kbps"),
+            (NET_PACKETS_SENT, "/s"),
+            (NET_PACKETS_RECEIVED, "/s"),
+        ] {
+            app.register_diagnostic(diagnostic(path, suffix));
+        }
+        app.init_resource::<EndpointDiagnostics<T>>().add_systems(
+            Update,
+            record_diagnostics::<T>.run_if(on_timer(DIAGNOSTICS_INTERVAL)),
+        );
+    }
+}
+
+fn diagnostic(path: DiagnosticPath, suffix: &'static str) -> Diagnostic {
+    Diagnostic::new(path)
+        .with_suffix(suffix)
+        .with_max_history_length(DIAGNOSTICS_HISTORY)
+}
+
+/// Add a measurement, registering the diagnostic first if this is a new endpoint or event.
+fn measure(store: &mut DiagnosticsStore, path: DiagnosticPath, suffix: &'static str, value: f64) {
+    if store.get(&path).is_none() {
+        store.add(diagnostic(path.clone(), suffix));
+    }
+    if let Some(diagnostic) = store.get_mut(&path).filter(|x| x.is_enabled) {
+        diagnostic.add_measurement(DiagnosticMeasurement {
+            time: Instant::now(),
+            value,
+        });
+    }
+}
+
+fn kbps(traffic: &Traffic, secs: f64) -> f64 {
+    traffic.bytes as f64 * 8.0 / 1000.0 / secs
+}
+
+/// Measure everything in [EndpointStats] that is worth graphing. `path` says where each stat
+/// goes, given its name like `rtt`.
+fn measure_endpoint(
+    store: &mut DiagnosticsStore,
+    path: impl Fn(&str) -> DiagnosticPath,
+    now: &EndpointStats,
+    earlier: &EndpointStats,
+    secs: f64,
+) {
+    let sent = now.sent.since(&earlier.sent);
+    let received = now.received.since(&earlier.received);
+    if let Some(rtt) = now.rtt {
+        measure(store, path("rtt"), "ms", rtt.as_secs_f64() * 1000.0);
+    }
+    measure(store, path("loss"), "%", now.loss_since(earlier));
+    measure(store, path("sent"), "kbps", kbps(&sent, secs));
+    measure(store, path("received"), "kbps", kbps(&received, secs));
+    measure(store, path("packets_sent"), "/s", sent.count as f64 / secs);
+    measure(
+        store,
+        path("packets_received"),
+        "/s",
+        received.count as f64 / secs,
+    );
+}
+
+fn record_diagnostics<T: NetworkingEvent>(
+    sr: Option<Res<ServerResources<T>>>,
+    mut store: ResMut<DiagnosticsStore>,
+    mut slots: ResMut<EndpointDiagnostics<T>>,
+    mut last: Local<Option<(Instant, NetStats)>>,
+) {
+    let Some(sr) = sr else {
+        *last = None;
+        slots.release(&mut store, |_| false);
+        return;
+    };
+
+    let stats = sr.handler.stats();
+    let now = Instant::now();
+    let Some((then, earlier)) = last.replace((now, stats.clone())) else {
+        return;
+    };
+    let secs = now.duration_since(then).as_secs_f64().max(f64::EPSILON);
+
+    measure_endpoint(
+        &mut store,
+        |stat| DiagnosticPath::new(format!("net/{stat}")),
+        &stats.total(),
+        &earlier.total(),
+        secs,
+    );
+
+    // Before handing out slots, so anyone who left makes room for anyone new
+    slots.release(&mut store, |x| stats.endpoints.contains_key(x));
+    for (endpoint, now) in &stats.endpoints {
+        let Some(slot) = slots.assign(*endpoint) else {
+            continue;
+        };
+        let earlier = earlier.endpoints.get(endpoint).cloned().unwrap_or_default();
+        measure_endpoint(
+            &mut store,
+            |stat| slot_path(slot, stat),
+            now,
+            &earlier,
+            secs,
+        );
+    }
+
+    for (name, now) in &stats.events {
+        let earlier = earlier.events.get(name).cloned().unwrap_or_default();
+        let sent = now.sent.since(&earlier.sent);
+        let received = now.received.since(&earlier.received);
+        measure(
+            &mut store,
+            event_path(name, "sent"),
+            "kbps",
+            kbps(&sent, secs),
+        );
+        measure(
+            &mut store,
+            event_path(name, "received"),
+            "kbps",
+            kbps(&received, secs),
+        );
+        measure(
+            &mut store,
+            event_path(name, "count_sent"),
+            "/s",
+            sent.count as f64 / secs,
+        );
+        measure(
+            &mut store,
+            event_path(name, "count_received"),
+            "/s",
+            received.count as f64 / secs,
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::{io, net::SocketAddr};
+
+use message_io::{network::Endpoint, node::NodeHandler};
+
+use super::{NetSignal, NetworkConnectionTarget};
+
+/// What actually moves datagrams for a [super::NetworkHandler]. Reliability, encryption and the
+/// stats all sit on top of this, so they work the same whichever one is used.
+pub trait DatagramTransport: Send + Sync {
+    /// Start accepting connections, returning the address we ended up on
+    fn listen(&self, target: &NetworkConnectionTarget) -> io::Result<SocketAddr>;
+
+    /// Returns the endpoint to send to, and the address it resolved to
+    fn connect(&self, target: &NetworkConnectionTarget) -> io::Result<(Endpoint, SocketAddr)>;
+
+    fn send(&self, endpoint: Endpoint, datagram: &[u8]);
+
+    /// Stop delivering anything, without waiting for what is still in flight
+    fn stop(&self);
+}
+
+/// Real sockets, read by the listener thread started in [super::setup_shared]
+impl DatagramTransport for NodeHandler<NetSignal> {
+    fn listen(&self, target: &NetworkConnectionTarget) -> io::Result<SocketAddr> {
+        let con_str = (target.ip.as_str(), target.port);
+        let (_, addr) = self.network().listen(target.transport.into(), con_str)?;
+        Ok(addr)
+    }
+
+    fn connect(&self, target: &NetworkConnectionTarget) -> io::Result<(Endpoint, SocketAddr)> {
+        let con_str = (target.ip.as_str(), target.port);
+        self.network().connect(target.transport.into(), con_str)
+    }
+
+    fn send(&self, endpoint: Endpoint, datagram: &[u8]) {
+        self.network().send(endpoint, datagram);
+    }
+
+    fn stop(&self) {
+        NodeHandler::stop(self);
+    }
+}
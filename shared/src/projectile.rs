@@ -0,0 +1,145 @@
+//! Everything that flies, like [crate::skills::SkillKind::Shoot]. The client and server both run
+//! [Projectile::advance] every frame so they see it in the same place, but only the server decides
+//! what it hits.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{event::NetEntId, skills::SkillKind};
+
+/// Every unit's hitbox is a sphere this big around its transform
+pub const UNIT_RADIUS: f32 = 1.0;
+
+/// How a skill's projectile flies, as written in `skills.yaml`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectileInfo {
+    /// In units per second
+    pub speed: f32,
+    /// How far it goes before it disappears
+    pub range: f32,
+    /// How close to a unit's hitbox it has to get to hit them
+    pub radius: f32,
+    /// How high above a straight line it is halfway to where it lands. 0 flies straight.
+    #[serde(default)]
+    pub arc: f32,
+    /// Turns to follow whoever it was shot at
+    #[serde(default)]
+    pub homing: bool,
+    /// How many units it goes through before it stops at the next one
+    #[serde(default)]
+    pub pierce: u32,
+}
+
+impl ProjectileInfo {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let positive = [
+            ("speed", self.speed),
+            ("range", self.range),
+            ("radius", self.radius),
+        ];
+        for (name, x) in positive {
+            if !x.is_finite() || x <= 0.0 {
+                return Err(format!("projectile has a {name} of {x}"));
+            }
+        }
+        if !self.arc.is_finite() || self.arc < 0.0 {
+            return Err(format!("projectile has an arc of {}", self.arc));
+        }
+        Ok(())
+    }
+}
+
+/// Something in flight. It disappears once it has gone its whole range, or hit more units than it
+/// can go through.
+#[derive(Component, Debug, Clone)]
+pub struct Projectile {
+    /// The skill that fired it, for how much damage it does
+    pub skill: SkillKind,
+    pub info: ProjectileInfo,
+    /// Who it was shot at, which [ProjectileInfo::homing] ones follow
+    pub target: Option<NetEntId>,
+    /// Where it would be without the arc
+    ground: Vec3,
+    direction: Vec3,
+    /// How far it goes before the arc comes back down
+    flight: f32,
+    travelled: f32,
+    hits: Vec<NetEntId>,
+}
+
+impl Projectile {
+    /// Fired from `from` towards `towards`, landing there if it arcs
+    pub fn new(skill: SkillKind, info: ProjectileInfo, from: Vec3, towards: Vec3) -> Self {
+        Self {
+            skill,
+            info,
+            target: None,
+            ground: from,
+            direction: (towards - from).normalize_or_zero(),
+            flight: from.distance(towards).min(info.range),
+            travelled: 0.0,
+            hits: vec![],
+        }
+    }
+
+    /// Shot at a unit standing at `towards`
+    pub fn at_unit(
+        skill: SkillKind,
+        info: ProjectileInfo,
+        from: Vec3,
+        target: NetEntId,
+        towards: Vec3,
+    ) -> Self {
+        Self {
+            target: Some(target),
+            ..Self::new(skill, info, from, towards)
+        }
+    }
+
+    /// Fly for `delta` seconds. `target` is where [Projectile::target] is now, if they are still
+    /// around. Returns false once it has gone its whole range.
+    pub fn advance(&mut self, delta: f32, target: Option<Vec3>) -> bool {
+        let mut step = (self.info.speed * delta).min(self.info.range - self.travelled);
+
+        if let Some(target) = target.filter(|_| self.info.homing) {
+            let to_target = target - self.ground;
+            if let Some(direction) = to_target.try_normalize() {
+                self.direction = direction;
+            }
+            // Come down on them wherever they are now, and don't fly past them
+            self.flight = self.travelled + to_target.length();
+            step = step.min(to_target.length());
+        }
+
+        self.ground += self.direction * step;
+        self.travelled += step;
+        self.travelled < self.info.range
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        let t = if self.flight > 0.0 {
+            (self.travelled / self.flight).min(1.0)
+        } else {
+            1.0
+        };
+        self.ground + Vec3::Y * 4.0 * self.info.arc * t * (1.0 - t)
+    }
+
+    /// Whether it touches the hitbox of a unit standing at `unit`
+    pub fn touches(&self, unit: Vec3) -> bool {
+        self.translation().distance(unit) < self.info.radius + UNIT_RADIUS
+    }
+
+    pub fn has_hit(&self, unit: &NetEntId) -> bool {
+        self.hits.contains(unit)
+    }
+
+    /// It went into `unit`. Returns true once it can't go through anyone else.
+    pub fn hit(&mut self, unit: NetEntId) -> bool {
+        if !self.has_hit(&unit) {
+            self.hits.push(unit);
+        }
+        self.hits.len() > self.info.pierce as usize
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::{client::WorldSnapshot, NetEntId},
+    unit::{AttackIntention, MovementIntention},
+};
+
+/// How many snapshots each side remembers. If the client hasn't acked anything in this many
+/// snapshots, the server gives up on deltas and sends everything again.
+pub const SNAPSHOT_HISTORY: usize = 32;
+
+/// Positions are sent in 1/64ths of a unit
+const POSITION_SCALE: f32 = 64.0;
+
+/// What other clients need to know about a unit, squashed down so it is cheap to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitState {
+    pub position: [i32; 3],
+    /// Rotation around the y axis, where `u16::MAX` is a full turn
+    pub yaw: u16,
+    /// [MovementIntention] scaled so 127 is 1
+    pub intention: [i8; 2],
+    /// None if the unit has no [AttackIntention] at all
+    pub attacking: Option<bool>,
+}
+
+impl UnitState {
+    pub fn new(
+        transform: &Transform,
+        intention: &MovementIntention,
+        attack: Option<&AttackIntention>,
+    ) -> Self {
+        let quantize_pos = |x: f32| (x * POSITION_SCALE).round() as i32;
+        let quantize_intent = |x: f32| (x.clamp(-1.0, 1.0) * 127.0).round() as i8;
+
+        let (yaw, _, _) = transform.rotation.to_euler(EulerRot::YXZ);
+        Self {
+            position: transform.translation.to_array().map(quantize_pos),
+            yaw: (yaw.rem_euclid(std::f32::consts::TAU) / std::f32::consts::TAU * u16::MAX as f32)
+                .round() as u16,
+            intention: intention.0.to_array().map(quantize_intent),
+            attacking: attack.map(|x| matches!(x, AttackIntention::AutoAttack(_))),
+        }
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        Vec3::from_array(self.position.map(|x| x as f32 / POSITION_SCALE))
+    }
+
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw as f32 / u16::MAX as f32 * std::f32::consts::TAU)
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.translation()).with_rotation(self.rotation())
+    }
+
+    pub fn intention(&self) -> Vec2 {
+        Vec2::from_array(self.intention.map(|x| x as f32 / 127.0))
+    }
+}
+
+/// The fields of a unit that changed since the baseline. A unit the baseline does not know about
+/// has every field set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitDelta {
+    pub id: NetEntId,
+    pub position: Option<[i32; 3]>,
+    pub yaw: Option<u16>,
+    pub intention: Option<[i8; 2]>,
+    pub attacking: Option<bool>,
+}
+
+impl UnitDelta {
+    /// None if nothing changed
+    fn between(id: NetEntId, old: Option<&UnitState>, new: &UnitState) -> Option<Self> {
+        fn changed<T: PartialEq + Copy>(old: Option<T>, new: T) -> Option<T> {
+            (old != Some(new)).then_some(new)
+        }
+
+        let delta = Self {
+            id,
+            position: changed(old.map(|x| x.position), new.position),
+            yaw: changed(old.map(|x| x.yaw), new.yaw),
+            intention: changed(old.map(|x| x.intention), new.intention),
+            attacking: new
+                .attacking
+                .and_then(|new| changed(old.and_then(|x| x.attacking), new)),
+        };
+
+        let unchanged = delta.position.is_none()
+            && delta.yaw.is_none()
+            && delta.intention.is_none()
+            && delta.attacking.is_none();
+        (!unchanged).then_some(delta)
+    }
+
+    /// None if this only makes sense on top of a baseline we don't have
+    fn apply(&self, old: Option<&UnitState>) -> Option<UnitState> {
+        Some(UnitState {
+            position: self.position.or(old.map(|x| x.position))?,
+            yaw: self.yaw.or(old.map(|x| x.yaw))?,
+            intention: self.intention.or(old.map(|x| x.intention))?,
+            attacking: self.attacking.or(old.and_then(|x| x.attacking)),
+        })
+    }
+}
+
+type WorldState = HashMap<NetEntId, UnitState>;
+
+/// The last [SNAPSHOT_HISTORY] world states, by snapshot id
+#[derive(Debug, Default)]
+struct History {
+    states: VecDeque<(u32, WorldState)>,
+}
+
+impl History {
+    fn push(&mut self, id: u32, state: WorldState) {
+        if self.states.len() >= SNAPSHOT_HISTORY {
+            self.states.pop_front();
+        }
+        self.states.push_back((id, state));
+    }
+
+    fn get(&self, id: u32) -> Option<&WorldState> {
+        self.states
+            .iter()
+            .find(|(x, _)| *x == id)
+            .map(|(_, state)| state)
+    }
+}
+
+/// Server side replication state for a single client.
+#[derive(Component, Debug, Default)]
+pub struct Replicator {
+    next_id: u32,
+    /// The newest snapshot the client told us it has
+    acked: Option<u32>,
+    sent: History,
+}
+
+impl Replicator {
+    /// Build the next snapshot for this client, as a delta against the newest one they acked.
+    /// Returns None if nothing changed since then.
+    pub fn snapshot(&mut self, tick: u64, world: WorldState) -> Option<WorldSnapshot> {
+        // If the ack is too old we no longer know what they have, so send everything
+        let baseline = self.acked.and_then(|id| Some((id, self.sent.get(id)?)));
+
+        let units: Vec<_> = world
+            .iter()
+            .filter_map(|(id, state)| {
+                UnitDelta::between(*id, baseline.and_then(|(_, b)| b.get(id)), state)
+            })
+            .collect();
+        let removed: Vec<_> = baseline
+            .iter()
+            .flat_map(|(_, b)| b.keys())
+            .filter(|id| !world.contains_key(id))
+            .copied()
+            .collect();
+
+        if baseline.is_some() && units.is_empty() && removed.is_empty() {
+            return None;
+        }
+
+        let snapshot = WorldSnapshot {
+            id: self.next_id,
+            baseline: baseline.map(|(id, _)| id),
+            tick,
+            units,
+            removed,
+        };
+        self.next_id += 1;
+        self.sent.push(snapshot.id, world);
+
+        Some(snapshot)
+    }
+
+    pub fn ack(&mut self, id: u32) {
+        if self.acked.is_none_or(|acked| id > acked) {
+            self.acked = Some(id);
+        }
+    }
+}
+
+/// Client side replication state: every snapshot we have decoded recently, so later deltas have
+/// something to build on.
+#[derive(Resource, Debug, Default)]
+pub struct ReplicaHistory {
+    received: History,
+}
+
+impl ReplicaHistory {
+    /// Decode a snapshot, returning the full new state of every unit that changed. Returns None if
+    /// we don't have the baseline it was built against, in which case it should not be acked.
+    pub fn receive(&mut self, snapshot: &WorldSnapshot) -> Option<Vec<(NetEntId, UnitState)>> {
+        let mut world = match snapshot.baseline {
+            Some(id) => self.received.get(id)?.clone(),
+            None => WorldState::default(),
+        };
+
+        for id in &snapshot.removed {
+            world.remove(id);
+        }
+
+        let mut changed = Vec::with_capacity(snapshot.units.len());
+        for delta in &snapshot.units {
+            let Some(state) = delta.apply(world.get(&delta.id)) else {
+                warn!(?delta, "Got a delta for a unit we have never seen");
+                continue;
+            };
+            world.insert(delta.id, state);
+            changed.push((delta.id, state));
+        }
+
+        self.received.push(snapshot.id, world);
+        Some(changed)
+    }
+}
@@ -0,0 +1,278 @@
+//! The numbers behind every skill, loaded from `skills.yaml` so balancing doesn't need a rebuild.
+
+use std::{
+    collections::BTreeMap,
+    env::current_dir,
+    fs::{self, OpenOptions},
+    io::Write,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animations::AnimationState, event::server::Cast, projectile::ProjectileInfo,
+    status_effects::EffectInfo,
+};
+
+/// What gets written out when there is no `skills.yaml` yet, and what tests use
+const DEFAULT_SKILLS: &str = include_str!("../assets/skills.yaml");
+
+/// Which skill a [Cast] is, without the data that comes with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SkillKind {
+    Teleport,
+    Shoot,
+    ShootTargeted,
+    Melee,
+    Aoe,
+    Buff,
+}
+
+impl SkillKind {
+    pub const ALL: [SkillKind; 6] = [
+        SkillKind::Teleport,
+        SkillKind::Shoot,
+        SkillKind::ShootTargeted,
+        SkillKind::Melee,
+        SkillKind::Aoe,
+        SkillKind::Buff,
+    ];
+}
+
+impl Cast {
+    pub fn kind(&self) -> SkillKind {
+        match self {
+            Cast::Teleport(_) => SkillKind::Teleport,
+            Cast::Shoot(_) => SkillKind::Shoot,
+            Cast::ShootTargeted(_, _) => SkillKind::ShootTargeted,
+            Cast::Melee => SkillKind::Melee,
+            Cast::Aoe(_) => SkillKind::Aoe,
+            Cast::Buff => SkillKind::Buff,
+        }
+    }
+}
+
+/// Durations are written as seconds
+pub(crate) mod seconds {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(duration.as_secs_f32())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f32::deserialize(deserializer)?;
+        Duration::try_from_secs_f32(secs)
+            .map_err(|_| D::Error::custom(format!("{secs} is not a valid number of seconds")))
+    }
+}
+
+/// Fields a skill doesn't use can be left out, and are 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SkillInfo {
+    #[serde(with = "seconds")]
+    pub frontswing: Duration,
+    #[serde(with = "seconds")]
+    pub windup: Duration,
+    #[serde(with = "seconds")]
+    pub winddown: Duration,
+    #[serde(with = "seconds")]
+    pub backswing: Duration,
+
+    #[serde(with = "seconds")]
+    pub cooldown: Duration,
+
+    #[serde(default)]
+    pub damage: f64,
+    /// How far from the caster it can be aimed
+    #[serde(default)]
+    pub range: f32,
+    /// For skills that hit everything around a point
+    #[serde(default)]
+    pub area_radius: f32,
+    /// What skills that shoot something shoot
+    #[serde(default)]
+    pub projectile: Option<ProjectileInfo>,
+    /// Given to whoever the skill hits. [SkillKind::Buff] gives them to allies around the caster.
+    #[serde(default)]
+    pub effects: Vec<EffectInfo>,
+}
+
+impl SkillInfo {
+    /// Duration until the skill is complete
+    pub fn get_total_duration(&self) -> Duration {
+        self.frontswing + self.windup + self.winddown + self.backswing
+    }
+
+    /// Duration until the skill is actually cast on the server (eg does damage or whatever)
+    pub fn get_cast_point(&self) -> Duration {
+        self.frontswing + self.windup
+    }
+
+    pub fn get_free_point(&self) -> Duration {
+        self.frontswing + self.windup + self.winddown
+    }
+
+    /// Only for skills that shoot something, which are checked to have a projectile when loading
+    pub fn get_projectile(&self) -> ProjectileInfo {
+        self.projectile.expect("Checked when loading")
+    }
+
+    pub fn get_current_animation(&self, mut time: Duration) -> AnimationState {
+        if time < self.frontswing {
+            return AnimationState::FrontSwing;
+        }
+        time -= self.frontswing;
+
+        if time < self.windup {
+            return AnimationState::WindUp;
+        }
+        time -= self.windup;
+
+        if time < self.winddown {
+            return AnimationState::WindDown;
+        }
+        time -= self.winddown;
+
+        if time < self.backswing {
+            return AnimationState::Backswing;
+        }
+
+        AnimationState::Done
+    }
+
+    fn validate(&self, kind: SkillKind) -> Result<(), String> {
+        if !self.damage.is_finite() || self.damage < 0.0 {
+            return Err(format!("{kind:?} has a damage of {}", self.damage));
+        }
+        let numbers = [("range", self.range), ("area_radius", self.area_radius)];
+        for (name, x) in numbers {
+            if !x.is_finite() || x < 0.0 {
+                return Err(format!("{kind:?} has a {name} of {x}"));
+            }
+        }
+
+        // Whatever the skill needs to do anything at all
+        let required = match kind {
+            SkillKind::Teleport | SkillKind::ShootTargeted => Some(("range", self.range)),
+            SkillKind::Shoot => None,
+            SkillKind::Melee | SkillKind::Aoe | SkillKind::Buff => {
+                Some(("area_radius", self.area_radius))
+            }
+        };
+        if let Some((name, 0.0)) = required {
+            return Err(format!("{kind:?} needs a {name}"));
+        }
+
+        let shoots = matches!(kind, SkillKind::Shoot | SkillKind::ShootTargeted);
+        match &self.projectile {
+            None if shoots => return Err(format!("{kind:?} needs a projectile")),
+            Some(_) if !shoots => return Err(format!("{kind:?} doesn't shoot anything")),
+            Some(projectile) => projectile
+                .validate()
+                .map_err(|e| format!("{kind:?}: {e}"))?,
+            None => {}
+        }
+        if kind == SkillKind::Buff && self.effects.is_empty() {
+            return Err(format!("{kind:?} needs effects"));
+        }
+
+        for effect in &self.effects {
+            effect.validate().map_err(|e| format!("{kind:?}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Every skill, by kind. The client and server need the same table, or they will disagree about
+/// when casts go off, which is what [Skills::checksum] is for.
+#[derive(Resource, Debug, Clone)]
+pub struct Skills {
+    skills: BTreeMap<SkillKind, SkillInfo>,
+    checksum: u64,
+}
+
+impl Default for Skills {
+    fn default() -> Self {
+        Self::from_yaml(DEFAULT_SKILLS).expect("The default skills are invalid")
+    }
+}
+
+impl Skills {
+    /// Parse and check a skill table. Every skill has to be in it.
+    pub fn from_yaml(text: &str) -> Result<Self, String> {
+        let skills: BTreeMap<SkillKind, SkillInfo> =
+            serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+
+        for kind in SkillKind::ALL {
+            let Some(skill) = skills.get(&kind) else {
+                return Err(format!("{kind:?} is missing"));
+            };
+            skill.validate(kind)?;
+        }
+
+        // Hash what we parsed rather than the text, so comments and formatting don't matter
+        let hash = blake3::hash(&postcard::to_stdvec(&skills).unwrap());
+        let checksum = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+        Ok(Self { skills, checksum })
+    }
+
+    pub fn load_from_main_dir() -> Self {
+        let mut path = current_dir().unwrap();
+        path.push("skills.yaml");
+
+        info!("Loading skills from {path:?}");
+        match fs::read_to_string(&path) {
+            Ok(text) => match Self::from_yaml(&text) {
+                Ok(skills) => skills,
+                Err(e) => {
+                    eprintln!("====================================");
+                    eprintln!("===  Failed to load the skills   ===");
+                    eprintln!("====================================");
+                    eprintln!("{e}");
+                    panic!("Please fix the above error in {path:?} and restart your program");
+                }
+            },
+            Err(e) => match e.kind() {
+                // if it doesn't exist, start with the defaults
+                std::io::ErrorKind::NotFound => {
+                    OpenOptions::new()
+                        .create_new(true)
+                        .write(true)
+                        .open(&path)
+                        .and_then(|mut file| file.write_all(DEFAULT_SKILLS.as_bytes()))
+                        .unwrap_or_else(|e| warn!(?e, "Could not write the default skills"));
+                    Self::default()
+                }
+                e => panic!("Failed to open skills file {e:?}"),
+            },
+        }
+    }
+
+    pub fn get(&self, cast: &Cast) -> &SkillInfo {
+        self.get_kind(cast.kind())
+    }
+
+    pub fn get_kind(&self, kind: SkillKind) -> &SkillInfo {
+        // Checked when loading
+        &self.skills[&kind]
+    }
+
+    /// Hash of the whole table, the same for anyone with the same numbers
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+}
+
+pub struct SkillsPlugin;
+
+impl Plugin for SkillsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Skills::load_from_main_dir());
+    }
+}
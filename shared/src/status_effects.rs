@@ -0,0 +1,209 @@
+//! Timed effects on units, like the ones [crate::skills::SkillKind::Buff] gives out.
+//!
+//! Only the server ticks them. Whenever a unit gets a new one or one runs out, the server sends
+//! its whole [StatusEffects] to everyone who can see it, so clients never have to guess when they
+//! end.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{event::NetEntId, skills::seconds};
+
+/// How often damage and heal over time go off
+pub const PULSE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EffectKind {
+    /// Damage dealt is multiplied by the magnitude
+    DamageUp,
+    /// Movement speed is multiplied by the magnitude
+    SpeedUp,
+    /// Cooldowns are divided by the magnitude
+    Haste,
+    /// Takes the magnitude in damage every [PULSE], for each stack
+    DamageOverTime,
+    /// Heals the magnitude every [PULSE], for each stack
+    HealOverTime,
+    /// Movement speed is multiplied by the magnitude, which is less than 1
+    Slow,
+    /// Can't move or start casting
+    Stun,
+}
+
+impl EffectKind {
+    /// How many times a unit can have it at once. Every stack does the whole magnitude again.
+    pub fn max_stacks(self) -> u8 {
+        match self {
+            EffectKind::DamageOverTime => 5,
+            EffectKind::HealOverTime => 3,
+            _ => 1,
+        }
+    }
+
+    /// Whether a magnitude of `a` is stronger than `b`
+    fn is_stronger(self, a: f32, b: f32) -> bool {
+        match self {
+            EffectKind::Slow => a < b,
+            _ => a > b,
+        }
+    }
+}
+
+/// An effect a skill gives, as written in `skills.yaml`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectInfo {
+    pub kind: EffectKind,
+    /// What this means depends on the [EffectKind]. Stuns don't need one.
+    #[serde(default)]
+    pub magnitude: f32,
+    #[serde(with = "seconds")]
+    pub duration: Duration,
+}
+
+impl EffectInfo {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let kind = self.kind;
+        if self.duration.is_zero() {
+            return Err(format!("{kind:?} lasts no time at all"));
+        }
+
+        let magnitude = self.magnitude;
+        let valid = match kind {
+            EffectKind::DamageUp | EffectKind::SpeedUp | EffectKind::Haste => magnitude >= 1.0,
+            EffectKind::DamageOverTime | EffectKind::HealOverTime => magnitude > 0.0,
+            EffectKind::Slow => (0.0..1.0).contains(&magnitude),
+            EffectKind::Stun => true,
+        };
+        if !magnitude.is_finite() || !valid {
+            return Err(format!("{kind:?} has a magnitude of {magnitude}"));
+        }
+        Ok(())
+    }
+}
+
+/// An effect a unit has right now
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffect {
+    pub kind: EffectKind,
+    pub magnitude: f32,
+    pub stacks: u8,
+    pub remaining: Duration,
+    /// Since damage or heal over time last went off
+    since_pulse: Duration,
+}
+
+/// What one [StatusEffects::tick] did
+#[derive(Debug, Default, PartialEq)]
+pub struct Ticked {
+    pub damage: f64,
+    pub heal: f64,
+    /// At least one effect ran out
+    pub expired: bool,
+}
+
+/// Every effect a unit has, at most one of each [EffectKind]. Units that never had any effects
+/// don't have this at all.
+#[derive(Component, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffects(Vec<StatusEffect>);
+
+impl StatusEffects {
+    /// Getting an effect again adds a stack, if it can have more, and the stronger magnitude of
+    /// the two is kept. Either way, it lasts for the whole duration again.
+    pub fn apply(&mut self, info: &EffectInfo) {
+        let Some(effect) = self.0.iter_mut().find(|x| x.kind == info.kind) else {
+            self.0.push(StatusEffect {
+                kind: info.kind,
+                magnitude: info.magnitude,
+                stacks: 1,
+                remaining: info.duration,
+                since_pulse: Duration::ZERO,
+            });
+            self.0.sort_by_key(|x| x.kind);
+            return;
+        };
+
+        if effect.stacks < info.kind.max_stacks() {
+            effect.stacks += 1;
+        }
+        if info.kind.is_stronger(info.magnitude, effect.magnitude) {
+            effect.magnitude = info.magnitude;
+        }
+        effect.remaining = effect.remaining.max(info.duration);
+    }
+
+    /// Count every effect down, removing the ones that ran out
+    pub fn tick(&mut self, delta: Duration) -> Ticked {
+        let mut ticked = Ticked::default();
+        for effect in &mut self.0 {
+            // An effect that runs out partway through still gets its last pulse
+            let delta = delta.min(effect.remaining);
+            effect.remaining -= delta;
+            effect.since_pulse += delta;
+
+            while effect.since_pulse >= PULSE {
+                effect.since_pulse -= PULSE;
+                let amount = effect.magnitude as f64 * effect.stacks as f64;
+                match effect.kind {
+                    EffectKind::DamageOverTime => ticked.damage += amount,
+                    EffectKind::HealOverTime => ticked.heal += amount,
+                    _ => {}
+                }
+            }
+        }
+
+        let before = self.0.len();
+        self.0.retain(|x| !x.remaining.is_zero());
+        ticked.expired = self.0.len() != before;
+        ticked
+    }
+
+    pub fn get(&self, kind: EffectKind) -> Option<&StatusEffect> {
+        self.0.iter().find(|x| x.kind == kind)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StatusEffect> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// 1 if the unit doesn't have it
+    fn magnitude(&self, kind: EffectKind) -> f32 {
+        self.get(kind).map_or(1.0, |x| x.magnitude)
+    }
+
+    /// How much harder this unit hits
+    pub fn damage_multiplier(&self) -> f32 {
+        self.magnitude(EffectKind::DamageUp)
+    }
+
+    /// How much faster this unit moves, which is 0 while it is stunned
+    pub fn speed_multiplier(&self) -> f32 {
+        if self.stunned().is_some() {
+            return 0.0;
+        }
+        self.magnitude(EffectKind::SpeedUp) * self.magnitude(EffectKind::Slow)
+    }
+
+    /// How much longer this unit's cooldowns are
+    pub fn cooldown_multiplier(&self) -> f32 {
+        1.0 / self.magnitude(EffectKind::Haste)
+    }
+
+    /// How long until this unit can do anything again
+    pub fn stunned(&self) -> Option<Duration> {
+        self.get(EffectKind::Stun).map(|x| x.remaining)
+    }
+}
+
+/// Give `target` an effect. Only the server handles these.
+#[derive(Event, Debug, Clone)]
+pub struct ApplyEffect {
+    pub target: NetEntId,
+    pub effect: EffectInfo,
+}
@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+
+/// How many times per second the server runs `FixedUpdate`
+pub const TICK_HZ: f64 = 60.0;
+
+/// How much each new sample moves our RTT and offset estimates. Lower is smoother but slower to
+/// notice when the connection changes.
+const SMOOTHING: f64 = 0.1;
+
+/// Which simulation step the server is on. Goes up by one every `FixedUpdate`.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServerTick(pub u64);
+
+/// When `tick` started on the server, in seconds since it booted
+pub fn tick_secs(tick: u64) -> f64 {
+    tick as f64 / TICK_HZ
+}
+
+/// The client's guess of what time it is on the server, built from heartbeat round trips.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct ClockSync {
+    /// Smoothed round trip time, in seconds
+    rtt: Option<f64>,
+    /// Server seconds minus local seconds
+    offset: Option<f64>,
+}
+
+impl ClockSync {
+    /// A heartbeat we sent at `sent_at` came back at `now`, and the server was on `tick` when it
+    /// answered. Both times are local seconds.
+    pub fn on_heartbeat_response(&mut self, sent_at: f64, now: f64, tick: u64) {
+        let rtt = (now - sent_at).max(0.0);
+        // Assume the trip there took as long as the trip back
+        let offset = tick_secs(tick) + rtt / 2.0 - now;
+
+        let smooth = |old: Option<f64>, new| match old {
+            Some(old) => old + (new - old) * SMOOTHING,
+            None => new,
+        };
+        self.rtt = Some(smooth(self.rtt, rtt));
+        self.offset = Some(smooth(self.offset, offset));
+    }
+
+    /// None until the first heartbeat comes back
+    pub fn rtt(&self) -> Option<f64> {
+        self.rtt
+    }
+
+    /// What time we think it is on the server right now. Before we have heard back from the
+    /// server this is just the local time.
+    pub fn server_time(&self, now: f64) -> f64 {
+        now + self.offset.unwrap_or(0.0)
+    }
+
+    /// The tick the server is probably running right now
+    pub fn estimated_tick(&self, now: f64) -> u64 {
+        (self.server_time(now) * TICK_HZ).max(0.0) as u64
+    }
+}
@@ -0,0 +1,57 @@
+use shared::event::server::{Heartbeat, SendChat};
+use shared::netlib::{
+    fragment::MAX_DATAGRAM_SIZE, pack_batches, reliability::Reliability, EventGroupingRef,
+    EventToServer,
+};
+
+fn encoded_size(events: &[&EventToServer]) -> usize {
+    postcard::to_stdvec(&EventGroupingRef::Batch {
+        tick: Some(u64::MAX),
+        events,
+    })
+    .unwrap()
+    .len()
+}
+
+#[test]
+fn batches_fit_in_a_datagram() {
+    let events: Vec<_> = (0..500)
+        .map(|x| {
+            EventToServer::SendChat(SendChat {
+                text: format!("message number {x}"),
+            })
+        })
+        .collect();
+
+    let batches = pack_batches(&events);
+    assert!(batches.len() > 1);
+    for (_, batch) in &batches {
+        assert!(encoded_size(batch) < MAX_DATAGRAM_SIZE);
+    }
+
+    let unpacked: Vec<_> = batches.into_iter().flat_map(|(_, x)| x).cloned().collect();
+    assert_eq!(format!("{unpacked:?}"), format!("{events:?}"));
+}
+
+#[test]
+fn reliabilities_are_not_mixed() {
+    let events: Vec<_> = (0..10)
+        .map(|x| match x % 2 {
+            0 => EventToServer::Heartbeat(Heartbeat {
+                client_time: x as f64,
+            }),
+            _ => EventToServer::SendChat(SendChat {
+                text: x.to_string(),
+            }),
+        })
+        .collect();
+
+    let batches = pack_batches(&events);
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].0, Reliability::Unreliable);
+    assert_eq!(batches[1].0, Reliability::ReliableOrdered);
+    assert!(batches[1]
+        .1
+        .iter()
+        .all(|x| matches!(x, EventToServer::SendChat(_))));
+}
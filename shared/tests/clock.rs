@@ -0,0 +1,31 @@
+use shared::tick::{tick_secs, ClockSync, TICK_HZ};
+
+#[test]
+fn first_response_sets_the_clock() {
+    let mut clock = ClockSync::default();
+    assert_eq!(clock.rtt(), None);
+
+    // The server is 100 seconds ahead of us and it takes 50ms each way
+    let tick = (100.0 * TICK_HZ) as u64;
+    clock.on_heartbeat_response(9.95, 10.05, tick);
+
+    assert!((clock.rtt().unwrap() - 0.1).abs() < 1e-9);
+    assert!((clock.server_time(10.05) - (tick_secs(tick) + 0.05)).abs() < 1e-9);
+}
+
+#[test]
+fn jitter_is_smoothed_out() {
+    let mut clock = ClockSync::default();
+    let offset = 42.0;
+    for i in 0..200 {
+        let sent_at = i as f64 * 0.2;
+        // Alternate between a fast and a slow round trip
+        let rtt = if i % 2 == 0 { 0.05 } else { 0.15 };
+        let server_time = sent_at + rtt / 2.0 + offset;
+        clock.on_heartbeat_response(sent_at, sent_at + rtt, (server_time * TICK_HZ) as u64);
+    }
+
+    assert!((clock.rtt().unwrap() - 0.1).abs() < 0.01);
+    // Ticks are only 1/TICK_HZ long, so that is as close as we can get
+    assert!((clock.server_time(100.0) - (100.0 + offset)).abs() < 1.0 / TICK_HZ + 0.01);
+}
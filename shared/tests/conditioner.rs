@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+use shared::netlib::{
+    conditioner::{LinkConditioner, LinkConditions},
+    reliability::{Reliability, ReliableChannel},
+};
+
+fn conditions(seed: u64) -> LinkConditions {
+    LinkConditions {
+        latency_ms: 50,
+        jitter_ms: 20,
+        loss: 0.2,
+        duplicate: 0.1,
+        reorder: 0.1,
+        seed: Some(seed),
+    }
+}
+
+/// Push one datagram per millisecond, then collect what arrives and when.
+fn run(seed: u64) -> Vec<(u32, Duration)> {
+    let start = Instant::now();
+    let mut link = LinkConditioner::new(conditions(seed), 0);
+    let mut arrived = vec![];
+    for ms in 0..1000 {
+        let now = start + Duration::from_millis(ms);
+        if ms < 500 {
+            link.push(ms as u32, now);
+        }
+        for x in link.pop_ready(now) {
+            arrived.push((x, now - start));
+        }
+    }
+    assert!(link.is_empty());
+    arrived
+}
+
+#[test]
+fn same_seed_same_network() {
+    assert_eq!(run(7), run(7));
+    assert_ne!(run(7), run(8));
+}
+
+#[test]
+fn conditions_are_applied() {
+    let arrived = run(3);
+
+    for (sent, at) in &arrived {
+        assert!(at.as_millis() >= *sent as u128 + 50);
+    }
+
+    let mut unique: Vec<_> = arrived.iter().map(|(x, _)| *x).collect();
+    unique.sort();
+    unique.dedup();
+    // Some were dropped, some arrived twice
+    assert!(unique.len() < 450 && unique.len() > 300);
+    assert!(arrived.len() > unique.len());
+    // And some arrived after datagrams sent later
+    assert!(arrived.windows(2).any(|x| x[0].0 > x[1].0));
+}
+
+#[test]
+fn disabled_conditions_change_nothing() {
+    assert!(!LinkConditions::default().is_enabled());
+
+    let now = Instant::now();
+    let mut link = LinkConditioner::new(LinkConditions::default(), 0);
+    for x in 0..10 {
+        link.push(x, now);
+    }
+    assert_eq!(link.pop_ready(now), (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn reliable_messages_survive_a_bad_link() {
+    let start = Instant::now();
+    let mut a = ReliableChannel::default();
+    let mut b = ReliableChannel::default();
+    let mut to_b = LinkConditioner::new(conditions(11), 0);
+    let mut to_a = LinkConditioner::new(conditions(11), 1);
+
+    for x in 0..100u8 {
+        for datagram in a.wrap(Reliability::ReliableOrdered, vec![x], start) {
+            to_b.push(datagram, start);
+        }
+    }
+
+    let mut received = vec![];
+    for ms in 0..5000 {
+        let now = start + Duration::from_millis(ms);
+        for datagram in a.resend(now) {
+            to_b.push(datagram, now);
+        }
+        for datagram in to_b.pop_ready(now) {
+            let r = b.receive(&datagram, now).unwrap();
+            received.extend(r.payloads);
+            if let Some(ack) = r.ack {
+                to_a.push(ack, now);
+            }
+        }
+        for datagram in to_a.pop_ready(now) {
+            a.receive(&datagram, now).unwrap();
+        }
+    }
+
+    assert_eq!(received, (0..100u8).map(|x| vec![x]).collect::<Vec<_>>());
+    assert_eq!(a.unacked_len(), 0);
+}
@@ -0,0 +1,41 @@
+use shared::faction::{
+    Faction::{Monsters, Players, Wildlife},
+    FactionRules, Relation,
+};
+
+#[test]
+fn relations_go_both_ways() {
+    let rules = FactionRules::default();
+    let all = [Players, Monsters, Wildlife];
+    for a in all {
+        for b in all {
+            assert_eq!(
+                rules.relation(a, b),
+                rules.relation(b, a),
+                "{a:?} and {b:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn players_only_fight_each_other_with_pvp() {
+    let peaceful = FactionRules { pvp: false };
+    let pvp = FactionRules { pvp: true };
+
+    assert_eq!(peaceful.relation(Players, Players), Relation::Allied);
+    assert_eq!(pvp.relation(Players, Players), Relation::Hostile);
+    for rules in [peaceful, pvp] {
+        assert_eq!(rules.relation(Monsters, Monsters), Relation::Allied);
+        assert_eq!(rules.relation(Players, Monsters), Relation::Hostile);
+        assert_eq!(rules.relation(Wildlife, Monsters), Relation::Neutral);
+        assert_eq!(rules.relation(Wildlife, Players), Relation::Neutral);
+    }
+}
+
+#[test]
+fn only_allies_are_safe() {
+    assert!(Relation::Hostile.can_hurt());
+    assert!(Relation::Neutral.can_hurt());
+    assert!(!Relation::Allied.can_hurt());
+}
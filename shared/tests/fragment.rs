@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use shared::netlib::fragment::{split, Reassembly, MAX_DATAGRAM_SIZE};
+
+/// Insert every fragment of the message but the last
+fn start(reassembly: &mut Reassembly, message: u32, data: &[u8], now: Instant) {
+    let mut fragments = split(message, data);
+    fragments.pop();
+    for fragment in fragments {
+        assert_eq!(reassembly.insert(fragment, now), None);
+    }
+}
+
+/// Insert the last fragment of the message, which completes it if nothing else was lost
+fn finish(reassembly: &mut Reassembly, message: u32, data: &[u8], now: Instant) -> Option<Vec<u8>> {
+    let last = split(message, data).pop().unwrap();
+    reassembly.insert(last, now)
+}
+
+#[test]
+fn fragments_come_back_together() {
+    let mut reassembly = Reassembly::default();
+    let now = Instant::now();
+    let data: Vec<u8> = (0..10_000).map(|x| x as u8).collect();
+
+    start(&mut reassembly, 0, &data, now);
+    assert_eq!(finish(&mut reassembly, 0, &data, now), Some(data));
+}
+
+#[test]
+fn starting_too_many_messages_drops_the_oldest() {
+    let mut reassembly = Reassembly::default();
+    let start_time = Instant::now();
+    let data = vec![1; 2 * MAX_DATAGRAM_SIZE];
+
+    let messages = 0..20u32;
+    for message in messages.clone() {
+        let now = start_time + Duration::from_millis(message as u64);
+        start(&mut reassembly, message, &data, now);
+    }
+
+    let now = start_time + Duration::from_millis(20);
+    assert_eq!(finish(&mut reassembly, 0, &data, now), None);
+    assert_eq!(
+        finish(&mut reassembly, messages.end - 1, &data, now),
+        Some(data)
+    );
+}
+
+#[test]
+fn big_messages_can_only_take_up_so_much_memory() {
+    let mut reassembly = Reassembly::default();
+    let start_time = Instant::now();
+    // About as big as a message is allowed to get
+    let data = vec![1; 900 * MAX_DATAGRAM_SIZE];
+
+    for message in 0..3u32 {
+        let now = start_time + Duration::from_millis(message as u64);
+        start(&mut reassembly, message, &data, now);
+    }
+
+    let now = start_time + Duration::from_millis(3);
+    assert_eq!(finish(&mut reassembly, 0, &data, now), None);
+    assert_eq!(finish(&mut reassembly, 2, &data, now), Some(data));
+}
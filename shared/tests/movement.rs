@@ -0,0 +1,82 @@
+use bevy::math::{Vec2, Vec3};
+use shared::movement::{
+    apply_input, in_world, MovementInput, PendingInputs, MAX_INPUT_DT, PLAYER_SPEED,
+    WORLD_HALF_SIZE,
+};
+
+/// Run the inputs the server has received so far, the same way it does.
+fn simulate(start: Vec3, inputs: &[MovementInput]) -> Vec3 {
+    inputs
+        .iter()
+        .fold(start, |pos, input| apply_input(pos, input, 1.0))
+}
+
+#[test]
+fn reconcile_replays_unacked_inputs() {
+    let mut pending = PendingInputs::default();
+    let mut predicted = Vec3::ZERO;
+    let mut sent = vec![];
+    for i in 0..10 {
+        let input = pending.push(Vec2::new(1.0, (i % 3) as f32 - 1.0), 1.0 / 60.0, 1.0);
+        predicted = apply_input(predicted, &input, 1.0);
+        sent.push(input);
+    }
+
+    // The server has only seen the first 4 inputs so far
+    let authoritative = simulate(Vec3::ZERO, &sent[..4]);
+    let reconciled = pending.reconcile(authoritative, sent[3].seq).unwrap();
+
+    assert!(reconciled.distance(predicted) < 1e-4);
+    assert_eq!(pending.unacked().count(), 6);
+}
+
+#[test]
+fn reconcile_snaps_to_the_server() {
+    let mut pending = PendingInputs::default();
+    let input = pending.push(Vec2::X, 1.0 / 60.0, 1.0);
+
+    // Something moved us on the server that we didn't predict
+    let reconciled = pending.reconcile(Vec3::new(50.0, 0.0, 0.0), input.seq);
+
+    assert_eq!(reconciled, Some(Vec3::new(50.0, 0.0, 0.0)));
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn reconcile_ignores_stale_results() {
+    let mut pending = PendingInputs::default();
+    let first = pending.push(Vec2::X, 1.0 / 60.0, 1.0);
+    let second = pending.push(Vec2::X, 1.0 / 60.0, 1.0);
+
+    assert!(pending.reconcile(Vec3::ONE, second.seq).is_some());
+    assert_eq!(pending.reconcile(Vec3::ZERO, first.seq), None);
+}
+
+#[test]
+fn long_inputs_are_clamped() {
+    let input = MovementInput {
+        seq: 0,
+        intention: Vec2::new(10.0, 0.0),
+        dt: 5.0,
+    };
+
+    let moved = apply_input(Vec3::ZERO, &input, 1.0);
+
+    assert!((moved.x - PLAYER_SPEED * MAX_INPUT_DT).abs() < 1e-4);
+}
+
+#[test]
+fn nobody_walks_off_the_edge_of_the_world() {
+    let inputs: Vec<MovementInput> = (0..10_000)
+        .map(|seq| MovementInput {
+            seq,
+            intention: Vec2::new(1.0, -1.0).normalize(),
+            dt: MAX_INPUT_DT,
+        })
+        .collect();
+
+    let end = simulate(Vec3::new(WORLD_HALF_SIZE - 1.0, 2.0, 0.0), &inputs);
+
+    assert!(in_world(end));
+    assert_eq!(end, Vec3::new(WORLD_HALF_SIZE, 2.0, -WORLD_HALF_SIZE));
+}
@@ -0,0 +1,85 @@
+use bevy::prelude::*;
+use shared::{
+    event::NetEntId,
+    projectile::{Projectile, ProjectileInfo, UNIT_RADIUS},
+    skills::SkillKind,
+};
+
+const FRAME: f32 = 1.0 / 60.0;
+
+fn info() -> ProjectileInfo {
+    ProjectileInfo {
+        speed: 10.0,
+        range: 20.0,
+        radius: 0.5,
+        arc: 0.0,
+        homing: false,
+        pierce: 0,
+    }
+}
+
+#[test]
+fn flies_straight_until_out_of_range() {
+    let mut projectile = Projectile::new(SkillKind::Shoot, info(), Vec3::ZERO, Vec3::X);
+
+    assert!(projectile.advance(1.0, None));
+    assert_eq!(projectile.translation(), Vec3::new(10.0, 0.0, 0.0));
+    // Never further than its range, even on a long frame
+    assert!(!projectile.advance(5.0, None));
+    assert_eq!(projectile.translation(), Vec3::new(20.0, 0.0, 0.0));
+}
+
+#[test]
+fn arcs_come_down_where_they_were_aimed() {
+    let info = ProjectileInfo { arc: 5.0, ..info() };
+    let towards = Vec3::new(10.0, 0.0, 0.0);
+    let mut projectile = Projectile::new(SkillKind::Shoot, info, Vec3::ZERO, towards);
+
+    projectile.advance(0.5, None);
+    assert_eq!(projectile.translation(), Vec3::new(5.0, 5.0, 0.0));
+    projectile.advance(0.5, None);
+    assert!(projectile.translation().distance(towards) < 1e-4);
+}
+
+#[test]
+fn homing_follows_the_target() {
+    let homing = ProjectileInfo {
+        homing: true,
+        ..info()
+    };
+    let target = NetEntId::random();
+    let mut projectile = Projectile::at_unit(
+        SkillKind::ShootTargeted,
+        homing,
+        Vec3::ZERO,
+        target,
+        Vec3::X * 5.0,
+    );
+
+    // They ran off to the side
+    let moved = Vec3::new(0.0, 0.0, 5.0);
+    for _ in 0..60 {
+        projectile.advance(FRAME, Some(moved));
+    }
+    assert!(projectile.touches(moved));
+
+    let straight = Projectile::new(SkillKind::Shoot, info(), Vec3::ZERO, Vec3::X * 5.0);
+    assert!(!straight.touches(moved));
+}
+
+#[test]
+fn hits_count_against_pierce() {
+    let info = ProjectileInfo {
+        pierce: 1,
+        ..info()
+    };
+    let mut projectile = Projectile::new(SkillKind::Shoot, info, Vec3::ZERO, Vec3::X);
+    assert!(projectile.touches(Vec3::X * (UNIT_RADIUS + 0.4)));
+
+    let (a, b) = (NetEntId::random(), NetEntId::random());
+    assert!(!projectile.hit(a));
+    // Still only one unit
+    assert!(!projectile.hit(a));
+    assert!(projectile.has_hit(&a));
+    assert!(projectile.hit(b));
+}
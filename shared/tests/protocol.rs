@@ -0,0 +1,77 @@
+use shared::event::{
+    client::{Chat, ConnectRejected},
+    server::{Heartbeat, Request, SendChat},
+};
+use shared::netlib::{rpc::RequestId, EventToClient, EventToServer};
+
+#[test]
+fn the_id_is_what_goes_on_the_wire() {
+    let event = EventToClient::Chat(Chat {
+        source: None,
+        text: "hello".into(),
+    });
+    assert_eq!(event.id(), 13);
+
+    let bytes = postcard::to_stdvec(&event).unwrap();
+    // Postcard writes the variant as a varint, and 13 fits in one byte
+    assert_eq!(bytes[0], 13);
+
+    let EventToClient::Chat(chat) = postcard::from_bytes(&bytes).unwrap() else {
+        panic!("Decoded as something else");
+    };
+    assert_eq!(chat.text, "hello");
+}
+
+#[test]
+fn connect_rejected_is_always_first() {
+    let event = EventToClient::ConnectRejected(ConnectRejected {
+        server_protocol_version: 7,
+        reason: "old".into(),
+    });
+    assert_eq!(event.id(), 0);
+    assert_eq!(postcard::to_stdvec(&event).unwrap()[0], 0);
+}
+
+#[test]
+fn events_round_trip() {
+    let events = [
+        EventToServer::SendChat(SendChat { text: "hi".into() }),
+        EventToServer::Heartbeat(Heartbeat { client_time: 1.5 }),
+    ];
+    for event in events {
+        let bytes = postcard::to_stdvec(&event).unwrap();
+        let decoded: EventToServer = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.id(), event.id());
+        assert_eq!(format!("{decoded:?}"), format!("{event:?}"));
+    }
+}
+
+#[test]
+fn unknown_ids_are_an_error() {
+    assert!(postcard::from_bytes::<EventToServer>(&[100, 0]).is_err());
+}
+
+#[test]
+fn requests_can_only_be_one_deep() {
+    let request = EventToServer::Request(Request {
+        id: RequestId(3),
+        event: Box::new(EventToServer::SendChat(SendChat {
+            text: "/who".into(),
+        })),
+    });
+    let bytes = postcard::to_stdvec(&request).unwrap();
+    let EventToServer::Request(decoded) = postcard::from_bytes(&bytes).unwrap() else {
+        panic!("Decoded as something else");
+    };
+    assert_eq!(decoded.id, RequestId(3));
+
+    // Deep enough to overflow the stack if every level was decoded. Each one is the event id
+    // and a request id of 0, which both fit in a byte.
+    let nested = |id: u32| {
+        let mut bytes = [id as u8, 0].repeat(1_000_000);
+        bytes.extend([2, 0, 0, 0, 0, 0, 0, 0, 0]);
+        bytes
+    };
+    assert!(postcard::from_bytes::<EventToServer>(&nested(request.id())).is_err());
+    assert!(postcard::from_bytes::<EventToClient>(&nested(18)).is_err());
+}
@@ -0,0 +1,247 @@
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use shared::netlib::{
+    fragment::MAX_DATAGRAM_SIZE,
+    reliability::{Reliability, ReliableChannel, GIVE_UP_AFTER, RECEIVE_WINDOW, RESEND_AFTER},
+};
+
+/// Two channels talking to each other over a fake network that drops and reorders datagrams.
+struct LossyLoopback {
+    rng: StdRng,
+    loss: f64,
+    now: Instant,
+    a: ReliableChannel,
+    b: ReliableChannel,
+    /// (to_b, datagram)
+    in_flight: Vec<(bool, Vec<u8>)>,
+    received_by_b: Vec<Vec<u8>>,
+}
+
+impl LossyLoopback {
+    fn new(seed: u64, loss: f64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            loss,
+            now: Instant::now(),
+            a: ReliableChannel::default(),
+            b: ReliableChannel::default(),
+            in_flight: vec![],
+            received_by_b: vec![],
+        }
+    }
+
+    fn send_from_a(&mut self, reliability: Reliability, payload: Vec<u8>) {
+        for datagram in self.a.wrap(reliability, payload, self.now) {
+            self.in_flight.push((true, datagram));
+        }
+    }
+
+    /// Deliver everything currently in flight in a random order, then advance time.
+    fn step(&mut self) {
+        let mut in_flight = std::mem::take(&mut self.in_flight);
+        while !in_flight.is_empty() {
+            let (to_b, datagram) = in_flight.swap_remove(self.rng.gen_range(0..in_flight.len()));
+            if self.rng.gen_bool(self.loss) {
+                continue;
+            }
+
+            let receiver = if to_b { &mut self.b } else { &mut self.a };
+            let received = receiver.receive(&datagram, self.now).unwrap();
+            if let Some(ack) = received.ack {
+                self.in_flight.push((!to_b, ack));
+            }
+            if to_b {
+                self.received_by_b.extend(received.payloads);
+            }
+        }
+
+        self.now += RESEND_AFTER + Duration::from_millis(1);
+        for datagram in self.a.resend(self.now) {
+            self.in_flight.push((true, datagram));
+        }
+    }
+}
+
+#[test]
+fn reliable_ordered_survives_loss() {
+    let mut net = LossyLoopback::new(1, 0.3);
+    let sent: Vec<Vec<u8>> = (0..200u8).map(|x| vec![x]).collect();
+    for payload in &sent {
+        net.send_from_a(Reliability::ReliableOrdered, payload.clone());
+    }
+
+    for _ in 0..100 {
+        net.step();
+    }
+
+    assert_eq!(net.received_by_b, sent);
+    assert_eq!(net.a.unacked_len(), 0);
+}
+
+#[test]
+fn reliable_unordered_arrives_exactly_once() {
+    let mut net = LossyLoopback::new(2, 0.3);
+    for x in 0..200u8 {
+        net.send_from_a(Reliability::ReliableUnordered, vec![x]);
+    }
+
+    for _ in 0..100 {
+        net.step();
+    }
+
+    let mut got = net.received_by_b.clone();
+    got.sort();
+    assert_eq!(got, (0..200u8).map(|x| vec![x]).collect::<Vec<_>>());
+}
+
+#[test]
+fn unreliable_is_never_resent() {
+    let mut net = LossyLoopback::new(3, 0.5);
+    for x in 0..200u8 {
+        net.send_from_a(Reliability::Unreliable, vec![x]);
+    }
+
+    for _ in 0..10 {
+        net.step();
+    }
+
+    assert!(net.received_by_b.len() < 200);
+    assert_eq!(net.a.unacked_len(), 0);
+}
+
+#[test]
+fn big_payloads_are_fragmented_and_reassembled() {
+    let mut net = LossyLoopback::new(4, 0.0);
+    let big: Vec<u8> = (0..10_000).map(|x| x as u8).collect();
+    for datagram in net.a.wrap(Reliability::Unreliable, big.clone(), net.now) {
+        assert!(datagram.len() <= MAX_DATAGRAM_SIZE);
+        net.in_flight.push((true, datagram));
+    }
+
+    net.step();
+
+    assert_eq!(net.received_by_b, vec![big]);
+}
+
+#[test]
+fn big_reliable_payloads_survive_loss() {
+    let mut net = LossyLoopback::new(5, 0.1);
+    let sent: Vec<Vec<u8>> = (0..20u8).map(|x| vec![x; 5000]).collect();
+    for payload in &sent {
+        net.send_from_a(Reliability::ReliableOrdered, payload.clone());
+    }
+
+    for _ in 0..100 {
+        net.step();
+    }
+
+    assert_eq!(net.received_by_b, sent);
+    assert_eq!(net.a.unacked_len(), 0);
+}
+
+#[test]
+fn rtt_comes_from_acks_of_datagrams_sent_once() {
+    let start = Instant::now();
+    let mut a = ReliableChannel::default();
+    let mut b = ReliableChannel::default();
+    assert_eq!(a.rtt(), None);
+
+    let datagram = a
+        .wrap(Reliability::ReliableOrdered, vec![1], start)
+        .pop()
+        .unwrap();
+    let ack = b.receive(&datagram, start).unwrap().ack.unwrap();
+    a.receive(&ack, start + Duration::from_millis(40)).unwrap();
+    assert_eq!(a.rtt(), Some(Duration::from_millis(40)));
+
+    // Resent, so the ack could be for either copy
+    a.wrap(Reliability::ReliableOrdered, vec![2], start);
+    let later = start + RESEND_AFTER;
+    let datagram = a.resend(later).pop().unwrap();
+    let ack = b.receive(&datagram, later).unwrap().ack.unwrap();
+    a.receive(&ack, later + Duration::from_secs(1)).unwrap();
+    assert_eq!(a.rtt(), Some(Duration::from_millis(40)));
+}
+
+#[test]
+fn channels_give_up_when_nothing_gets_acked() {
+    let start = Instant::now();
+    let mut a = ReliableChannel::default();
+    let mut b = ReliableChannel::default();
+
+    // Acked right away, so it doesn't count
+    let datagram = a
+        .wrap(Reliability::ReliableOrdered, vec![1], start)
+        .pop()
+        .unwrap();
+    let ack = b.receive(&datagram, start).unwrap().ack.unwrap();
+    a.receive(&ack, start).unwrap();
+
+    a.wrap(Reliability::ReliableOrdered, vec![2], start);
+    let mut now = start;
+    while now < start + GIVE_UP_AFTER {
+        assert!(!a.is_dead(now));
+        now += RESEND_AFTER;
+        assert_eq!(a.resend(now).len(), 1);
+    }
+    assert!(a.is_dead(now));
+}
+
+#[test]
+fn skipping_ahead_does_not_fill_up_memory() {
+    let mut net = LossyLoopback::new(6, 0.0);
+    let sent: Vec<Vec<u8>> = (0..2000u32).map(|x| x.to_le_bytes().to_vec()).collect();
+    for payload in &sent {
+        net.send_from_a(Reliability::ReliableOrdered, payload.clone());
+    }
+    // The first one is lost, so everything else has to wait for it
+    net.in_flight.remove(0);
+
+    net.step();
+    assert!(net.b.waiting_len() <= 2 * RECEIVE_WINDOW as usize);
+
+    // Whatever didn't fit gets resent once the gap is filled
+    for _ in 0..100 {
+        net.step();
+    }
+    assert_eq!(net.received_by_b, sent);
+    assert_eq!(net.b.waiting_len(), 0);
+    assert_eq!(net.a.unacked_len(), 0);
+}
+
+#[test]
+fn big_ordered_payloads_can_only_take_up_so_much_memory() {
+    let start = Instant::now();
+    let mut a = ReliableChannel::default();
+    let mut b = ReliableChannel::default();
+    let sent: Vec<Vec<u8>> = (0..30u8).map(|x| vec![x; 300_000]).collect();
+    let mut datagrams: Vec<Vec<Vec<u8>>> = sent
+        .iter()
+        .map(|x| a.wrap(Reliability::ReliableOrdered, x.clone(), start))
+        .collect();
+
+    // The first one is lost, so everything else has to wait for it
+    let mut received = vec![];
+    for datagram in datagrams.drain(..).skip(1).flatten() {
+        received.extend(b.receive(&datagram, start).unwrap().payloads);
+    }
+    assert!(received.is_empty());
+    // Each counts once for its id and once for its payload
+    assert!(b.waiting_len() < 2 * 20);
+
+    // Whatever didn't fit gets resent once the gap is filled
+    let mut now = start;
+    while received.len() < sent.len() {
+        assert!(now < start + GIVE_UP_AFTER);
+        now += RESEND_AFTER;
+        for datagram in a.resend(now) {
+            let got = b.receive(&datagram, now).unwrap();
+            received.extend(got.payloads);
+            if let Some(ack) = got.ack {
+                a.receive(&ack, now).unwrap();
+            }
+        }
+    }
+    assert_eq!(received, sent);
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use shared::{
+    event::NetEntId,
+    replication::{ReplicaHistory, Replicator, UnitState, SNAPSHOT_HISTORY},
+    unit::{AttackIntention, MovementIntention},
+};
+
+fn unit(x: f32) -> UnitState {
+    UnitState::new(
+        &Transform::from_xyz(x, 0.0, 0.0),
+        &MovementIntention(Vec2::X),
+        Some(&AttackIntention::None),
+    )
+}
+
+#[test]
+fn only_changed_units_are_sent() {
+    let mut server = Replicator::default();
+    let mut client = ReplicaHistory::default();
+    let mut world = HashMap::from([(NetEntId(1), unit(0.0)), (NetEntId(2), unit(5.0))]);
+
+    let full = server.snapshot(1, world.clone()).unwrap();
+    assert_eq!(full.baseline, None);
+    assert_eq!(client.receive(&full).unwrap().len(), 2);
+    server.ack(full.id);
+
+    world.insert(NetEntId(2), unit(6.0));
+    let delta = server.snapshot(2, world.clone()).unwrap();
+    assert_eq!(delta.baseline, Some(full.id));
+    assert_eq!(delta.units.len(), 1);
+    assert_eq!(delta.units[0].yaw, None);
+
+    let changed = client.receive(&delta).unwrap();
+    assert_eq!(changed, vec![(NetEntId(2), world[&NetEntId(2)])]);
+    assert!((changed[0].1.translation().x - 6.0).abs() < 0.1);
+
+    // Nothing changed since the last ack, so there is nothing to send
+    server.ack(delta.id);
+    assert!(server.snapshot(3, world).is_none());
+}
+
+#[test]
+fn lost_snapshots_are_covered_by_the_next_delta() {
+    let mut server = Replicator::default();
+    let mut client = ReplicaHistory::default();
+
+    let full = server
+        .snapshot(1, HashMap::from([(NetEntId(1), unit(0.0))]))
+        .unwrap();
+    client.receive(&full).unwrap();
+    server.ack(full.id);
+
+    // This one never arrives
+    server
+        .snapshot(2, HashMap::from([(NetEntId(1), unit(1.0))]))
+        .unwrap();
+
+    let next = server
+        .snapshot(3, HashMap::from([(NetEntId(1), unit(2.0))]))
+        .unwrap();
+    assert_eq!(next.baseline, Some(full.id));
+    let changed = client.receive(&next).unwrap();
+    assert!((changed[0].1.translation().x - 2.0).abs() < 0.1);
+}
+
+#[test]
+fn falls_back_to_full_snapshot_without_acks() {
+    let mut server = Replicator::default();
+    let full = server
+        .snapshot(0, HashMap::from([(NetEntId(1), unit(0.0))]))
+        .unwrap();
+    server.ack(full.id);
+
+    // The client stops acking for long enough that we forget what it had
+    for i in 0..SNAPSHOT_HISTORY {
+        server.snapshot(
+            i as u64,
+            HashMap::from([(NetEntId(1), unit(i as f32 + 1.0))]),
+        );
+    }
+
+    let snapshot = server
+        .snapshot(100, HashMap::from([(NetEntId(1), unit(0.0))]))
+        .unwrap();
+    assert_eq!(snapshot.baseline, None);
+
+    // A client that lost its history can still use it
+    let mut client = ReplicaHistory::default();
+    assert_eq!(client.receive(&snapshot).unwrap().len(), 1);
+}
+
+#[test]
+fn missing_baseline_is_not_decoded() {
+    let mut server = Replicator::default();
+    let full = server
+        .snapshot(0, HashMap::from([(NetEntId(1), unit(0.0))]))
+        .unwrap();
+    server.ack(full.id);
+    let delta = server
+        .snapshot(1, HashMap::from([(NetEntId(1), unit(3.0))]))
+        .unwrap();
+
+    let mut client = ReplicaHistory::default();
+    assert!(client.receive(&delta).is_none());
+}
@@ -0,0 +1,139 @@
+use std::time::Instant;
+
+use shared::netlib::{
+    reliability::{InvalidDatagram, Reliability, ReliableChannel, RESEND_AFTER},
+    secure::{ClientHandshake, Handshake, ServerIdentity},
+};
+
+/// A client and server channel that have finished the handshake.
+fn connected(now: Instant) -> (ReliableChannel, ReliableChannel) {
+    let identity = ServerIdentity::generate();
+    let mut client = ReliableChannel::connecting(ClientHandshake::default());
+    let mut server = ReliableChannel::secure_only();
+
+    let hello = client.resend(now).pop().unwrap();
+    let Some(Handshake::Hello { public }) = server.receive(&hello, now).unwrap().handshake else {
+        panic!("Expected a hello");
+    };
+    let reply = server.accept_handshake(&identity, public).unwrap();
+
+    let Some(Handshake::Reply {
+        server: key,
+        public,
+    }) = client.receive(&reply, now).unwrap().handshake
+    else {
+        panic!("Expected a reply");
+    };
+    assert_eq!(key, identity.public());
+    assert!(client.finish_handshake(key, public));
+    assert!(client.is_secure() && server.is_secure());
+
+    (client, server)
+}
+
+#[test]
+fn sealed_datagrams_arrive() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+
+    let datagrams = client.wrap(Reliability::ReliableOrdered, b"cast".to_vec(), now);
+    let received = server.receive(&datagrams[0], now).unwrap();
+    assert_eq!(received.payloads, vec![b"cast".to_vec()]);
+
+    client.receive(&received.ack.unwrap(), now).unwrap();
+    assert_eq!(client.unacked_len(), 0);
+}
+
+#[test]
+fn tampered_and_replayed_datagrams_are_dropped() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+
+    let mut datagram = client
+        .wrap(Reliability::Unreliable, b"move".to_vec(), now)
+        .pop()
+        .unwrap();
+    let original = datagram.clone();
+    *datagram.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        server.receive(&datagram, now),
+        Err(InvalidDatagram::Tampered)
+    ));
+
+    assert!(server.receive(&original, now).is_ok());
+    assert!(matches!(
+        server.receive(&original, now),
+        Err(InvalidDatagram::Tampered)
+    ));
+}
+
+#[test]
+fn plaintext_is_refused() {
+    let now = Instant::now();
+    let (_, mut server) = connected(now);
+
+    let mut spoofer = ReliableChannel::default();
+    let datagram = spoofer
+        .wrap(Reliability::Unreliable, b"move".to_vec(), now)
+        .pop()
+        .unwrap();
+    assert!(matches!(
+        server.receive(&datagram, now),
+        Err(InvalidDatagram::Unencrypted)
+    ));
+    assert!(matches!(
+        ReliableChannel::secure_only().receive(&datagram, now),
+        Err(InvalidDatagram::Unencrypted)
+    ));
+}
+
+#[test]
+fn nothing_is_sent_before_the_handshake() {
+    let now = Instant::now();
+    let identity = ServerIdentity::generate();
+    let mut client = ReliableChannel::connecting(ClientHandshake::default());
+    let mut server = ReliableChannel::secure_only();
+
+    assert!(client
+        .wrap(Reliability::ReliableOrdered, b"hi".to_vec(), now)
+        .is_empty());
+
+    let hello = client.resend(now).pop().unwrap();
+    let Some(Handshake::Hello { public }) = server.receive(&hello, now).unwrap().handshake else {
+        panic!("Expected a hello");
+    };
+    let reply = server.accept_handshake(&identity, public).unwrap();
+    // The same hello again gets the same answer, instead of new keys
+    assert_eq!(
+        server.accept_handshake(&identity, public),
+        Some(reply.clone())
+    );
+
+    let Some(Handshake::Reply {
+        server: key,
+        public,
+    }) = client.receive(&reply, now).unwrap().handshake
+    else {
+        panic!("Expected a reply");
+    };
+    assert!(client.finish_handshake(key, public));
+
+    let later = now + RESEND_AFTER;
+    let resent = client.resend(later);
+    assert_eq!(resent.len(), 1);
+    let received = server.receive(&resent[0], later).unwrap();
+    assert_eq!(received.payloads, vec![b"hi".to_vec()]);
+}
+
+#[cfg(unix)]
+#[test]
+fn only_the_server_can_read_its_key() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("private_server_key");
+    let _ = std::fs::remove_file(&path);
+    ServerIdentity::load_or_generate(&path).unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
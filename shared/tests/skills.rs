@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use shared::{
+    event::server::Cast,
+    skills::{SkillKind, Skills},
+};
+
+const DEFAULTS: &str = include_str!("../assets/skills.yaml");
+
+#[test]
+fn the_defaults_load() {
+    let skills = Skills::default();
+    let melee = skills.get(&Cast::Melee);
+    assert_eq!(melee.frontswing, Duration::from_secs_f32(0.2));
+    assert_eq!(melee.damage, 25.0);
+    assert_eq!(skills.get_kind(SkillKind::Teleport).range, 30.0);
+}
+
+#[test]
+fn the_checksum_only_depends_on_the_numbers() {
+    let reformatted = DEFAULTS
+        .lines()
+        .filter(|x| !x.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    assert_eq!(
+        Skills::from_yaml(&reformatted).unwrap().checksum(),
+        Skills::default().checksum()
+    );
+
+    let rebalanced = DEFAULTS.replace("damage: 25.0", "damage: 26.0");
+    assert_ne!(
+        Skills::from_yaml(&rebalanced).unwrap().checksum(),
+        Skills::default().checksum()
+    );
+}
+
+#[test]
+fn every_skill_is_required() {
+    let start = DEFAULTS.find("Buff:").unwrap();
+    let error = Skills::from_yaml(&DEFAULTS[..start]).unwrap_err();
+    assert!(error.contains("Buff"), "{error}");
+}
+
+#[test]
+fn only_shooting_skills_have_projectiles() {
+    let start = DEFAULTS.find("  projectile:").unwrap();
+    let end = DEFAULTS[start..].find("\n\n").unwrap() + start;
+    let without = format!("{}{}", &DEFAULTS[..start], &DEFAULTS[end + 1..]);
+    let error = Skills::from_yaml(&without).unwrap_err();
+    assert!(error.contains("Shoot"), "{error}");
+
+    let projectile = &DEFAULTS[start..end + 1];
+    let melee = DEFAULTS.replace(
+        "  area_radius: 5.0\n",
+        &format!("  area_radius: 5.0\n{projectile}"),
+    );
+    let error = Skills::from_yaml(&melee).unwrap_err();
+    assert!(error.contains("Melee"), "{error}");
+}
+
+#[test]
+fn bad_numbers_are_rejected() {
+    for (from, to) in [
+        ("frontswing: 0.2", "frontswing: -0.2"),
+        ("damage: 25.0", "damage: .nan"),
+        ("range: 30.0", "range: 0.0"),
+        ("area_radius: 5.0", "area_radius: -5.0"),
+        ("area_radius: 5.0", "area_radus: 5.0"),
+        ("magnitude: 1.25", "magnitude: 0.5"),
+        ("duration: 10.0", "duration: 0.0"),
+        ("speed: 50.0", "speed: 0.0"),
+        ("radius: 1.25", "radius: .inf"),
+        ("arc: 5.0", "arc: -5.0"),
+        ("    range: 250.0\n", ""),
+    ] {
+        let changed = DEFAULTS.replacen(from, to, 1);
+        assert!(
+            Skills::from_yaml(&changed).is_err(),
+            "{to} should have been rejected"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use shared::{
+    skills::{SkillKind, Skills},
+    status_effects::{EffectInfo, EffectKind, StatusEffects, Ticked},
+};
+
+fn effect(kind: EffectKind, magnitude: f32, secs: u64) -> EffectInfo {
+    EffectInfo {
+        kind,
+        magnitude,
+        duration: Duration::from_secs(secs),
+    }
+}
+
+#[test]
+fn the_stronger_effect_wins() {
+    let mut effects = StatusEffects::default();
+    effects.apply(&effect(EffectKind::SpeedUp, 1.5, 2));
+    effects.apply(&effect(EffectKind::SpeedUp, 1.2, 5));
+    effects.apply(&effect(EffectKind::Slow, 0.8, 5));
+    effects.apply(&effect(EffectKind::Slow, 0.5, 1));
+
+    let speed = effects.get(EffectKind::SpeedUp).unwrap();
+    assert_eq!(speed.magnitude, 1.5);
+    assert_eq!(speed.stacks, 1);
+    assert_eq!(speed.remaining, Duration::from_secs(5));
+    assert_eq!(effects.get(EffectKind::Slow).unwrap().magnitude, 0.5);
+    assert_eq!(effects.speed_multiplier(), 1.5 * 0.5);
+
+    effects.apply(&effect(EffectKind::Stun, 0.0, 1));
+    assert_eq!(effects.speed_multiplier(), 0.0);
+    assert_eq!(effects.stunned(), Some(Duration::from_secs(1)));
+}
+
+#[test]
+fn damage_over_time_stacks_and_pulses() {
+    let mut effects = StatusEffects::default();
+    let poison = effect(EffectKind::DamageOverTime, 2.0, 3);
+    for _ in 0..10 {
+        effects.apply(&poison);
+    }
+    assert_eq!(effects.get(EffectKind::DamageOverTime).unwrap().stacks, 5);
+
+    // Nothing until a whole pulse has gone by
+    assert_eq!(effects.tick(Duration::from_millis(500)), Ticked::default());
+    let ticked = effects.tick(Duration::from_millis(600));
+    assert_eq!(ticked.damage, 10.0);
+    assert!(!ticked.expired);
+
+    // The last pulse goes off as it runs out, and a long frame can't squeeze out any more
+    let ticked = effects.tick(Duration::from_secs(10));
+    assert_eq!(ticked.damage, 20.0);
+    assert!(ticked.expired);
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn buff_gives_effects() {
+    let buff = Skills::default();
+    let buff = buff.get_kind(SkillKind::Buff);
+    assert!(!buff.effects.is_empty());
+
+    let mut effects = StatusEffects::default();
+    for effect in &buff.effects {
+        effects.apply(effect);
+    }
+    assert!(effects.damage_multiplier() > 1.0);
+}